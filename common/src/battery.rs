@@ -0,0 +1,225 @@
+//! Estimates a battery's charge from its voltage.
+//!
+//! The battery is assumed to be a single LiPo cell wired to an ADC pin through a divider of two equal resistors, which
+//! keeps a full cell's 4.2V under the ADC's 3.3V reference. Charge is estimated from a typical LiPo discharge curve in
+//! [`DISCHARGE_CURVE`], which is rough but good enough for a battery indicator.
+
+/// Voltage the ADC reads at its max value, in mV
+const ADC_REFERENCE_MV: u32 = 3300;
+/// Max value of the 12-bit ADC
+const ADC_MAX: u32 = 4095;
+/// The battery's voltage is divided by this before reaching the ADC pin
+const DIVIDER_RATIO: u32 = 2;
+/// Battery voltage in mV and the charge left at it, lowest first, interpolated linearly between points. A LiPo's
+/// voltage stays flat through most of its charge, so a straight line from empty to full would read far too low.
+const DISCHARGE_CURVE: &[(u16, u8)] = &[
+    (3300, 0),
+    (3610, 5),
+    (3690, 10),
+    (3730, 20),
+    (3770, 30),
+    (3800, 40),
+    (3840, 50),
+    (3870, 60),
+    (3950, 70),
+    (4020, 80),
+    (4110, 90),
+    (4200, 100),
+];
+/// The voltage has to come back this far above a threshold before the level goes back up, so it doesn't flap while the
+/// voltage hovers around it
+const HYSTERESIS_MV: u16 = 100;
+
+/// How much is left in the battery, as far as what the device should still transmit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum BatteryLevel {
+    #[default]
+    Normal,
+    /// Below the low threshold: packets from other devices are only relayed if they're calls for help, and the time
+    /// isn't broadcast
+    Low,
+    /// Below the critical threshold: nothing but calls for help is sent, and the backlight stays dimmed
+    Critical,
+}
+
+impl BatteryLevel {
+    /// Level at `millivolts`, given the level before it. Drops as soon as a threshold is crossed, but only comes back
+    /// up once the voltage is [`HYSTERESIS_MV`] above it.
+    pub fn next(self, millivolts: u16, low_mv: u16, critical_mv: u16) -> Self {
+        let falling = Self::at(millivolts, low_mv, critical_mv);
+        let rising = Self::at(
+            millivolts.saturating_sub(HYSTERESIS_MV),
+            low_mv,
+            critical_mv,
+        );
+        if falling > self {
+            falling
+        } else if rising < self {
+            rising
+        } else {
+            self
+        }
+    }
+
+    const fn at(millivolts: u16, low_mv: u16, critical_mv: u16) -> Self {
+        if millivolts < critical_mv {
+            Self::Critical
+        } else if millivolts < low_mv {
+            Self::Low
+        } else {
+            Self::Normal
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatteryReading {
+    /// Estimated charge, 0 to 100
+    pub percent: u8,
+    /// Battery voltage, in mV
+    pub millivolts: u16,
+}
+
+impl BatteryReading {
+    /// Converts a raw reading of the ADC pin the battery is wired to.
+    pub fn from_adc(counts: u16) -> Self {
+        Self {
+            percent: percent_from_adc(counts),
+            millivolts: millivolts_from_adc(counts),
+        }
+    }
+}
+
+/// Battery voltage, in mV, for a raw ADC reading.
+#[allow(clippy::cast_possible_truncation)]
+pub fn millivolts_from_adc(counts: u16) -> u16 {
+    // At most 4095 * 3300 * 2 / 4095 = 6600, so always fits
+    (u32::from(counts.min(ADC_MAX as u16)) * ADC_REFERENCE_MV * DIVIDER_RATIO / ADC_MAX) as u16
+}
+
+/// Estimated charge, 0 to 100, for a battery voltage in mV, following [`DISCHARGE_CURVE`].
+#[allow(clippy::cast_possible_truncation)]
+pub fn percent_from_millivolts(millivolts: u16) -> u8 {
+    let Some(upper) = DISCHARGE_CURVE
+        .iter()
+        .position(|&(point_mv, _)| millivolts < point_mv)
+    else {
+        return 100;
+    };
+    let Some(&(low_mv, low_percent)) = upper.checked_sub(1).map(|lower| &DISCHARGE_CURVE[lower])
+    else {
+        return 0;
+    };
+    let (high_mv, high_percent) = DISCHARGE_CURVE[upper];
+
+    // Between the two points, so at most `high_percent` and always fits
+    (u32::from(low_percent)
+        + u32::from(millivolts - low_mv) * u32::from(high_percent - low_percent)
+            / u32::from(high_mv - low_mv)) as u8
+}
+
+/// Estimated charge, 0 to 100, for a raw ADC reading.
+pub fn percent_from_adc(counts: u16) -> u8 {
+    percent_from_millivolts(millivolts_from_adc(counts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOW_MV: u16 = 3700;
+    const CRITICAL_MV: u16 = 3450;
+
+    #[test]
+    fn adc_range_covers_twice_the_reference() {
+        assert_eq!(millivolts_from_adc(0), 0);
+        assert_eq!(millivolts_from_adc(4095), 6600);
+        // Past the 12-bit max, which the ADC never reads
+        assert_eq!(millivolts_from_adc(u16::MAX), 6600);
+    }
+
+    #[test]
+    fn curve_points_are_exact() {
+        for &(millivolts, percent) in DISCHARGE_CURVE {
+            assert_eq!(percent_from_millivolts(millivolts), percent);
+        }
+        assert_eq!(percent_from_millivolts(3840), 50);
+    }
+
+    #[test]
+    fn charge_is_interpolated_between_points() {
+        // Halfway from 3800 mV at 40% to 3840 mV at 50%
+        assert_eq!(percent_from_millivolts(3820), 45);
+        // Halfway from 3300 mV at 0% to 3610 mV at 5%, rounded down
+        assert_eq!(percent_from_millivolts(3455), 2);
+    }
+
+    #[test]
+    fn charge_is_clamped_past_the_curve() {
+        assert_eq!(percent_from_millivolts(3299), 0);
+        assert_eq!(percent_from_millivolts(0), 0);
+        assert_eq!(percent_from_millivolts(4200), 100);
+        assert_eq!(percent_from_millivolts(6600), 100);
+    }
+
+    #[test]
+    fn reading_converts_adc_counts() {
+        assert_eq!(
+            BatteryReading::from_adc(2383),
+            BatteryReading {
+                percent: 50,
+                millivolts: 3840,
+            }
+        );
+        assert_eq!(
+            BatteryReading::from_adc(0),
+            BatteryReading {
+                percent: 0,
+                millivolts: 0,
+            }
+        );
+        assert_eq!(BatteryReading::from_adc(4095).percent, 100);
+    }
+
+    #[test]
+    fn level_drops_as_soon_as_a_threshold_is_crossed() {
+        let next = |level: BatteryLevel, millivolts| level.next(millivolts, LOW_MV, CRITICAL_MV);
+
+        assert_eq!(next(BatteryLevel::Normal, LOW_MV), BatteryLevel::Normal);
+        assert_eq!(next(BatteryLevel::Normal, LOW_MV - 1), BatteryLevel::Low);
+        assert_eq!(
+            next(BatteryLevel::Low, CRITICAL_MV - 1),
+            BatteryLevel::Critical
+        );
+        // Straight past low
+        assert_eq!(next(BatteryLevel::Normal, 3400), BatteryLevel::Critical);
+    }
+
+    #[test]
+    fn level_only_rises_once_past_the_hysteresis() {
+        let next = |level: BatteryLevel, millivolts| level.next(millivolts, LOW_MV, CRITICAL_MV);
+
+        assert_eq!(next(BatteryLevel::Low, LOW_MV), BatteryLevel::Low);
+        assert_eq!(
+            next(BatteryLevel::Low, LOW_MV + HYSTERESIS_MV - 1),
+            BatteryLevel::Low
+        );
+        assert_eq!(
+            next(BatteryLevel::Low, LOW_MV + HYSTERESIS_MV),
+            BatteryLevel::Normal
+        );
+
+        assert_eq!(
+            next(BatteryLevel::Critical, CRITICAL_MV + 50),
+            BatteryLevel::Critical
+        );
+        assert_eq!(
+            next(BatteryLevel::Critical, CRITICAL_MV + HYSTERESIS_MV),
+            BatteryLevel::Low
+        );
+        assert_eq!(
+            next(BatteryLevel::Critical, LOW_MV + HYSTERESIS_MV),
+            BatteryLevel::Normal
+        );
+    }
+}
@@ -1,4 +1,514 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod battery;
+
+use core::fmt;
+use core::ops::RangeInclusive;
+
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use strum::{AsRefStr, Display, EnumCount, EnumIter, EnumString, IntoEnumIterator};
+
+/// Size of the display panel as the ST7735 addresses it, in portrait
 pub const DISPLAY_WIDTH: u32 = 128;
 pub const DISPLAY_HEIGHT: u32 = 160;
+/// Width of the screen as it's drawn on, once it's rotated 90° into landscape. Everything drawn on the screen should go
+/// by these rather than the panel's size.
+pub const ROTATED_WIDTH: u32 = DISPLAY_HEIGHT;
+/// Height of the screen as it's drawn on, once it's rotated 90° into landscape
+pub const ROTATED_HEIGHT: u32 = DISPLAY_WIDTH;
+
+/// The device's two buttons
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Good,
+    Help,
+}
+
+impl Button {
+    /// What a press stands for, shown on the sender once it's delivered and on every device that receives it.
+    pub const fn message(self) -> &'static str {
+        match self {
+            Self::Good => "All good!",
+            Self::Help => "HELP NEEDED",
+        }
+    }
+}
+
+/// What a message is, sent as the first byte of its payload so receivers know how to read the rest.
+///
+/// Button presses are sent as just this byte and the sender's station, so they cost as little airtime as possible and
+/// are only turned into text by the receiver.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// The rest of the payload is UTF-8 text
+    Text = 0,
+    /// Followed by the sender's [`Station`] as a `u8`, if it has picked one
+    Good = 1,
+    /// Followed by the sender's [`Station`] as a `u8`, if it has picked one
+    Help = 2,
+}
+
+impl MessageKind {
+    /// The button a press of which is sent as this kind, if any
+    pub const fn button(self) -> Option<Button> {
+        match self {
+            Self::Text => None,
+            Self::Good => Some(Button::Good),
+            Self::Help => Some(Button::Help),
+        }
+    }
+}
+
+impl From<Button> for MessageKind {
+    fn from(button: Button) -> Self {
+        match button {
+            Button::Good => Self::Good,
+            Button::Help => Self::Help,
+        }
+    }
+}
+
+/// Shown for a Good press received from another device, naming the sender's station if it has picked one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllGood {
+    pub station: Option<Station>,
+}
+
+impl fmt::Display for AllGood {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.station {
+            Some(station) => write!(f, "All good at {station}"),
+            None => f.write_str(Button::Good.message()),
+        }
+    }
+}
+
+/// Stands in for bytes that aren't valid UTF-8, since the display's fonts only have ASCII
+pub const REPLACEMENT_CHAR: char = '?';
+
+/// Text that may not be valid UTF-8, shown with each invalid sequence replaced by a single [`REPLACEMENT_CHAR`], so it's
+/// never longer than the bytes it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LossyText<'a>(pub &'a [u8]);
+
+impl fmt::Display for LossyText<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                fmt::Write::write_char(f, REPLACEMENT_CHAR)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Shown before what was sent once another device has, or hasn't, acknowledged it.
+pub const fn delivery_prefix(delivered: bool) -> &'static str {
+    if delivered {
+        "Delivered: "
+    } else {
+        "Not delivered: "
+    }
+}
+
+/// Shown before a message received from another device: its ID and the signal strength it came in at, out of 4 bars,
+/// then `DM` if it was sent to only this device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SenderPrefix {
+    pub sender_id: u16,
+    pub signal_bars: u8,
+    /// Whether the message was sent to only this device, rather than every device
+    pub direct: bool,
+}
+
+impl fmt::Display for SenderPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X} ({}/4)", self.sender_id, self.signal_bars)?;
+        if self.direct {
+            f.write_str(" DM")?;
+        }
+        f.write_str(": ")
+    }
+}
+
+/// Emergency shown for a call for help, naming the sender's station, or its ID if it hasn't picked one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HelpNeeded {
+    pub sender_id: u16,
+    pub station: Option<Station>,
+}
+
+impl fmt::Display for HelpNeeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.station {
+            Some(station) => write!(f, "HELP\n{station}"),
+            None => write!(f, "HELP\n{:04X}", self.sender_id),
+        }
+    }
+}
+
+/// Must be written to the factory reset characteristic, little endian, to confirm a reset
+pub const FACTORY_RESET_CONFIRMATION: u32 = 0x5445_5352; // "RSET"
+
+/// Something a central asks the device to do by writing an opcode, followed by any arguments, to the command
+/// characteristic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// `0`: reboots, applying any settings changed since boot
+    Reboot,
+    /// `1`: erases the message history, then reboots so the history view is emptied too
+    ClearHistory,
+    /// `2` then [`FACTORY_RESET_CONFIRMATION`]: the same as writing to the factory reset characteristic
+    FactoryReset,
+    /// `3`: takes down the key mismatch hint on the status bar
+    ClearKeyMismatch,
+    /// `4`: sends a few test packets
+    SendTestPackets,
+}
+
+/// Why a write to the command characteristic was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    /// Nothing was written, or a command's arguments were missing or had extra bytes after them
+    WrongLength,
+    /// The opcode isn't one of [`Command`]'s
+    UnknownOpcode,
+    /// A factory reset was asked for without [`FACTORY_RESET_CONFIRMATION`]
+    Unconfirmed,
+}
+
+impl Command {
+    /// Longest command written to the command characteristic, an opcode and a `u32` argument
+    pub const MAX_SIZE: usize = 1 + size_of::<u32>();
+
+    const REBOOT: u8 = 0;
+    const CLEAR_HISTORY: u8 = 1;
+    const FACTORY_RESET: u8 = 2;
+    const CLEAR_KEY_MISMATCH: u8 = 3;
+    const SEND_TEST_PACKETS: u8 = 4;
+
+    /// Decodes a write to the command characteristic, rejecting unknown opcodes and missing or extra arguments.
+    pub fn decode(data: &[u8]) -> Result<Self, CommandError> {
+        match data {
+            [Self::REBOOT] => Ok(Self::Reboot),
+            [Self::CLEAR_HISTORY] => Ok(Self::ClearHistory),
+            [Self::CLEAR_KEY_MISMATCH] => Ok(Self::ClearKeyMismatch),
+            [Self::SEND_TEST_PACKETS] => Ok(Self::SendTestPackets),
+            [Self::FACTORY_RESET, confirmation @ ..] => {
+                let confirmation: [u8; 4] = confirmation
+                    .try_into()
+                    .map_err(|_| CommandError::WrongLength)?;
+                match u32::from_le_bytes(confirmation) {
+                    FACTORY_RESET_CONFIRMATION => Ok(Self::FactoryReset),
+                    _ => Err(CommandError::Unconfirmed),
+                }
+            }
+            [
+                Self::REBOOT
+                | Self::CLEAR_HISTORY
+                | Self::CLEAR_KEY_MISMATCH
+                | Self::SEND_TEST_PACKETS,
+                ..,
+            ]
+            | [] => Err(CommandError::WrongLength),
+            _ => Err(CommandError::UnknownOpcode),
+        }
+    }
+}
+
+/// Regulatory regions the radio can operate in, which limit the frequencies and transmit power it may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, TryFromPrimitive, IntoPrimitive, AsRefStr)]
+#[repr(u8)]
+pub enum Region {
+    #[default]
+    US915 = 0,
+    EU868 = 1,
+    AU915 = 2,
+    AS923 = 3,
+    IN865 = 4,
+    KR920 = 5,
+}
+
+impl Region {
+    /// Band LoRa devices may transmit in, in Hz
+    pub const fn frequency_range_hz(self) -> RangeInclusive<u32> {
+        match self {
+            Self::US915 => 902_000_000..=928_000_000,
+            Self::EU868 => 863_000_000..=870_000_000,
+            Self::AU915 | Self::AS923 => 915_000_000..=928_000_000,
+            Self::IN865 => 865_000_000..=867_000_000,
+            Self::KR920 => 920_900_000..=923_300_000,
+        }
+    }
+
+    /// Frequency used when none has been configured, in Hz
+    pub const fn default_frequency_hz(self) -> u32 {
+        match self {
+            Self::US915 => 915_000_000,
+            Self::EU868 => 868_100_000,
+            Self::AU915 => 916_800_000,
+            Self::AS923 => 923_200_000,
+            Self::IN865 => 865_062_500,
+            Self::KR920 => 922_100_000,
+        }
+    }
+
+    /// Highest transmit power allowed, in dBm
+    pub const fn max_tx_power_dbm(self) -> i8 {
+        match self {
+            Self::US915 | Self::AU915 | Self::IN865 => 30,
+            Self::AS923 => 16,
+            Self::EU868 | Self::KR920 => 14,
+        }
+    }
+
+    /// Max fraction of time that may be spent transmitting, in thousandths
+    pub const fn max_duty_cycle_permille(self) -> u16 {
+        match self {
+            Self::EU868 | Self::AS923 => 10,
+            Self::US915 | Self::AU915 | Self::IN865 | Self::KR920 => 1000,
+        }
+    }
+}
+
+/// Caltrain stations, north to south, that a device can be placed at.
+///
+/// Displays as, and parses from, its name as it's shown to riders, e.g. "San Francisco". Parsing an unknown name fails
+/// with [`strum::ParseError::VariantNotFound`].
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    AsRefStr,
+    Display,
+    EnumString,
+    EnumIter,
+    EnumCount,
+)]
+#[repr(u8)]
+pub enum Station {
+    #[strum(serialize = "San Francisco")]
+    SanFrancisco = 0,
+    #[strum(serialize = "22nd Street")]
+    TwentySecondStreet = 1,
+    Bayshore = 2,
+    #[strum(serialize = "South San Francisco")]
+    SouthSanFrancisco = 3,
+    #[strum(serialize = "San Bruno")]
+    SanBruno = 4,
+    Millbrae = 5,
+    Burlingame = 6,
+    #[strum(serialize = "San Mateo")]
+    SanMateo = 7,
+    #[strum(serialize = "Hayward Park")]
+    HaywardPark = 8,
+    Hillsdale = 9,
+    Belmont = 10,
+    #[strum(serialize = "San Carlos")]
+    SanCarlos = 11,
+    #[strum(serialize = "Redwood City")]
+    RedwoodCity = 12,
+    #[strum(serialize = "Menlo Park")]
+    MenloPark = 13,
+    #[strum(serialize = "Palo Alto")]
+    PaloAlto = 14,
+    Stanford = 15,
+    #[strum(serialize = "California Avenue")]
+    CaliforniaAvenue = 16,
+    #[strum(serialize = "San Antonio")]
+    SanAntonio = 17,
+    #[strum(serialize = "Mountain View")]
+    MountainView = 18,
+    Sunnyvale = 19,
+    Lawrence = 20,
+    #[strum(serialize = "Santa Clara")]
+    SantaClara = 21,
+    #[strum(serialize = "College Park")]
+    CollegePark = 22,
+    #[strum(serialize = "San Jose Diridon")]
+    SanJoseDiridon = 23,
+    Tamien = 24,
+    Capitol = 25,
+    #[strum(serialize = "Blossom Hill")]
+    BlossomHill = 26,
+    #[strum(serialize = "Morgan Hill")]
+    MorganHill = 27,
+    #[strum(serialize = "San Martin")]
+    SanMartin = 28,
+    Gilroy = 29,
+}
+
+impl Station {
+    /// Every station, north to south. There are [`Station::COUNT`] of them.
+    pub fn all() -> impl Iterator<Item = Self> {
+        Self::iter()
+    }
+
+    /// Number of stops from `self` to `other` along the line, the same in either direction. Zero if they're the same
+    /// station.
+    pub const fn stops_between(self, other: Self) -> u8 {
+        (self as u8).abs_diff(other as u8)
+    }
+
+    /// Whether `self` is further down the line than `other`, for showing which way another station is, e.g. "2 stops
+    /// south".
+    pub const fn is_south_of(self, other: Self) -> bool {
+        self as u8 > other as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buttons_round_trip_through_their_wire_byte() {
+        for (button, byte) in [(Button::Good, 1), (Button::Help, 2)] {
+            let encoded: u8 = MessageKind::from(button).into();
+            assert_eq!(encoded, byte);
+
+            let decoded = MessageKind::try_from(encoded).unwrap();
+            assert_eq!(decoded.button(), Some(button));
+        }
+    }
+
+    #[test]
+    fn text_isnt_a_button() {
+        let decoded = MessageKind::try_from(0).unwrap();
+        assert_eq!(decoded, MessageKind::Text);
+        assert_eq!(decoded.button(), None);
+    }
+
+    #[test]
+    fn unknown_kind_is_rejected() {
+        assert!(MessageKind::try_from(3).is_err());
+    }
+
+    #[test]
+    fn each_opcode_decodes_to_its_command() {
+        assert_eq!(Command::decode(&[0]), Ok(Command::Reboot));
+        assert_eq!(Command::decode(&[1]), Ok(Command::ClearHistory));
+        assert_eq!(Command::decode(&[3]), Ok(Command::ClearKeyMismatch));
+        assert_eq!(Command::decode(&[4]), Ok(Command::SendTestPackets));
+        assert_eq!(
+            Command::decode(&[2, 0x52, 0x53, 0x45, 0x54]),
+            Ok(Command::FactoryReset)
+        );
+    }
+
+    #[test]
+    fn invalid_opcode_is_rejected() {
+        assert_eq!(Command::decode(&[5]), Err(CommandError::UnknownOpcode));
+        assert_eq!(
+            Command::decode(&[0xFF, 0]),
+            Err(CommandError::UnknownOpcode)
+        );
+    }
+
+    #[test]
+    fn wrong_length_is_rejected() {
+        assert_eq!(Command::decode(&[]), Err(CommandError::WrongLength));
+        assert_eq!(Command::decode(&[0, 0]), Err(CommandError::WrongLength));
+        assert_eq!(
+            Command::decode(&[2, 0x52, 0x53, 0x45]),
+            Err(CommandError::WrongLength)
+        );
+    }
+
+    #[test]
+    fn factory_reset_needs_its_confirmation() {
+        assert_eq!(
+            Command::decode(&[2, 0, 0, 0, 0]),
+            Err(CommandError::Unconfirmed)
+        );
+    }
+
+    #[test]
+    fn stations_run_north_to_south_one_stop_apart() {
+        // Discriminants count up from the north end one stop at a time, which `stops_between` and `is_south_of` rely on
+        let stations: std::vec::Vec<_> = Station::all().collect();
+        assert_eq!(stations.len(), Station::COUNT);
+        assert_eq!(stations.first(), Some(&Station::SanFrancisco));
+        assert_eq!(stations.last(), Some(&Station::Gilroy));
+
+        for (index, station) in stations.iter().enumerate() {
+            assert_eq!(usize::from(u8::from(*station)), index);
+        }
+        for pair in stations.windows(2) {
+            assert_eq!(pair[0].stops_between(pair[1]), 1);
+            assert!(pair[1].is_south_of(pair[0]));
+            assert!(!pair[0].is_south_of(pair[1]));
+        }
+    }
+
+    #[test]
+    fn stops_between_is_the_same_both_ways() {
+        for from in Station::all() {
+            assert_eq!(from.stops_between(from), 0);
+            for to in Station::all() {
+                assert_eq!(from.stops_between(to), to.stops_between(from));
+            }
+        }
+        assert_eq!(Station::SanFrancisco.stops_between(Station::Gilroy), 29);
+        assert_eq!(Station::SanMateo.stops_between(Station::Millbrae), 2);
+    }
+
+    #[test]
+    fn stations_round_trip_through_their_names() {
+        for station in Station::all() {
+            assert_eq!(station.to_string().parse::<Station>(), Ok(station));
+            assert_eq!(station.to_string(), station.as_ref());
+        }
+        assert_eq!(Station::SanFrancisco.to_string(), "San Francisco");
+    }
+
+    #[test]
+    fn unknown_station_name_is_rejected() {
+        assert_eq!(
+            "Nowhere".parse::<Station>(),
+            Err(strum::ParseError::VariantNotFound)
+        );
+    }
+
+    #[test]
+    fn stations_round_trip_through_their_byte() {
+        for station in Station::all() {
+            assert_eq!(Station::try_from(u8::from(station)), Ok(station));
+        }
+        // The first byte past the last station, as written over BLE
+        assert!(Station::try_from(Station::COUNT as u8).is_err());
+    }
+
+    const LOSSY_CASES: [(&[u8], &str); 6] = [
+        (b"All good", "All good"),
+        ("Café".as_bytes(), "Café"),
+        // Invalid bytes mid-string
+        (b"ab\xffcd", "ab?cd"),
+        // A multibyte sequence cut off at the end
+        (b"caf\xc3", "caf?"),
+        (b"\xe2\x82", "?"),
+        // Nothing valid at all
+        (b"\xff\xfe\x80", "???"),
+    ];
+
+    #[test]
+    fn invalid_utf8_is_replaced() {
+        for (bytes, shown) in LOSSY_CASES {
+            assert_eq!(LossyText(bytes).to_string(), shown);
+        }
+    }
+
+    #[test]
+    fn lossy_text_is_never_longer_than_its_bytes() {
+        for (bytes, _) in LOSSY_CASES {
+            assert!(LossyText(bytes).to_string().len() <= bytes.len());
+        }
+        assert!(LossyText(&[]).to_string().is_empty());
+    }
+}
@@ -1,42 +1,703 @@
-#![no_std]
-use core::fmt::Debug;
+#![cfg_attr(not(test), no_std)]
+use core::fmt::{self, Write};
 
+use common::Station;
 use embedded_graphics::{
-    mono_font::{MonoTextStyleBuilder, ascii::FONT_9X15},
+    mono_font::{
+        MonoFont, MonoTextStyle, MonoTextStyleBuilder,
+        ascii::{FONT_6X10, FONT_9X15, FONT_10X20},
+    },
     pixelcolor::Rgb565,
     prelude::*,
-    primitives::Rectangle,
+    primitives::{PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
 };
 use embedded_text::{
-    TextBox, alignment::HorizontalAlignment, style::HeightMode, style::TextBoxStyleBuilder,
+    TextBox,
+    alignment::{HorizontalAlignment, VerticalAlignment},
+    style::HeightMode,
+    style::TextBoxStyleBuilder,
 };
 
-pub fn fill<D: DrawTargetExt<Color = Rgb565>>(target: &mut D)
-where
-    D::Error: Debug,
-{
-    target.clear(Rgb565::new(0, 0, 0)).unwrap();
+/// Height of the status bar along the top of the screen
+pub const STATUS_BAR_HEIGHT: u32 = 12;
+/// Top of the message, below the status bar
+const MESSAGE_TOP: i32 = STATUS_BAR_HEIGHT as i32 + 2;
+/// Height of the part of the screen messages are shown in
+pub const MESSAGE_VIEWPORT_HEIGHT: u32 = common::ROTATED_HEIGHT - MESSAGE_TOP as u32;
+/// Number of bars signal strength is shown with
+pub const SIGNAL_BARS: u8 = 4;
+
+/// What the status bar shows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Status {
+    /// Strength of the last received packet's signal, 0 to 4 bars, or `None` if nothing has been received yet
+    pub signal_bars: Option<u8>,
+    /// Whether a BLE central is connected
+    pub ble_connected: bool,
+    /// Station the device is placed at, if one has been picked
+    pub station: Option<Station>,
+    /// Whether this device is repeating a call for help
+    pub emergency_broadcast: bool,
+    /// Whether the battery is running low
+    pub low_battery: bool,
+    /// Whether enough packets in a row failed to decrypt that this device's key probably doesn't match the others'
+    pub key_mismatch: bool,
+    /// How long ago the message on screen was sent, shown in place of the station while it's up
+    pub message_age: Option<MessageAge>,
+}
+
+impl Status {
+    pub const fn new() -> Self {
+        Self {
+            signal_bars: None,
+            ble_connected: false,
+            station: None,
+            emergency_broadcast: false,
+            low_battery: false,
+            key_mismatch: false,
+            message_age: None,
+        }
+    }
+}
+
+/// Size of the font a message is drawn in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FontSize {
+    /// 6x10, fits the most text
+    Small,
+    /// 9x15
+    #[default]
+    Medium,
+    /// 10x20, readable from a distance
+    Large,
+}
+
+impl FontSize {
+    const fn font(self) -> &'static MonoFont<'static> {
+        match self {
+            Self::Small => &FONT_6X10,
+            Self::Medium => &FONT_9X15,
+            Self::Large => &FONT_10X20,
+        }
+    }
+}
+
+/// How the lines of a message line up with the sides of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MessageAlignment {
+    /// Ragged on the right, with single spaces between words
+    Left,
+    /// Lines that wrap are stretched to both sides, which can leave wide gaps in short lines
+    #[default]
+    Justified,
+}
+
+impl MessageAlignment {
+    const fn horizontal(self) -> HorizontalAlignment {
+        match self {
+            Self::Left => HorizontalAlignment::Left,
+            Self::Justified => HorizontalAlignment::Justified,
+        }
+    }
+}
+
+/// Where a message's lines can break
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Wrap {
+    /// At any space. Words too long for a whole line are split wherever the line ends.
+    #[default]
+    Words,
+    /// At any space except the one after a word of up to [`SHORT_WORD_LEN`] characters, so short words stay on the
+    /// same line as the next one and names like "San Bruno" or "at Millbrae" aren't split up. Messages longer than
+    /// [`MAX_REWRAPPED_LEN`] bytes wrap like [`Wrap::Words`].
+    KeepShortWords,
+}
+
+/// Longest word [`Wrap::KeepShortWords`] keeps on the same line as the next one
+pub const SHORT_WORD_LEN: usize = 3;
+/// Longest message [`Wrap::KeepShortWords`] applies to, in bytes once its spaces are made non-breaking
+pub const MAX_REWRAPPED_LEN: usize = 256;
+/// Space `embedded_text` never breaks a line at
+const NON_BREAKING_SPACE: char = '\u{a0}';
+
+/// How a message's text looks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageStyle {
+    pub color: Rgb565,
+    pub font_size: FontSize,
+    pub alignment: MessageAlignment,
+    pub wrap: Wrap,
+}
+
+impl MessageStyle {
+    /// Red in the medium font, what [`draw_message`] uses
+    pub const DEFAULT: Self = Self {
+        color: Rgb565::RED,
+        font_size: FontSize::Medium,
+        alignment: MessageAlignment::Justified,
+        wrap: Wrap::Words,
+    };
+    /// White in the medium font, for everyday messages
+    pub const NORMAL: Self = Self {
+        color: Rgb565::WHITE,
+        font_size: FontSize::Medium,
+        alignment: MessageAlignment::Justified,
+        wrap: Wrap::Words,
+    };
+    /// Red in the large font, for messages that mustn't be missed
+    pub const URGENT: Self = Self {
+        color: Rgb565::RED,
+        font_size: FontSize::Large,
+        alignment: MessageAlignment::Justified,
+        wrap: Wrap::Words,
+    };
+    /// Gray in the small font, for status text from the device itself that shouldn't draw the eye
+    pub const SYSTEM: Self = Self {
+        color: Rgb565::CSS_LIGHT_GRAY,
+        font_size: FontSize::Small,
+        alignment: MessageAlignment::Justified,
+        wrap: Wrap::Words,
+    };
+
+    /// Height of a line of text, a good amount to scroll by
+    pub const fn line_height(&self) -> u32 {
+        self.font_size.font().character_size.height
+    }
+}
+
+impl Default for MessageStyle {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+pub fn fill<D: DrawTargetExt<Color = Rgb565>>(target: &mut D) -> Result<(), D::Error> {
+    target.clear(Rgb565::new(0, 0, 0))
+}
+
+/// Draws a message below the status bar in [`MessageStyle::DEFAULT`].
+pub fn draw_message<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    message: &str,
+) -> Result<(), D::Error> {
+    draw_message_styled(target, message, &MessageStyle::DEFAULT)
+}
+
+/// Draws a message below the status bar in `style`.
+pub fn draw_message_styled<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    message: &str,
+    style: &MessageStyle,
+) -> Result<(), D::Error> {
+    with_message_text_box(message, MESSAGE_TOP, style, |text_box| {
+        text_box.draw(target)
+    })?;
+    Ok(())
+}
+
+/// Furthest a message can be scrolled, which leaves its last line at the bottom of the screen. 0 if it already fits.
+pub fn max_scroll_offset(message: &str, style: &MessageStyle) -> u32 {
+    let height = with_message_text_box(message, MESSAGE_TOP, style, |text_box| {
+        text_box.bounding_box().size.height
+    });
+    height.saturating_sub(MESSAGE_VIEWPORT_HEIGHT)
+}
+
+/// Offset to draw a message at after scrolling `step` pixels from `offset`. Stops at [`max_scroll_offset`] so the
+/// last line is fully shown, then wraps back around to the top.
+pub fn next_scroll_offset(message: &str, style: &MessageStyle, offset: u32, step: u32) -> u32 {
+    let max = max_scroll_offset(message, style);
+    if offset >= max {
+        0
+    } else {
+        (offset + step).min(max)
+    }
+}
+
+/// Draws a message scrolled up by `offset` pixels, clipped to the area below the status bar, which is cleared first.
+/// Offsets past [`max_scroll_offset`] wrap around.
+pub fn draw_message_scrolled<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    message: &str,
+    style: &MessageStyle,
+    offset: u32,
+) -> Result<(), D::Error> {
+    let offset = offset % (max_scroll_offset(message, style) + 1);
+
+    let viewport = Rectangle::new(
+        Point::new(0, MESSAGE_TOP),
+        Size::new(common::ROTATED_WIDTH, MESSAGE_VIEWPORT_HEIGHT),
+    );
+    let mut target = target.clipped(&viewport);
+    target.clear(Rgb565::BLACK)?;
+
+    with_message_text_box(message, MESSAGE_TOP - offset as i32, style, |text_box| {
+        text_box.draw(&mut target)
+    })?;
+    Ok(())
+}
+
+/// Calls `f` with the box of the message starting at `top`, as tall as the text once wrapped the way `style` says.
+fn with_message_text_box<R>(
+    message: &str,
+    top: i32,
+    style: &MessageStyle,
+    f: impl FnOnce(TextBox<'_, MonoTextStyle<'static, Rgb565>>) -> R,
+) -> R {
+    let mut rewrapped = heapless::String::<MAX_REWRAPPED_LEN>::new();
+    let text = match style.wrap {
+        Wrap::KeepShortWords if keep_short_words(message, &mut rewrapped).is_ok() => {
+            rewrapped.as_str()
+        }
+        Wrap::Words | Wrap::KeepShortWords => message,
+    };
+    f(message_text_box(text, top, style))
+}
+
+/// Copies `message` into `out` with the space after each word of up to [`SHORT_WORD_LEN`] characters made
+/// non-breaking. Fails if it doesn't fit.
+fn keep_short_words<const N: usize>(
+    message: &str,
+    out: &mut heapless::String<N>,
+) -> Result<(), ()> {
+    let mut word_len = 0;
+    for c in message.chars() {
+        if c == ' ' && (1..=SHORT_WORD_LEN).contains(&word_len) {
+            out.push(NON_BREAKING_SPACE)?;
+        } else {
+            out.push(c)?;
+        }
+        word_len = if c.is_whitespace() { 0 } else { word_len + 1 };
+    }
+    Ok(())
 }
 
-pub fn draw_message<D: DrawTargetExt<Color = Rgb565>>(target: &mut D, message: &str)
-where
-    D::Error: Debug,
-{
+/// Box of the message starting at `top`, as tall as the wrapped text
+fn message_text_box<'a>(
+    message: &'a str,
+    top: i32,
+    style: &MessageStyle,
+) -> TextBox<'a, MonoTextStyle<'static, Rgb565>> {
     let name_text_style = MonoTextStyleBuilder::new()
-        .font(&FONT_9X15)
-        .text_color(Rgb565::new(255, 0, 0))
+        .font(style.font_size.font())
+        .text_color(style.color)
         .build();
 
-    // Use height as width of text box since the screen is rotated
-    let bounds = Rectangle::new(Point::new(2, 0), Size::new(common::DISPLAY_HEIGHT - 2, 0));
+    let bounds = Rectangle::new(Point::new(2, top), Size::new(common::ROTATED_WIDTH - 2, 0));
 
     let textbox_style = TextBoxStyleBuilder::new()
         .height_mode(HeightMode::FitToText)
-        .alignment(HorizontalAlignment::Justified)
+        .alignment(style.alignment.horizontal())
         .paragraph_spacing(6)
         .build();
 
-    let text_box = TextBox::with_textbox_style(message, bounds, name_text_style, textbox_style);
+    TextBox::with_textbox_style(message, bounds, name_text_style, textbox_style)
+}
+
+/// Fills the whole screen and draws `text` large and centered on it, for alerts that mustn't be missed. `inverted`
+/// swaps the colors, so flipping it back and forth makes the alert blink.
+pub fn draw_emergency<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    text: &str,
+    inverted: bool,
+) -> Result<(), D::Error> {
+    let (background, foreground) = if inverted {
+        (Rgb565::WHITE, Rgb565::RED)
+    } else {
+        (Rgb565::RED, Rgb565::WHITE)
+    };
+    target.clear(background)?;
+
+    let bounds = Rectangle::new(
+        Point::zero(),
+        Size::new(common::ROTATED_WIDTH, common::ROTATED_HEIGHT),
+    );
+
+    let textbox_style = TextBoxStyleBuilder::new()
+        .alignment(HorizontalAlignment::Center)
+        .vertical_alignment(VerticalAlignment::Middle)
+        .build();
+
+    TextBox::with_textbox_style(
+        text,
+        bounds,
+        MonoTextStyle::new(&FONT_10X20, foreground),
+        textbox_style,
+    )
+    .draw(target)?;
+    Ok(())
+}
+
+/// Fills the screen with the product name, and below it the device's ID, firmware version, and station if it has
+/// one, so it's clear what's running at a glance.
+pub fn draw_splash<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    id: &str,
+    version: &str,
+    station: Option<Station>,
+) -> Result<(), D::Error> {
+    target.clear(Rgb565::BLACK)?;
+
+    let center = common::ROTATED_WIDTH as i32 / 2;
+    let centered = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Top)
+        .build();
+
+    Text::with_text_style(
+        "LEWOC",
+        Point::new(center, 24),
+        MonoTextStyle::new(&FONT_10X20, Rgb565::RED),
+        centered,
+    )
+    .draw(target)?;
+
+    let mut details = heapless::String::<96>::new();
+    // ID and version are short, and station names are at most 19 bytes, so always fits
+    write!(details, "ID {id}\nv{version}").unwrap();
+    if let Some(station) = station {
+        write!(details, "\n{station}").unwrap();
+    }
+    Text::with_text_style(
+        &details,
+        Point::new(center, 56),
+        MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE),
+        centered,
+    )
+    .draw(target)?;
+
+    Ok(())
+}
+
+/// How long ago a message was sent, or received if the sender or this device has never synced its clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageAge {
+    Secs(u64),
+    /// Received before the device restarted
+    BeforeRestart,
+}
+
+impl MessageAge {
+    /// How many seconds old the message will be when its age is next shown differently, or `None` if it never is.
+    pub const fn next_change_secs(self) -> Option<u64> {
+        match self {
+            Self::Secs(0..60) => Some(60),
+            Self::Secs(secs @ 60..3600) => Some((secs / 60 + 1) * 60),
+            Self::Secs(secs) => match (secs / 3600).checked_add(1) {
+                Some(hours) => hours.checked_mul(3600),
+                None => None,
+            },
+            Self::BeforeRestart => None,
+        }
+    }
+}
+
+impl fmt::Display for MessageAge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BeforeRestart => f.write_str("before restart"),
+            Self::Secs(0..60) => f.write_str("now"),
+            Self::Secs(secs @ 60..3600) => write!(f, "{}m ago", secs / 60),
+            Self::Secs(secs) => write!(f, "{}h ago", secs / 3600),
+        }
+    }
+}
+
+/// A message in the history view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryEntry<'a> {
+    pub age: MessageAge,
+    pub message: &'a str,
+}
+
+/// Fills the screen with a list of messages, newest first, starting from `entries[first]`. Each is shown below how long
+/// ago it was sent, and as many as fit are drawn.
+pub fn draw_history<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    entries: &[HistoryEntry<'_>],
+    first: usize,
+) -> Result<(), D::Error> {
+    target.clear(Rgb565::BLACK)?;
+
+    let label_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_LIGHT_GRAY);
+    let text_style = TextStyleBuilder::new().baseline(Baseline::Top).build();
+
+    let mut header = heapless::String::<32>::new();
+    if entries.is_empty() {
+        header.push_str("No messages yet").unwrap();
+    } else {
+        // Always fits
+        write!(header, "History {}/{}", first + 1, entries.len()).unwrap();
+    }
+    Text::with_text_style(&header, Point::new(2, 1), label_style, text_style).draw(target)?;
+
+    let message_style = MessageStyle {
+        color: Rgb565::WHITE,
+        font_size: FontSize::Small,
+        ..MessageStyle::DEFAULT
+    };
+    let bottom = common::ROTATED_HEIGHT as i32;
+    let mut top = MESSAGE_TOP;
+    for entry in entries.iter().skip(first) {
+        if top >= bottom {
+            break;
+        }
+
+        let mut age = heapless::String::<24>::new();
+        // Always fits, even for u64::MAX hours
+        write!(age, "{}", entry.age).unwrap();
+        Text::with_text_style(&age, Point::new(2, top), label_style, text_style).draw(target)?;
+        top += FONT_6X10.character_size.height as i32;
+
+        let height = with_message_text_box(entry.message, top, &message_style, |text_box| {
+            text_box
+                .draw(target)
+                .map(|_| text_box.bounding_box().size.height)
+        })?;
+        top += height as i32 + 4;
+    }
+
+    Ok(())
+}
+
+/// Raw link parameters shown on the diagnostics screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Diagnostics {
+    pub frequency_hz: u32,
+    pub spreading_factor: u8,
+    pub bandwidth_hz: u32,
+    /// `n` in a coding rate of `4/(4 + n)`
+    pub coding_rate: u8,
+    pub tx_power_dbm: i32,
+    /// RSSI of the last received packet in dBm, or `None` if nothing has been received yet
+    pub rssi: Option<i16>,
+    /// SNR of the last received packet in dB, or `None` if nothing has been received yet
+    pub snr: Option<i16>,
+    /// What last went wrong with the radio, or `None` if nothing has
+    pub last_error: Option<&'static str>,
+}
+
+impl Diagnostics {
+    pub const fn new() -> Self {
+        Self {
+            frequency_hz: 0,
+            spreading_factor: 0,
+            bandwidth_hz: 0,
+            coding_rate: 0,
+            tx_power_dbm: 0,
+            rssi: None,
+            snr: None,
+            last_error: None,
+        }
+    }
+}
+
+/// Fills the screen with the radio's config and the signal of the last received packet, one value per line, for
+/// tuning range in the field.
+pub fn draw_diagnostics<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    diagnostics: &Diagnostics,
+) -> Result<(), D::Error> {
+    target.clear(Rgb565::BLACK)?;
+
+    let label_style = MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_LIGHT_GRAY);
+    let value_style = MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE);
+    let text_style = TextStyleBuilder::new().baseline(Baseline::Top).build();
+
+    Text::with_text_style("Diagnostics", Point::new(2, 1), label_style, text_style).draw(target)?;
+
+    let mut lines = heapless::String::<160>::new();
+    // Every value is a bounded number or a short error name, so always fits
+    write!(
+        lines,
+        "Freq {}.{:03} MHz\nSF{} BW{}k CR4/{}\nTX   {} dBm\n",
+        diagnostics.frequency_hz / 1_000_000,
+        diagnostics.frequency_hz / 1000 % 1000,
+        diagnostics.spreading_factor,
+        diagnostics.bandwidth_hz / 1000,
+        4 + diagnostics.coding_rate,
+        diagnostics.tx_power_dbm,
+    )
+    .unwrap();
+    match diagnostics.rssi {
+        Some(rssi) => writeln!(lines, "RSSI {rssi} dBm"),
+        None => writeln!(lines, "RSSI --"),
+    }
+    .unwrap();
+    match diagnostics.snr {
+        Some(snr) => writeln!(lines, "SNR  {snr} dB"),
+        None => writeln!(lines, "SNR  --"),
+    }
+    .unwrap();
+    write!(lines, "Err  {}", diagnostics.last_error.unwrap_or("none")).unwrap();
+
+    Text::with_text_style(&lines, Point::new(2, MESSAGE_TOP), value_style, text_style)
+        .draw(target)?;
+
+    Ok(())
+}
+
+/// Draws the status bar along the top of the screen, over whatever was there: signal strength on the left, then a
+/// BLE indicator, and the station name on the right.
+pub fn draw_status_bar<D: DrawTargetExt<Color = Rgb565>>(
+    target: &mut D,
+    status: &Status,
+) -> Result<(), D::Error> {
+    let width = common::ROTATED_WIDTH as i32;
+    let height = STATUS_BAR_HEIGHT as i32;
+
+    Rectangle::new(
+        Point::zero(),
+        Size::new(common::ROTATED_WIDTH, STATUS_BAR_HEIGHT),
+    )
+    .into_styled(PrimitiveStyle::with_fill(Rgb565::BLACK))
+    .draw(target)?;
+
+    // Each bar is taller than the last, and dimmed if the signal doesn't reach it
+    let bars = status.signal_bars.unwrap_or(0);
+    for bar in 0..SIGNAL_BARS {
+        let bar_height = height * i32::from(bar + 1) / i32::from(SIGNAL_BARS);
+        let color = if bar < bars {
+            Rgb565::WHITE
+        } else {
+            Rgb565::CSS_DIM_GRAY
+        };
+        Rectangle::new(
+            Point::new(2 + i32::from(bar) * 4, height - bar_height),
+            Size::new(3, bar_height as u32),
+        )
+        .into_styled(PrimitiveStyle::with_fill(color))
+        .draw(target)?;
+    }
+
+    let text_style = TextStyleBuilder::new().baseline(Baseline::Top).build();
+    if status.ble_connected {
+        Text::with_text_style(
+            "BT",
+            Point::new(2 + i32::from(SIGNAL_BARS) * 4 + 4, 1),
+            MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_DODGER_BLUE),
+            text_style,
+        )
+        .draw(target)?;
+    }
+
+    let right_style = TextStyleBuilder::new()
+        .alignment(Alignment::Right)
+        .baseline(Baseline::Top)
+        .build();
+    if let Some(age) = status.message_age {
+        let mut text = heapless::String::<24>::new();
+        // Always fits, even for u64::MAX hours
+        write!(text, "{age}").unwrap();
+        Text::with_text_style(
+            &text,
+            Point::new(width - 2, 1),
+            MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_LIGHT_GRAY),
+            right_style,
+        )
+        .draw(target)?;
+    } else if let Some(station) = status.station {
+        Text::with_text_style(
+            station.as_ref(),
+            Point::new(width - 2, 1),
+            MonoTextStyle::new(&FONT_6X10, Rgb565::WHITE),
+            right_style,
+        )
+        .draw(target)?;
+    }
+
+    // Badges are drawn last with their own background, so a long station name can't hide them
+    if status.emergency_broadcast {
+        let sos_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::WHITE)
+            .background_color(Rgb565::RED)
+            .build();
+        Text::with_text_style(
+            "SOS",
+            // After where BT would be, so they never overlap
+            Point::new(2 + i32::from(SIGNAL_BARS) * 4 + 4 + 18, 1),
+            sos_style,
+            text_style,
+        )
+        .draw(target)?;
+    }
+
+    if status.low_battery {
+        let low_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::BLACK)
+            .background_color(Rgb565::YELLOW)
+            .build();
+        Text::with_text_style(
+            "BAT",
+            // After where SOS would be
+            Point::new(2 + i32::from(SIGNAL_BARS) * 4 + 4 + 18 + 24, 1),
+            low_style,
+            text_style,
+        )
+        .draw(target)?;
+    }
+
+    if status.key_mismatch {
+        let key_style = MonoTextStyleBuilder::new()
+            .font(&FONT_6X10)
+            .text_color(Rgb565::WHITE)
+            .background_color(Rgb565::CSS_DARK_MAGENTA)
+            .build();
+        Text::with_text_style(
+            "KEY?",
+            // After where BAT would be
+            Point::new(2 + i32::from(SIGNAL_BARS) * 4 + 4 + 18 + 24 + 24, 1),
+            key_style,
+            text_style,
+        )
+        .draw(target)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn age_is_shown_in_the_largest_whole_unit() {
+        for (secs, shown) in [
+            (0, "now"),
+            (59, "now"),
+            (60, "1m ago"),
+            (119, "1m ago"),
+            (3599, "59m ago"),
+            (3600, "1h ago"),
+            (7199, "1h ago"),
+            (86_400, "24h ago"),
+        ] {
+            assert_eq!(MessageAge::Secs(secs).to_string(), shown, "{secs}s");
+        }
+        assert_eq!(MessageAge::BeforeRestart.to_string(), "before restart");
+    }
+
+    #[test]
+    fn age_changes_when_its_next_change_says() {
+        // Each age is shown differently from the one before it changes
+        for secs in [0, 59, 60, 61, 3599, 3600, 7199, 7200] {
+            let age = MessageAge::Secs(secs);
+            let next_change = age.next_change_secs().unwrap();
+            assert!(next_change > secs);
+            assert_eq!(
+                MessageAge::Secs(next_change - 1).to_string(),
+                age.to_string()
+            );
+            assert_ne!(MessageAge::Secs(next_change).to_string(), age.to_string());
+        }
+    }
 
-    text_box.draw(target).unwrap();
+    #[test]
+    fn age_stops_changing_when_it_cant_be_counted_higher() {
+        assert_eq!(MessageAge::Secs(u64::MAX).next_change_secs(), None);
+        assert_eq!(MessageAge::BeforeRestart.next_change_secs(), None);
+    }
 }
@@ -0,0 +1,105 @@
+//! Stepping the spreading factor up while messages go unacknowledged, trading airtime for range until they get
+//! through.
+
+/// Attempts in a row that go unacknowledged before the spreading factor steps up
+pub const STEP_UP_FAILURES: u8 = 2;
+/// Highest spreading factor stepped up to, the highest the SX1276 supports
+pub const MAX_SF: u8 = 12;
+
+/// Steps the spreading factor up from the preset's, one for every [`STEP_UP_FAILURES`] attempts in a row that go
+/// unacknowledged, up to [`MAX_SF`]. Each acknowledged message steps it back down one, until it's back at the
+/// preset's.
+pub struct SfAdapter {
+    base: u8,
+    current: u8,
+    failures: u8,
+}
+
+impl SfAdapter {
+    pub const fn new(base: u8) -> Self {
+        Self {
+            base,
+            current: base,
+            failures: 0,
+        }
+    }
+
+    /// Counts an attempt that went unacknowledged, returning the spreading factor to step up to if it's time to
+    pub const fn failed(&mut self) -> Option<u8> {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures < STEP_UP_FAILURES || self.current >= MAX_SF {
+            return None;
+        }
+        self.failures = 0;
+        self.current += 1;
+        Some(self.current)
+    }
+
+    /// Counts an acknowledged message, returning the spreading factor to step back down to if it was stepped up
+    pub const fn succeeded(&mut self) -> Option<u8> {
+        self.failures = 0;
+        if self.current <= self.base {
+            return None;
+        }
+        self.current -= 1;
+        Some(self.current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_up_once_every_few_failures() {
+        let mut adapter = SfAdapter::new(7);
+
+        for sf in 8..=10 {
+            for _ in 1..STEP_UP_FAILURES {
+                assert_eq!(adapter.failed(), None);
+            }
+            assert_eq!(adapter.failed(), Some(sf));
+        }
+    }
+
+    #[test]
+    fn stops_at_the_max() {
+        let mut adapter = SfAdapter::new(MAX_SF - 1);
+        assert_eq!(adapter.failed(), None);
+        assert_eq!(adapter.failed(), Some(MAX_SF));
+
+        for _ in 0..4 * STEP_UP_FAILURES {
+            assert_eq!(adapter.failed(), None);
+        }
+    }
+
+    #[test]
+    fn steps_back_down_no_further_than_the_preset() {
+        let mut adapter = SfAdapter::new(9);
+        assert_eq!(adapter.succeeded(), None);
+
+        for _ in 0..2 * STEP_UP_FAILURES {
+            adapter.failed();
+        }
+        assert_eq!(adapter.succeeded(), Some(10));
+        assert_eq!(adapter.succeeded(), Some(9));
+        assert_eq!(adapter.succeeded(), None);
+    }
+
+    #[test]
+    fn success_resets_the_failure_count() {
+        let mut adapter = SfAdapter::new(7);
+
+        for _ in 0..3 {
+            for _ in 1..STEP_UP_FAILURES {
+                assert_eq!(adapter.failed(), None);
+            }
+            // Not in a row, so it doesn't step up
+            assert_eq!(adapter.succeeded(), None);
+        }
+        for _ in 1..STEP_UP_FAILURES {
+            assert_eq!(adapter.failed(), None);
+        }
+        assert_eq!(adapter.failed(), Some(8));
+    }
+}
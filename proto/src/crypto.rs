@@ -0,0 +1,522 @@
+//! Encryption of packets laid out by this crate, with [`AsconAead128`].
+
+use ascon_aead::{
+    AsconAead128,
+    aead::{AeadInPlace, heapless::Vec},
+};
+use heapless::Deque;
+use rand_core::RngCore;
+
+use crate::{CRC_SIZE, HEADER_SIZE, Packet, random::fill_random};
+
+/// Size of the MAC appended to every encrypted packet
+pub const MAC_SIZE: usize = 16;
+/// Size of the nonce appended to every encrypted packet, after the MAC
+pub const NONCE_SIZE: usize = 16;
+// The tag and nonce appended by `encrypt_in_place` are the sizes `decrypt_in_place` splits them off at
+const _: () = assert!(MAC_SIZE == size_of::<ascon_aead::Tag<AsconAead128>>());
+const _: () = assert!(NONCE_SIZE == size_of::<ascon_aead::AsconAead128Nonce>());
+
+/// Nonces generated in a row that may repeat a recent one before giving up on encrypting
+const MAX_NONCE_ATTEMPTS: u8 = 4;
+
+/// The last `N` nonces this device encrypted with, so a faulty RNG repeating one is caught before the nonce is reused.
+/// Reusing a nonce with the same key gives away the plaintexts of both packets, and lets their MACs be forged.
+pub struct RecentNonces<const N: usize> {
+    used: Deque<[u8; NONCE_SIZE], N>,
+}
+
+impl<const N: usize> RecentNonces<N> {
+    pub const fn new() -> Self {
+        Self { used: Deque::new() }
+    }
+
+    /// Returns `true` and remembers `nonce` if it isn't one of the last `N`, forgetting the oldest if they're full.
+    pub fn insert_if_new(&mut self, nonce: [u8; NONCE_SIZE]) -> bool {
+        if self.used.iter().any(|used| *used == nonce) {
+            return false;
+        }
+
+        if self.used.is_full() {
+            self.used.pop_front();
+        }
+        let _ = self.used.push_back(nonce);
+        true
+    }
+}
+
+/// Encrypts the contents of `buf` in-place. It should hold a [`Packet`](crate::Packet) with a plaintext payload before
+/// calling. The whole header, magic word included, stays readable but is authenticated as associated data, so
+/// tampering with any of it fails decryption.
+///
+/// After a successful call, `buf` will have structure: `HEADER (HEADER_SIZE-bytes) | CIPHERTEXT | MAC (MAC_SIZE-bytes) | NONCE (NONCE_SIZE-bytes) | CRC (CRC_SIZE-bytes)`
+///
+/// Fails without encrypting if the RNG keeps generating nonces that were used recently.
+pub fn encrypt_in_place<const R: usize, const N: usize>(
+    cipher: &AsconAead128,
+    rng: &mut impl RngCore,
+    recent_nonces: &mut RecentNonces<R>,
+    buf: &mut Vec<u8, N>,
+) -> ascon_aead::aead::Result<()> {
+    // The header is already in `buf`, so only the MAC, nonce, and CRC need room
+    if buf.capacity() - buf.len() < MAC_SIZE + NONCE_SIZE + CRC_SIZE {
+        log::error!("encrypt buf too small for data, mac, nonce, and crc");
+        return Err(ascon_aead::Error);
+    }
+
+    let nonce = generate_nonce(rng, recent_nonces)?;
+    let (header, plaintext) = buf.split_at_mut(HEADER_SIZE);
+    let tag = cipher.encrypt_in_place_detached(&nonce, header, plaintext)?;
+    buf.extend_from_slice(&tag).unwrap();
+    buf.extend_from_slice(&nonce).unwrap();
+    let crc = crate::crc(buf);
+    buf.extend_from_slice(&crc).unwrap();
+
+    Ok(())
+}
+
+/// Replaces the contents of `buf` with `packet` and encrypts it, ready to send. Every packet a device sends goes
+/// through here, its own and relayed ones alike, so they're all framed the same way.
+///
+/// Fails without encrypting if `buf` can't hold the encrypted packet, or the RNG keeps repeating nonces.
+pub fn build_outgoing<const R: usize, const N: usize>(
+    cipher: &AsconAead128,
+    rng: &mut impl RngCore,
+    recent_nonces: &mut RecentNonces<R>,
+    packet: &Packet<'_>,
+    buf: &mut Vec<u8, N>,
+) -> ascon_aead::aead::Result<()> {
+    buf.clear();
+    buf.resize_default(N).unwrap();
+    let Some(len) = packet.serialize_into(buf) else {
+        log::error!(
+            "Packet of {} bytes doesn't fit its buf",
+            packet.serialized_len()
+        );
+        buf.clear();
+        return Err(ascon_aead::Error);
+    };
+    buf.truncate(len);
+
+    encrypt_in_place(cipher, rng, recent_nonces, buf)
+}
+
+/// Decrypts the contents of `buf` in-place. At call-time, buf should have structure: `HEADER (HEADER_SIZE-bytes) | CIPHERTEXT | MAC (MAC_SIZE-bytes) | NONCE (NONCE_SIZE-bytes)`
+///
+/// Fails if the header, ciphertext, or MAC were tampered with.
+///
+/// After this function is successful, `buf` will have the structure: `HEADER (HEADER_SIZE-bytes) | PLAINTEXT`
+pub fn decrypt_in_place<const N: usize>(
+    cipher: &AsconAead128,
+    buf: &mut Vec<u8, N>,
+) -> ascon_aead::aead::Result<()> {
+    // Anything shorter can't hold the header, MAC, and nonce, so would panic when split
+    if buf.len() < HEADER_SIZE + MAC_SIZE + NONCE_SIZE {
+        log::error!("Invalid decrypt buf len: {}", buf.len());
+        return Err(ascon_aead::Error);
+    }
+
+    let tag_pos = buf.len() - MAC_SIZE - NONCE_SIZE;
+    let (ciphertext, tag_and_nonce) = buf.split_at_mut(tag_pos);
+    let (tag, nonce) = tag_and_nonce.split_at_mut(MAC_SIZE);
+    let (header, ciphertext) = ciphertext.split_at_mut(HEADER_SIZE);
+
+    cipher.decrypt_in_place_detached(
+        ascon_aead::AsconAead128Nonce::from_slice(nonce),
+        header,
+        ciphertext,
+        ascon_aead::Tag::<AsconAead128>::from_slice(tag),
+    )?;
+    buf.truncate(tag_pos);
+
+    Ok(())
+}
+
+/// Generates a random nonce that isn't one of the recent ones, trying again up to [`MAX_NONCE_ATTEMPTS`] times if it
+/// is. A repeat out of 128 random bits means the RNG is broken, so it's never used.
+fn generate_nonce<const R: usize>(
+    rng: &mut impl RngCore,
+    recent_nonces: &mut RecentNonces<R>,
+) -> ascon_aead::aead::Result<ascon_aead::AsconAead128Nonce> {
+    for _ in 0..MAX_NONCE_ATTEMPTS {
+        let nonce = fill_random::<NONCE_SIZE>(rng);
+        if recent_nonces.insert_if_new(nonce) {
+            return Ok(ascon_aead::AsconAead128Nonce::clone_from_slice(&nonce));
+        }
+        log::warn!("Generated a recently used nonce, the RNG may be faulty. Generating another");
+    }
+
+    log::error!("RNG kept generating recently used nonces, refusing to encrypt");
+    Err(ascon_aead::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use ascon_aead::aead::KeyInit;
+
+    use super::*;
+    use crate::{
+        BROADCAST_ID, KIND_SIZE, MAX_MSG_LEN, MAX_PAYLOAD_LEN, PacketHeader, PacketType,
+        random::TestRng, tests::HEADER,
+    };
+
+    const KEY: [u8; 16] = *b"0123456789abcdef";
+    /// Fits the longest packet
+    const BUF_LEN: usize = 256;
+
+    fn cipher() -> AsconAead128 {
+        AsconAead128::new(ascon_aead::AsconAead128Key::from_slice(&KEY))
+    }
+
+    fn framed<const N: usize>(payload: &[u8]) -> Vec<u8, N> {
+        let mut buf = Vec::new();
+        buf.resize_default(N).unwrap();
+        let len = Packet {
+            header: HEADER,
+            payload,
+        }
+        .serialize_into(&mut buf)
+        .unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    /// Hands out the given nonces in order, to stand in for a faulty RNG that repeats itself. Each nonce fills up to
+    /// [`NONCE_SIZE`] bytes of whatever's asked for.
+    struct NonceRng<'a>(&'a [[u8; NONCE_SIZE]]);
+
+    impl RngCore for NonceRng<'_> {
+        fn next_u32(&mut self) -> u32 {
+            rand_core::impls::next_u32_via_fill(self)
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_fill(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            for chunk in dest.chunks_mut(NONCE_SIZE) {
+                let (next, rest) = self.0.split_first().expect("ran out of nonces");
+                chunk.copy_from_slice(&next[..chunk.len()]);
+                self.0 = rest;
+            }
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    /// Frames `packet` like a sender, then checks and decrypts it like a receiver.
+    fn send_and_receive(packet: &Packet<'_>) -> Vec<u8, BUF_LEN> {
+        let mut rng = TestRng::new(1);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = Vec::new();
+        build_outgoing(&cipher(), &mut rng, &mut recent_nonces, packet, &mut buf).unwrap();
+        assert_eq!(
+            buf.len(),
+            packet.serialized_len() + MAC_SIZE + NONCE_SIZE + CRC_SIZE
+        );
+        assert!(crate::crc_matches(&buf));
+
+        buf.truncate(buf.len() - CRC_SIZE);
+        decrypt_in_place(&cipher(), &mut buf).unwrap();
+        buf
+    }
+
+    fn assert_framed(packet: &Packet<'_>) {
+        let buf = send_and_receive(packet);
+        assert_eq!(Packet::deserialize_from(&buf).as_ref(), Some(packet));
+    }
+
+    #[test]
+    fn encrypted_packet_round_trips() {
+        let mut rng = TestRng::new(1);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(b"All good");
+        let plain = buf.clone();
+
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        assert_eq!(buf.len(), plain.len() + MAC_SIZE + NONCE_SIZE + CRC_SIZE);
+        assert_ne!(buf[HEADER_SIZE..plain.len()], plain[HEADER_SIZE..]);
+        // The header stays readable
+        assert_eq!(buf[..HEADER_SIZE], plain[..HEADER_SIZE]);
+        assert!(crate::crc_matches(&buf));
+
+        buf.truncate(buf.len() - CRC_SIZE);
+        decrypt_in_place(&cipher(), &mut buf).unwrap();
+        assert_eq!(buf, plain);
+        let packet = Packet::deserialize_from(&buf).unwrap();
+        assert_eq!(packet.header, HEADER);
+        assert_eq!(packet.payload, b"All good");
+    }
+
+    #[test]
+    fn empty_plaintext_round_trips() {
+        let mut rng = TestRng::new(2);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(&[]);
+        let plain = buf.clone();
+
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        assert_eq!(buf.len(), HEADER_SIZE + MAC_SIZE + NONCE_SIZE + CRC_SIZE);
+
+        buf.truncate(buf.len() - CRC_SIZE);
+        decrypt_in_place(&cipher(), &mut buf).unwrap();
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn max_length_plaintext_round_trips() {
+        let mut rng = TestRng::new(3);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let payload = [0xA5; KIND_SIZE + MAX_MSG_LEN];
+        let mut buf = framed::<MAX_PAYLOAD_LEN>(&payload);
+        let plain = buf.clone();
+
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        assert_eq!(buf.len(), MAX_PAYLOAD_LEN);
+
+        buf.truncate(buf.len() - CRC_SIZE);
+        decrypt_in_place(&cipher(), &mut buf).unwrap();
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn plaintext_over_max_length_fails_encryption() {
+        let mut rng = TestRng::new(9);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let payload = [0xA5; KIND_SIZE + MAX_MSG_LEN + 1];
+        let mut buf = framed::<MAX_PAYLOAD_LEN>(&payload);
+        let plain = buf.clone();
+
+        assert!(encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).is_err());
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn tampered_mac_fails_decryption() {
+        let mut rng = TestRng::new(4);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(b"Help");
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        buf.truncate(buf.len() - CRC_SIZE);
+
+        let mac_pos = buf.len() - NONCE_SIZE - MAC_SIZE;
+        buf[mac_pos] ^= 1;
+
+        assert!(decrypt_in_place(&cipher(), &mut buf).is_err());
+    }
+
+    #[test]
+    fn flipped_magic_word_fails_decryption() {
+        let mut rng = TestRng::new(7);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(b"Help");
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        buf.truncate(buf.len() - CRC_SIZE);
+
+        buf[0] ^= 0x80;
+
+        assert!(decrypt_in_place(&cipher(), &mut buf).is_err());
+    }
+
+    #[test]
+    fn tampered_sender_fails_decryption() {
+        let mut rng = TestRng::new(8);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(b"Help");
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+        buf.truncate(buf.len() - CRC_SIZE);
+
+        let sender_pos = crate::MAGIC_WORD_SIZE + crate::VERSION_SIZE + crate::PACKET_TYPE_SIZE;
+        buf[sender_pos] ^= 1;
+
+        assert!(decrypt_in_place(&cipher(), &mut buf).is_err());
+    }
+
+    #[test]
+    fn corrupted_payload_fails_crc_and_decryption() {
+        let mut rng = TestRng::new(6);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = framed::<128>(b"Help");
+        encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).unwrap();
+
+        buf[HEADER_SIZE] ^= 0x10;
+
+        // Caught before decrypting
+        assert!(!crate::crc_matches(&buf));
+        // And still caught by the MAC if the CRC had happened to match
+        buf.truncate(buf.len() - CRC_SIZE);
+        assert!(decrypt_in_place(&cipher(), &mut buf).is_err());
+    }
+
+    #[test]
+    fn too_short_buffer_fails_decryption() {
+        for len in [0, HEADER_SIZE, HEADER_SIZE + MAC_SIZE + NONCE_SIZE - 1] {
+            let mut buf = Vec::<u8, BUF_LEN>::new();
+            buf.resize(len, 0xA5).unwrap();
+
+            assert!(decrypt_in_place(&cipher(), &mut buf).is_err());
+            // Left as it was
+            assert_eq!(buf.len(), len);
+        }
+    }
+
+    #[test]
+    fn too_short_buffer_fails_encryption() {
+        let mut rng = TestRng::new(5);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        // One byte short of room for the CRC
+        let mut buf = framed::<{ HEADER_SIZE + 4 + MAC_SIZE + NONCE_SIZE + CRC_SIZE - 1 }>(b"Help");
+        let plain = buf.clone();
+
+        assert!(encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).is_err());
+        // Left as it was rather than half encrypted
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn frames_data() {
+        assert_framed(&Packet {
+            header: HEADER,
+            payload: b"\x00Meet at the trailhead",
+        });
+    }
+
+    #[test]
+    fn frames_ack() {
+        assert_framed(&Packet {
+            header: PacketHeader {
+                packet_type: PacketType::Ack,
+                destination: 0x0BAD,
+                fragment: 0,
+                fragment_count: 1,
+                ..HEADER
+            },
+            payload: &[0xAD, 0x0B, 4, 3, 2, 1],
+        });
+    }
+
+    #[test]
+    fn frames_time_sync() {
+        assert_framed(&Packet {
+            header: PacketHeader {
+                packet_type: PacketType::TimeSync,
+                fragment: 0,
+                fragment_count: 1,
+                ..HEADER
+            },
+            payload: &[],
+        });
+    }
+
+    #[test]
+    fn frames_test() {
+        assert_framed(&Packet {
+            header: PacketHeader {
+                packet_type: PacketType::Test,
+                hops: 0,
+                fragment: 0,
+                fragment_count: 1,
+                ..HEADER
+            },
+            payload: b"test",
+        });
+    }
+
+    #[test]
+    fn relayed_packet_keeps_its_senders_header() {
+        // Relays send someone else's header with one less hop, and must not stamp their own on it
+        let relayed = Packet {
+            header: PacketHeader {
+                sender_id: 0x0042,
+                destination: BROADCAST_ID,
+                hops: HEADER.hops - 1,
+                ..HEADER
+            },
+            payload: b"\x00Relayed",
+        };
+        assert_framed(&relayed);
+    }
+
+    #[test]
+    fn build_outgoing_replaces_what_was_in_buf() {
+        let mut rng = TestRng::new(1);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = Vec::<u8, BUF_LEN>::from_slice(b"left over from the last packet").unwrap();
+        let packet = Packet {
+            header: HEADER,
+            payload: b"new",
+        };
+
+        build_outgoing(&cipher(), &mut rng, &mut recent_nonces, &packet, &mut buf).unwrap();
+
+        assert!(buf.starts_with(&crate::MAGIC_WORD.to_le_bytes()));
+        assert_eq!(
+            buf.len(),
+            HEADER_SIZE + 3 + MAC_SIZE + NONCE_SIZE + CRC_SIZE
+        );
+    }
+
+    #[test]
+    fn build_outgoing_rejects_packet_too_big_for_buf() {
+        let mut rng = TestRng::new(1);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let mut buf = Vec::<u8, { HEADER_SIZE + 4 }>::new();
+        let packet = Packet {
+            header: HEADER,
+            payload: b"too long",
+        };
+
+        assert!(
+            build_outgoing(&cipher(), &mut rng, &mut recent_nonces, &packet, &mut buf).is_err()
+        );
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn colliding_nonce_is_regenerated() {
+        let mut rng = NonceRng(&[[1; NONCE_SIZE], [1; NONCE_SIZE], [2; NONCE_SIZE]]);
+        let mut recent_nonces = RecentNonces::<4>::new();
+
+        let first = generate_nonce(&mut rng, &mut recent_nonces).unwrap();
+        let second = generate_nonce(&mut rng, &mut recent_nonces).unwrap();
+
+        assert_eq!(first.as_slice(), [1; NONCE_SIZE]);
+        // The repeat was thrown away and another drawn
+        assert_eq!(second.as_slice(), [2; NONCE_SIZE]);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn rng_stuck_on_one_nonce_refuses_to_encrypt() {
+        let stuck = [[7; NONCE_SIZE]; 1 + MAX_NONCE_ATTEMPTS as usize];
+        let mut rng = NonceRng(&stuck);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        generate_nonce(&mut rng, &mut recent_nonces).unwrap();
+        let mut buf = framed::<BUF_LEN>(b"Help");
+        let plain = buf.clone();
+
+        assert!(encrypt_in_place(&cipher(), &mut rng, &mut recent_nonces, &mut buf).is_err());
+        assert!(rng.0.is_empty());
+        assert_eq!(buf, plain);
+    }
+
+    #[test]
+    fn nonce_is_reusable_once_forgotten() {
+        let mut rng = NonceRng(&[
+            [1; NONCE_SIZE],
+            [2; NONCE_SIZE],
+            [3; NONCE_SIZE],
+            [1; NONCE_SIZE],
+        ]);
+        let mut recent_nonces = RecentNonces::<2>::new();
+
+        for _ in 0..4 {
+            generate_nonce(&mut rng, &mut recent_nonces).unwrap();
+        }
+        assert!(rng.0.is_empty());
+    }
+}
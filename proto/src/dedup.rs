@@ -0,0 +1,246 @@
+//! Recognizing packets that were already received, whether sent again by their sender, relayed back by another
+//! device, or captured and replayed.
+
+use embassy_time::{Duration, Instant};
+use heapless::{Deque, FnvIndexMap};
+
+/// Fixed-capacity cache of keys identifying recently received packets, so repeated transmissions of the same packet
+/// are only handled once. Keys are forgotten after `window`, or sooner if more than `N` packets arrive within it.
+pub struct RecentPackets<K, const N: usize> {
+    window: Duration,
+    seen: Deque<(K, Instant), N>,
+}
+
+impl<K: PartialEq, const N: usize> RecentPackets<K, N> {
+    pub const fn new(window: Duration) -> Self {
+        Self {
+            window,
+            seen: Deque::new(),
+        }
+    }
+
+    /// Returns `true` if a packet with `key` was inserted within the window.
+    pub fn is_duplicate(&mut self, key: &K) -> bool {
+        self.evict_expired();
+        self.seen.iter().any(|(seen, _)| seen == key)
+    }
+
+    /// Remembers `key`, evicting the oldest entry if the cache is full.
+    pub fn insert(&mut self, key: K) {
+        self.evict_expired();
+        if self.seen.is_full() {
+            self.seen.pop_front();
+        }
+
+        let _ = self.seen.push_back((key, Instant::now()));
+    }
+
+    fn evict_expired(&mut self) {
+        let now = Instant::now();
+        while self
+            .seen
+            .front()
+            .is_some_and(|(_, seen_at)| now - *seen_at > self.window)
+        {
+            self.seen.pop_front();
+        }
+    }
+}
+
+/// Tracks which sequence numbers were accepted from each sender, so captured packets can't be replayed later.
+///
+/// Each sequence number is accepted once, as long as it's one of the [`REPLAY_WINDOW`] up to the highest accepted from
+/// its sender. Packets arriving out of order, like a message resent after a call for help that went out ahead of it,
+/// still get through, while anything older is rejected. Only the latest `N` senders are tracked; if more show up, an
+/// arbitrary one is forgotten to make room.
+pub struct ReplayGuard<const N: usize> {
+    senders: FnvIndexMap<u16, AcceptedSequences, N>,
+}
+
+/// Number of sequence numbers up to the highest accepted from a sender that can still be accepted once
+pub const REPLAY_WINDOW: u32 = u64::BITS;
+
+/// Sequence numbers accepted from one sender
+struct AcceptedSequences {
+    highest: u32,
+    /// Bit `i` is set once `highest - i` has been accepted
+    accepted: u64,
+}
+
+impl AcceptedSequences {
+    const fn new(sequence: u32) -> Self {
+        Self {
+            highest: sequence,
+            accepted: 1,
+        }
+    }
+
+    const fn accept(&mut self, sequence: u32) -> bool {
+        if sequence > self.highest {
+            let shift = sequence - self.highest;
+            self.accepted = if shift < REPLAY_WINDOW {
+                (self.accepted << shift) | 1
+            } else {
+                1
+            };
+            self.highest = sequence;
+            return true;
+        }
+
+        let age = self.highest - sequence;
+        if age >= REPLAY_WINDOW || self.accepted & (1 << age) != 0 {
+            return false;
+        }
+        self.accepted |= 1 << age;
+        true
+    }
+}
+
+impl<const N: usize> ReplayGuard<N> {
+    pub const fn new() -> Self {
+        Self {
+            senders: FnvIndexMap::new(),
+        }
+    }
+
+    /// Returns `true` and records `sequence` if it hasn't been accepted from `sender` before, and isn't too old to tell.
+    /// Should only be called for authenticated packets.
+    pub fn accept(&mut self, sender_id: u16, sequence: u32) -> bool {
+        if let Some(accepted) = self.senders.get_mut(&sender_id) {
+            return accepted.accept(sequence);
+        }
+
+        if self.senders.len() == N {
+            let forgotten = *self.senders.keys().next().unwrap();
+            self.senders.remove(&forgotten);
+        }
+
+        let _ = self
+            .senders
+            .insert(sender_id, AcceptedSequences::new(sequence));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ascon_aead::{AsconAead128, AsconAead128Key, aead::KeyInit};
+
+    use super::*;
+    use crate::{
+        CRC_SIZE, Packet, crc_matches,
+        crypto::{RecentNonces, build_outgoing, decrypt_in_place},
+        random::TestRng,
+        sequence_start,
+        tests::HEADER,
+    };
+
+    #[test]
+    fn repeated_transmission_is_surfaced_once() {
+        let cipher = AsconAead128::new(AsconAead128Key::from_slice(b"0123456789abcdef"));
+        let mut frame = heapless::Vec::<u8, 128>::new();
+        build_outgoing(
+            &cipher,
+            &mut TestRng::new(1),
+            &mut RecentNonces::<4>::new(),
+            &Packet {
+                header: HEADER,
+                payload: b"All good!",
+            },
+            &mut frame,
+        )
+        .unwrap();
+        let mut recent_packets = RecentPackets::<(u16, u32), 8>::new(Duration::from_secs(30));
+
+        // The same frame, heard once for each time it was transmitted
+        let surfaced = [frame.clone(), frame]
+            .into_iter()
+            .filter(|frame| {
+                assert!(crc_matches(frame));
+                let mut buf = frame.clone();
+                buf.truncate(buf.len() - CRC_SIZE);
+                decrypt_in_place(&cipher, &mut buf).unwrap();
+                let header = Packet::deserialize_from(&buf).unwrap().header;
+
+                let key = (header.sender_id, header.sequence);
+                if recent_packets.is_duplicate(&key) {
+                    return false;
+                }
+                recent_packets.insert(key);
+                true
+            })
+            .count();
+
+        assert_eq!(surfaced, 1);
+    }
+
+    #[test]
+    fn packet_is_forgotten_after_its_window() {
+        let mut recent_packets = RecentPackets::<(u16, u32), 8>::new(Duration::from_millis(20));
+        recent_packets.insert((1, 10));
+        assert!(recent_packets.is_duplicate(&(1, 10)));
+        assert!(!recent_packets.is_duplicate(&(1, 11)));
+        assert!(!recent_packets.is_duplicate(&(2, 10)));
+
+        std::thread::sleep(core::time::Duration::from_millis(40));
+
+        assert!(!recent_packets.is_duplicate(&(1, 10)));
+    }
+
+    #[test]
+    fn replayed_packet_is_rejected() {
+        let mut guard = ReplayGuard::<4>::new();
+
+        assert!(guard.accept(1, 10));
+        assert!(guard.accept(1, 11));
+        assert!(!guard.accept(1, 10));
+        assert!(!guard.accept(1, 11));
+    }
+
+    #[test]
+    fn replay_after_reboot_is_rejected() {
+        let mut guard = ReplayGuard::<4>::new();
+        let before_reboot = sequence_start(7) + 1;
+        assert!(guard.accept(1, before_reboot));
+
+        // The sender reboots and numbers its packets from the next boot's range
+        let after_reboot = sequence_start(8) + 1;
+        assert!(after_reboot > before_reboot);
+        assert!(guard.accept(1, after_reboot));
+
+        assert!(!guard.accept(1, before_reboot));
+        assert!(!guard.accept(1, after_reboot));
+    }
+
+    #[test]
+    fn senders_are_tracked_separately() {
+        let mut guard = ReplayGuard::<4>::new();
+
+        assert!(guard.accept(1, 10));
+        assert!(guard.accept(2, 10));
+        assert!(!guard.accept(2, 10));
+    }
+
+    #[test]
+    fn out_of_order_packet_is_accepted_once() {
+        let mut guard = ReplayGuard::<4>::new();
+        assert!(guard.accept(1, 10));
+        assert!(guard.accept(1, 12));
+
+        // Arriving late, like a message held back behind a call for help
+        assert!(guard.accept(1, 11));
+        assert!(guard.accept(1, 9));
+        assert!(!guard.accept(1, 11));
+        assert!(!guard.accept(1, 9));
+    }
+
+    #[test]
+    fn packet_older_than_window_is_rejected() {
+        let mut guard = ReplayGuard::<4>::new();
+        assert!(guard.accept(1, 100));
+        assert!(guard.accept(1, 100 + REPLAY_WINDOW));
+
+        assert!(!guard.accept(1, 100));
+        assert!(guard.accept(1, 101));
+    }
+}
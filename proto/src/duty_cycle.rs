@@ -0,0 +1,155 @@
+//! Keeping transmissions within the airtime regional regulations allow.
+
+use embassy_time::{Duration, Instant};
+use heapless::Deque;
+
+/// LoRa settings that determine how long a packet takes to transmit
+#[derive(Debug, Clone, Copy)]
+pub struct AirtimeParams {
+    pub spreading_factor: u8,
+    pub bandwidth_hz: u32,
+    /// `n` in a coding rate of `4/(4 + n)`, from 1 to 4
+    pub coding_rate: u8,
+    pub preamble_len: u16,
+    pub explicit_header: bool,
+    pub crc: bool,
+}
+
+impl AirtimeParams {
+    /// Estimates how long a packet with `payload_len` bytes takes to transmit, using the time on air formula from the
+    /// SX1276 datasheet.
+    pub fn airtime(&self, payload_len: u8) -> Duration {
+        let sf = i64::from(self.spreading_factor);
+        let bandwidth_hz = u64::from(self.bandwidth_hz);
+        // Low data rate optimization is required once symbols are longer than 16ms
+        let low_data_rate = i64::from((1_000_000_u64 << sf) / bandwidth_hz > 16_000);
+
+        let payload_bits = 8 * i64::from(payload_len) - 4 * sf + 28 + 16 * i64::from(self.crc)
+            - 20 * i64::from(!self.explicit_header);
+        let bits_per_block = 4 * (sf - 2 * low_data_rate);
+        let blocks = if payload_bits > 0 {
+            (payload_bits + bits_per_block - 1) / bits_per_block
+        } else {
+            0
+        };
+        let payload_symbols = 8 + blocks * (i64::from(self.coding_rate) + 4);
+
+        // The preamble takes an extra 4.25 symbols, so count quarter symbols to stay in integers
+        let quarter_symbols =
+            (4 * (i64::from(self.preamble_len) + payload_symbols) + 17).unsigned_abs();
+        Duration::from_micros(((quarter_symbols * 1_000_000) << sf) / (4 * bandwidth_hz))
+    }
+}
+
+/// Keeps the time spent transmitting under a fraction of a sliding window, as regulations require in some regions.
+pub struct DutyCycle<const N: usize> {
+    window: Duration,
+    /// Max airtime allowed within `window`
+    budget: Duration,
+    /// When each transmission within `window` started and its airtime, oldest first
+    transmissions: Deque<(Instant, Duration), N>,
+}
+
+impl<const N: usize> DutyCycle<N> {
+    /// Allows transmitting for `permille` thousandths of any `window`.
+    pub fn new(window: Duration, permille: u16) -> Self {
+        Self {
+            window,
+            budget: window * u32::from(permille) / 1000,
+            transmissions: Deque::new(),
+        }
+    }
+
+    /// Returns how long to wait before `airtime` more can be spent transmitting, or `None` if it can be spent now.
+    pub fn wait_time(&mut self, airtime: Duration) -> Option<Duration> {
+        let now = Instant::now();
+        self.evict_expired(now);
+
+        let mut used = self
+            .transmissions
+            .iter()
+            .fold(Duration::from_ticks(0), |used, (_, airtime)| {
+                used + *airtime
+            });
+        if used + airtime <= self.budget && !self.transmissions.is_full() {
+            return None;
+        }
+
+        // Wait for the oldest transmissions to leave the window until there's enough budget
+        for (sent_at, sent_airtime) in &self.transmissions {
+            used -= *sent_airtime;
+            if used + airtime <= self.budget {
+                return Some(*sent_at + self.window - now);
+            }
+        }
+
+        // Doesn't fit even in an empty window
+        Some(self.window)
+    }
+
+    /// Records that `airtime` was just spent transmitting.
+    pub fn record(&mut self, airtime: Duration) {
+        let now = Instant::now();
+        self.evict_expired(now);
+        if self.transmissions.is_full() {
+            self.transmissions.pop_front();
+        }
+
+        let _ = self.transmissions.push_back((now, airtime));
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        while self
+            .transmissions
+            .front()
+            .is_some_and(|(sent_at, _)| now - *sent_at > self.window)
+        {
+            self.transmissions.pop_front();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const fn params(spreading_factor: u8) -> AirtimeParams {
+        AirtimeParams {
+            spreading_factor,
+            bandwidth_hz: 125_000,
+            coding_rate: 1,
+            preamble_len: 8,
+            explicit_header: true,
+            crc: true,
+        }
+    }
+
+    // Expected times are from Semtech's LoRa calculator, at 125kHz, CR 4/5, and an 8 symbol preamble
+
+    #[test]
+    fn airtime_at_sf7() {
+        assert_eq!(params(7).airtime(10), Duration::from_micros(41_216));
+        assert_eq!(params(7).airtime(50), Duration::from_micros(97_536));
+    }
+
+    #[test]
+    fn airtime_at_sf8() {
+        assert_eq!(params(8).airtime(20), Duration::from_micros(102_912));
+    }
+
+    #[test]
+    fn airtime_at_sf12_uses_low_data_rate_optimization() {
+        assert_eq!(params(12).airtime(10), Duration::from_micros(991_232));
+    }
+
+    #[test]
+    fn airtime_without_header_or_crc() {
+        let params = AirtimeParams {
+            explicit_header: false,
+            crc: false,
+            ..params(7)
+        };
+
+        assert_eq!(params.airtime(10), Duration::from_micros(36_096));
+    }
+}
@@ -0,0 +1,64 @@
+//! Converting between a device's uptime and the time shared across the fleet, which differ by an offset each device
+//! keeps once it's synced.
+
+/// Offset from uptime to fleet time, when the fleet time is `fleet_secs` at `uptime_secs`.
+#[allow(clippy::cast_possible_wrap)]
+pub const fn offset_secs(fleet_secs: u32, uptime_secs: u64) -> i64 {
+    // Uptime fits in an i64 for billions of years
+    fleet_secs as i64 - uptime_secs as i64
+}
+
+/// Fleet time at `uptime_secs`, or `None` if it's outside what a `u32` holds.
+#[allow(clippy::cast_possible_wrap)]
+pub fn to_fleet_secs(offset_secs: i64, uptime_secs: u64) -> Option<u32> {
+    u32::try_from(uptime_secs as i64 + offset_secs).ok()
+}
+
+/// Uptime at `fleet_secs`, or 0 if that was before boot.
+pub fn to_uptime_secs(offset_secs: i64, fleet_secs: u32) -> u64 {
+    u64::try_from(i64::from(fleet_secs) - offset_secs).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_set_is_read_back_as_it_runs() {
+        let offset = offset_secs(1_700_000_000, 120);
+
+        assert_eq!(to_fleet_secs(offset, 120), Some(1_700_000_000));
+        assert_eq!(to_fleet_secs(offset, 180), Some(1_700_000_060));
+        assert_eq!(to_uptime_secs(offset, 1_700_000_060), 180);
+    }
+
+    #[test]
+    fn fleet_time_can_be_behind_uptime() {
+        // Fleet time doesn't have to be since the epoch, so it can be set lower than the uptime
+        let offset = offset_secs(100, 1_000);
+        assert_eq!(offset, -900);
+
+        assert_eq!(to_fleet_secs(offset, 1_000), Some(100));
+        assert_eq!(to_fleet_secs(offset, 1_050), Some(150));
+        assert_eq!(to_uptime_secs(offset, 150), 1_050);
+        // Before fleet time started counting
+        assert_eq!(to_fleet_secs(offset, 899), None);
+    }
+
+    #[test]
+    fn fleet_time_past_u32_is_none() {
+        let offset = offset_secs(u32::MAX - 10, 0);
+
+        assert_eq!(to_fleet_secs(offset, 10), Some(u32::MAX));
+        assert_eq!(to_fleet_secs(offset, 11), None);
+    }
+
+    #[test]
+    fn times_before_boot_are_clamped_to_it() {
+        let offset = offset_secs(1_700_000_000, 60);
+
+        assert_eq!(to_uptime_secs(offset, 1_699_999_940), 0);
+        assert_eq!(to_uptime_secs(offset, 1_699_999_000), 0);
+        assert_eq!(to_uptime_secs(offset, 0), 0);
+    }
+}
@@ -0,0 +1,319 @@
+//! Splitting messages too long for one packet across several, and putting them back together.
+
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+/// Max number of fragments a message can be split into
+pub const MAX_FRAGMENTS: usize = 4;
+
+/// Splits `data` into the payloads of each fragment, each at most `fragment_len` bytes.
+/// Empty data still gets one empty fragment.
+pub fn split(data: &[u8], fragment_len: usize) -> impl Iterator<Item = &[u8]> {
+    let empty: &[u8] = &[];
+    data.chunks(fragment_len)
+        .chain(data.is_empty().then_some(empty))
+}
+
+/// A message from one sender that's still missing fragments
+struct Partial<const N: usize> {
+    sender_id: u16,
+    /// Sequence number of the message's first fragment, which identifies the message
+    first_sequence: u32,
+    fragment_count: u8,
+    /// Bit `i` is set once fragment `i` has arrived
+    received: u8,
+    /// Fragment `i` is stored at `i * fragment_len`
+    data: Vec<u8, N>,
+    /// Length of the last fragment, once it has arrived
+    last_len: usize,
+    started_at: Instant,
+}
+
+/// Puts messages split with [`split`] back together, holding partial messages from up to `S` senders at a time.
+/// Messages can be up to `N` bytes long.
+pub struct Reassembler<const S: usize, const N: usize> {
+    fragment_len: usize,
+    /// Partial messages are discarded if they aren't completed within this long
+    timeout: Duration,
+    partials: Vec<Partial<N>, S>,
+    complete: Vec<u8, N>,
+}
+
+impl<const S: usize, const N: usize> Reassembler<S, N> {
+    pub const fn new(fragment_len: usize, timeout: Duration) -> Self {
+        Self {
+            fragment_len,
+            timeout,
+            partials: Vec::new(),
+            complete: Vec::new(),
+        }
+    }
+
+    /// Adds fragment `index` of `fragment_count` of a message, returning the whole message once all of its fragments
+    /// have arrived.
+    ///
+    /// Fragments of a message must have consecutive sequence numbers, so the message is identified by its sender and
+    /// the sequence number of its first fragment.
+    pub fn insert<'a>(
+        &'a mut self,
+        sender_id: u16,
+        sequence: u32,
+        index: u8,
+        fragment_count: u8,
+        payload: &'a [u8],
+    ) -> Option<&'a [u8]> {
+        if fragment_count == 1 && index == 0 {
+            return Some(payload);
+        }
+
+        // Checked, since the index comes off the air and may be anything
+        let is_last = index.checked_add(1) == Some(fragment_count);
+        if index >= fragment_count
+            || usize::from(fragment_count) > MAX_FRAGMENTS
+            || usize::from(fragment_count) * self.fragment_len > N
+            || payload.len() > self.fragment_len
+            || (!is_last && payload.len() != self.fragment_len)
+        {
+            log::error!("Invalid fragment {index}/{fragment_count} from {sender_id:04X}");
+            return None;
+        }
+
+        self.evict_expired();
+
+        let first_sequence = sequence.wrapping_sub(index.into());
+        // A sender only sends one message at a time, so any other partial message from it won't be completed
+        self.partials.retain(|partial| {
+            let abandoned =
+                partial.sender_id == sender_id && partial.first_sequence != first_sequence;
+            if abandoned {
+                log::warn!("Discarding incomplete message from {sender_id:04X}");
+            }
+            !abandoned
+        });
+
+        let pos = if let Some(pos) = self.partials.iter().position(|partial| {
+            partial.sender_id == sender_id && partial.first_sequence == first_sequence
+        }) {
+            pos
+        } else {
+            if self.partials.is_full() {
+                log::warn!("Too many incomplete messages, discarding the oldest");
+                self.partials.remove(0);
+            }
+
+            let mut data = Vec::new();
+            data.resize_default(usize::from(fragment_count) * self.fragment_len)
+                .unwrap();
+            let _ = self.partials.push(Partial {
+                sender_id,
+                first_sequence,
+                fragment_count,
+                received: 0,
+                data,
+                last_len: 0,
+                started_at: Instant::now(),
+            });
+            self.partials.len() - 1
+        };
+
+        let partial = &mut self.partials[pos];
+        if partial.fragment_count != fragment_count {
+            log::error!("Fragment count changed mid-message from {sender_id:04X}");
+            self.partials.remove(pos);
+            return None;
+        }
+
+        let offset = usize::from(index) * self.fragment_len;
+        partial.data[offset..offset + payload.len()].copy_from_slice(payload);
+        partial.received |= 1 << index;
+        if is_last {
+            partial.last_len = payload.len();
+        }
+
+        if partial.received.count_ones() < u32::from(fragment_count) {
+            return None;
+        }
+
+        let partial = self.partials.remove(pos);
+        let len = (usize::from(fragment_count) - 1) * self.fragment_len + partial.last_len;
+        self.complete.clear();
+        self.complete
+            .extend_from_slice(&partial.data[..len])
+            .unwrap();
+        Some(&self.complete)
+    }
+
+    fn evict_expired(&mut self) {
+        let timeout = self.timeout;
+        self.partials.retain(|partial| {
+            let expired = partial.started_at.elapsed() > timeout;
+            if expired {
+                log::warn!(
+                    "Timed out waiting for fragments from {:04X}",
+                    partial.sender_id
+                );
+            }
+            !expired
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ascon_aead::{AsconAead128, AsconAead128Key, aead::KeyInit};
+
+    use super::*;
+    use crate::{
+        CRC_SIZE, MAX_MSG_LEN, Packet, PacketHeader,
+        crypto::{RecentNonces, build_outgoing, decrypt_in_place},
+        random::TestRng,
+        tests::HEADER,
+    };
+
+    const MESSAGE_LEN: usize = 400;
+
+    fn message() -> [u8; MESSAGE_LEN] {
+        core::array::from_fn(|i| (i % 251) as u8)
+    }
+
+    /// Sends each fragment of `data` as its own encrypted packet, and returns them as they're received: decrypted,
+    /// with the header and payload still together.
+    fn send_fragments(data: &[u8]) -> std::vec::Vec<Vec<u8, 256>> {
+        let cipher = AsconAead128::new(AsconAead128Key::from_slice(b"0123456789abcdef"));
+        let mut rng = TestRng::new(1);
+        let mut recent_nonces = RecentNonces::<4>::new();
+        let fragment_count = u8::try_from(split(data, MAX_MSG_LEN).count()).unwrap();
+
+        (0..)
+            .zip(split(data, MAX_MSG_LEN))
+            .map(|(index, payload)| {
+                let packet = Packet {
+                    header: PacketHeader {
+                        sequence: HEADER.sequence + u32::from(index),
+                        fragment: index,
+                        fragment_count,
+                        ..HEADER
+                    },
+                    payload,
+                };
+                let mut buf = Vec::new();
+                build_outgoing(&cipher, &mut rng, &mut recent_nonces, &packet, &mut buf).unwrap();
+                buf.truncate(buf.len() - CRC_SIZE);
+                decrypt_in_place(&cipher, &mut buf).unwrap();
+                buf
+            })
+            .collect()
+    }
+
+    fn receive<'a>(
+        reassembler: &'a mut Reassembler<2, { MAX_FRAGMENTS * MAX_MSG_LEN }>,
+        buf: &'a [u8],
+    ) -> Option<&'a [u8]> {
+        let packet = Packet::deserialize_from(buf).unwrap();
+        reassembler.insert(
+            packet.header.sender_id,
+            packet.header.sequence,
+            packet.header.fragment,
+            packet.header.fragment_count,
+            packet.payload,
+        )
+    }
+
+    #[test]
+    fn long_message_round_trips() {
+        let data = message();
+        let packets = send_fragments(&data);
+        assert_eq!(packets.len(), MESSAGE_LEN.div_ceil(MAX_MSG_LEN));
+        let mut reassembler = Reassembler::new(MAX_MSG_LEN, Duration::from_secs(30));
+
+        let (last, rest) = packets.split_last().unwrap();
+        for packet in rest {
+            assert_eq!(receive(&mut reassembler, packet), None);
+        }
+
+        assert_eq!(receive(&mut reassembler, last), Some(&data[..]));
+    }
+
+    #[test]
+    fn out_of_order_fragments_are_reassembled() {
+        let data = message();
+        let mut packets = send_fragments(&data);
+        packets.reverse();
+        let mut reassembler = Reassembler::new(MAX_MSG_LEN, Duration::from_secs(30));
+
+        let (last, rest) = packets.split_last().unwrap();
+        for packet in rest {
+            assert_eq!(receive(&mut reassembler, packet), None);
+        }
+
+        assert_eq!(receive(&mut reassembler, last), Some(&data[..]));
+    }
+
+    #[test]
+    fn short_message_is_one_fragment() {
+        let packets = send_fragments(b"All good!");
+        assert_eq!(packets.len(), 1);
+        let mut reassembler = Reassembler::new(MAX_MSG_LEN, Duration::from_secs(30));
+
+        assert_eq!(
+            receive(&mut reassembler, &packets[0]),
+            Some(&b"All good!"[..])
+        );
+    }
+
+    #[test]
+    fn partial_message_times_out() {
+        let data = message();
+        let packets = send_fragments(&data);
+        let mut reassembler = Reassembler::new(MAX_MSG_LEN, Duration::from_millis(20));
+
+        let (last, rest) = packets.split_last().unwrap();
+        for packet in rest {
+            assert_eq!(receive(&mut reassembler, packet), None);
+        }
+        std::thread::sleep(core::time::Duration::from_millis(40));
+
+        // The fragments before it were discarded, so it's still incomplete
+        assert_eq!(receive(&mut reassembler, last), None);
+    }
+
+    #[test]
+    fn out_of_range_fragment_is_rejected() {
+        let mut reassembler: Reassembler<2, { MAX_FRAGMENTS * MAX_MSG_LEN }> =
+            Reassembler::new(MAX_MSG_LEN, Duration::from_secs(30));
+        let payload = [0; MAX_MSG_LEN];
+
+        assert_eq!(reassembler.insert(0x1A2B, 1, u8::MAX, 2, &payload), None);
+        assert_eq!(
+            reassembler.insert(0x1A2B, 1, u8::MAX, u8::MAX, &payload),
+            None
+        );
+        assert_eq!(reassembler.insert(0x1A2B, 1, 2, 2, &payload), None);
+        assert_eq!(reassembler.insert(0x1A2B, 1, 3, 2, &payload), None);
+        assert_eq!(reassembler.insert(0x1A2B, 1, 0, 0, &payload), None);
+        // Nothing was kept from any of them
+        assert!(reassembler.partials.is_empty());
+    }
+
+    #[test]
+    fn split_starts_a_new_fragment_past_the_max_len() {
+        let data = [0xA5; MAX_FRAGMENTS * MAX_MSG_LEN + 1];
+
+        assert_eq!(split(&data[..MAX_MSG_LEN], MAX_MSG_LEN).count(), 1);
+        assert_eq!(split(&data[..MAX_MSG_LEN + 1], MAX_MSG_LEN).count(), 2);
+        assert_eq!(
+            split(&data[..MAX_FRAGMENTS * MAX_MSG_LEN], MAX_MSG_LEN).count(),
+            MAX_FRAGMENTS
+        );
+        // One byte more than a receiver can put back together
+        assert_eq!(split(&data, MAX_MSG_LEN).count(), MAX_FRAGMENTS + 1);
+    }
+
+    #[test]
+    fn split_of_nothing_is_one_empty_fragment() {
+        let empty: &[u8] = &[];
+        let fragments: std::vec::Vec<_> = split(empty, MAX_MSG_LEN).collect();
+
+        assert_eq!(fragments, [empty]);
+    }
+}
@@ -0,0 +1,64 @@
+//! Picking which of the hop channels the fleet is on, so an interferer parked on one frequency only blocks part of the
+//! time.
+
+/// Seconds of fleet time the radio stays on each hop channel. Long next to a packet's airtime, so few are cut off by a
+/// hop, and next to how far apart synced clocks can be.
+pub const DWELL_SECS: u32 = 60;
+
+/// Index of the channel to be on at `fleet_secs`, out of `channels`. Every synced device computes the same one, so the
+/// fleet hops together, moving to the next channel every [`DWELL_SECS`].
+const fn hop_index(fleet_secs: u32, channels: usize) -> usize {
+    (fleet_secs / DWELL_SECS) as usize % channels
+}
+
+/// Frequency to be on at `fleet_secs`: the hop channel for the fleet's time, the first hop channel if this device's
+/// clock has never been synced, or `fixed_hz` if there are none.
+pub fn frequency_hz(hop_channels_hz: &[u32], fixed_hz: u32, fleet_secs: Option<u32>) -> u32 {
+    match (hop_channels_hz, fleet_secs) {
+        ([], _) => fixed_hz,
+        (_, Some(fleet_secs)) => hop_channels_hz[hop_index(fleet_secs, hop_channels_hz.len())],
+        ([first, ..], None) => *first,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNELS_HZ: [u32; 4] = [903_900_000, 904_100_000, 904_300_000, 904_500_000];
+    const FIXED_HZ: u32 = 915_000_000;
+
+    #[test]
+    fn each_channel_lasts_a_dwell_then_wraps_around() {
+        // The fleet stays on a channel for the whole dwell, then moves to the next, wrapping around after the last
+        assert_eq!(hop_index(0, 4), 0);
+        assert_eq!(hop_index(DWELL_SECS - 1, 4), 0);
+        assert_eq!(hop_index(DWELL_SECS, 4), 1);
+        assert_eq!(hop_index(3 * DWELL_SECS, 4), 3);
+        assert_eq!(hop_index(4 * DWELL_SECS, 4), 0);
+        assert_eq!(hop_index(u32::MAX, 1), 0);
+    }
+
+    #[test]
+    fn synced_device_is_on_the_fleets_channel() {
+        assert_eq!(
+            frequency_hz(&CHANNELS_HZ, FIXED_HZ, Some(2 * DWELL_SECS + 5)),
+            CHANNELS_HZ[2]
+        );
+        assert_eq!(
+            frequency_hz(&CHANNELS_HZ, FIXED_HZ, Some(5 * DWELL_SECS)),
+            CHANNELS_HZ[1]
+        );
+    }
+
+    #[test]
+    fn unsynced_device_stays_on_the_first_channel() {
+        assert_eq!(frequency_hz(&CHANNELS_HZ, FIXED_HZ, None), CHANNELS_HZ[0]);
+    }
+
+    #[test]
+    fn without_hop_channels_the_frequency_is_fixed() {
+        assert_eq!(frequency_hz(&[], FIXED_HZ, None), FIXED_HZ);
+        assert_eq!(frequency_hz(&[], FIXED_HZ, Some(DWELL_SECS)), FIXED_HZ);
+    }
+}
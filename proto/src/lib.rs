@@ -0,0 +1,643 @@
+//! Wire format of LoRa packets, before they're encrypted, along with the rest of the logic for sending, receiving, and
+//! storing them that doesn't touch hardware.
+//!
+//! Kept out of the firmware crate and free of hardware dependencies, so it builds for the host as well as the RP2350.
+//!
+//! Every packet is laid out as `MAGIC (MAGIC_WORD_SIZE-bytes) | VERSION (VERSION_SIZE-bytes) | TYPE (PACKET_TYPE_SIZE-bytes) | SENDER (SENDER_ID_SIZE-bytes) | DESTINATION (DESTINATION_SIZE-bytes) | SEQUENCE (SEQUENCE_SIZE-bytes) | TIMESTAMP (TIMESTAMP_SIZE-bytes) | HOPS (HOPS_SIZE-bytes) | FRAGMENT (FRAGMENT_SIZE-bytes) | PAYLOAD`.
+//! Encryption only replaces the payload, so the header can be read from encrypted packets too. Once encrypted, a
+//! `CRC (CRC_SIZE-bytes)` of everything before it is appended, see [`crc_matches`].
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod adaptive_sf;
+pub mod crypto;
+pub mod dedup;
+pub mod duty_cycle;
+pub mod fleet_time;
+pub mod fragment;
+pub mod hopping;
+pub mod listen_before_talk;
+pub mod queue;
+pub mod random;
+pub mod signature;
+pub mod stats;
+pub mod storage;
+
+/// Packets must start with this "magic" word, or they will be ignored
+pub const MAGIC_WORD: u64 = 0x1234_5678_9012_3452;
+pub const MAGIC_WORD_SIZE: usize = size_of_val(&MAGIC_WORD);
+/// Version of the packet format this firmware sends and understands. Must be bumped whenever the layout changes, since
+/// packets with any other version are dropped. Always directly follows the magic word, so every version can read it.
+pub const PROTO_VERSION: u8 = 5;
+pub const VERSION_SIZE: usize = size_of_val(&PROTO_VERSION);
+/// Tells data packets apart from acknowledgements
+pub const PACKET_TYPE_SIZE: usize = 1;
+/// The sender ID follows the packet type in the clear so a packet's origin is known before decrypting it
+pub const SENDER_ID_SIZE: usize = size_of::<u16>();
+/// ID of the only device the packet is meant for, or [`BROADCAST_ID`] for every device. Others still relay it.
+pub const DESTINATION_SIZE: usize = size_of::<u16>();
+/// Sent as the destination of packets meant for every device
+pub const BROADCAST_ID: u16 = u16::MAX;
+/// Each sender numbers its packets in increasing order, so old ones can't be replayed
+pub const SEQUENCE_SIZE: usize = size_of::<u32>();
+
+/// Sequence numbers a device sends with during its `boot_count`th boot count up from here. Each boot gets a range of its
+/// own, so a device numbers its packets above any it sent before rebooting, and they aren't taken for replays.
+pub const fn sequence_start(boot_count: u16) -> u32 {
+    (boot_count as u32) << 16
+}
+/// Fleet time the packet was sent at, in seconds, authenticated with the rest of the header
+pub const TIMESTAMP_SIZE: usize = size_of::<u32>();
+/// Sent in place of a timestamp by devices whose clock has never been synced
+pub const UNSYNCED_TIMESTAMP: u32 = u32::MAX;
+/// Number of times the packet can still be relayed, decremented by each device that relays it
+pub const HOPS_SIZE: usize = size_of::<u8>();
+/// Index of the packet's fragment and the number of fragments in its message
+pub const FRAGMENT_SIZE: usize = 2;
+/// Everything in front of the payload
+pub const HEADER_SIZE: usize = MAGIC_WORD_SIZE
+    + VERSION_SIZE
+    + PACKET_TYPE_SIZE
+    + SENDER_ID_SIZE
+    + DESTINATION_SIZE
+    + SEQUENCE_SIZE
+    + TIMESTAMP_SIZE
+    + HOPS_SIZE
+    + FRAGMENT_SIZE;
+
+/// Longest packet sent or received, encrypted and with its CRC
+pub const MAX_PAYLOAD_LEN: usize = 222;
+/// Data packets' plaintext starts with the kind of message they carry
+pub const KIND_SIZE: usize = 1;
+/// Max length of a message that fits in a single packet
+pub const MAX_MSG_LEN: usize =
+    MAX_PAYLOAD_LEN - crypto::MAC_SIZE - crypto::NONCE_SIZE - CRC_SIZE - HEADER_SIZE - KIND_SIZE;
+// A message of exactly `MAX_MSG_LEN` bytes must fill a packet without overflowing it
+const _: () = assert!(
+    HEADER_SIZE + KIND_SIZE + MAX_MSG_LEN + crypto::MAC_SIZE + crypto::NONCE_SIZE + CRC_SIZE
+        == MAX_PAYLOAD_LEN
+);
+
+/// Follows the encrypted packet, so packets garbled on the air can be told apart from ones sent with another key
+/// before decrypting them. Only a sanity check, anything could have rewritten it along with the packet.
+pub const CRC_SIZE: usize = size_of::<u16>();
+const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+/// CRC of `bytes`, appended to them once they're an encrypted packet.
+pub const fn crc(bytes: &[u8]) -> [u8; CRC_SIZE] {
+    CRC.checksum(bytes).to_le_bytes()
+}
+
+/// Whether the CRC at the end of `packet` matches everything before it.
+pub const fn crc_matches(packet: &[u8]) -> bool {
+    let Some((bytes, crc)) = packet.split_last_chunk::<CRC_SIZE>() else {
+        return false;
+    };
+    CRC.checksum(bytes) == u16::from_le_bytes(*crc)
+}
+
+/// What a packet carries, sent in its authenticated header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PacketType {
+    /// A message, or fragment of one, which the receiver acknowledges once it has the whole message
+    Data = 0,
+    /// Acknowledges a message, carrying the sender ID and sequence number being acknowledged
+    Ack = 1,
+    /// Sent by the fleet's time source, whose timestamp everyone who hears it adopts. Has no payload.
+    TimeSync = 2,
+    /// Sent by a device checking that its radio transmits, see `lora::request_test` in the firmware. Never relayed, and
+    /// dropped by whoever hears it.
+    Test = 3,
+}
+
+impl TryFrom<u8> for PacketType {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Data),
+            1 => Ok(Self::Ack),
+            2 => Ok(Self::TimeSync),
+            3 => Ok(Self::Test),
+            other => Err(other),
+        }
+    }
+}
+
+/// The readable part of a packet, following the magic word
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketHeader {
+    pub version: u8,
+    pub packet_type: PacketType,
+    pub sender_id: u16,
+    /// [`BROADCAST_ID`] if the packet is meant for every device
+    pub destination: u16,
+    pub sequence: u32,
+    /// [`UNSYNCED_TIMESTAMP`] if the sender's clock has never been synced
+    pub timestamp: u32,
+    /// 0 once the packet shouldn't be relayed any further
+    pub hops: u8,
+    pub fragment: u8,
+    pub fragment_count: u8,
+}
+
+impl PacketHeader {
+    /// Whether the packet is laid out in the [`PROTO_VERSION`] this firmware understands. Packets of any other version
+    /// are dropped, since everything after the version could mean something else in them.
+    pub const fn is_supported_version(&self) -> bool {
+        self.version == PROTO_VERSION
+    }
+
+    /// The header the device with `id` passes the packet on with, one hop closer to being dropped, or `None` if it
+    /// shouldn't pass it on: it's out of hops, or it's meant for `id` and has nowhere further to go.
+    pub const fn relayed_by(&self, id: u16) -> Option<Self> {
+        if self.hops == 0 || self.destination == id {
+            return None;
+        }
+        Some(Self {
+            hops: self.hops - 1,
+            ..*self
+        })
+    }
+
+    /// Whether the packet is meant for the device with `id`, either sent to it or to every device.
+    pub const fn is_for(&self, id: u16) -> bool {
+        self.destination == BROADCAST_ID || self.destination == id
+    }
+
+    /// Whether the packet's message should be shown by a device that only shows messages from `allowed_senders`, or
+    /// from everyone if it's empty. Packets are relayed either way.
+    pub fn is_from_allowed(&self, allowed_senders: &[u16]) -> bool {
+        allowed_senders.is_empty() || allowed_senders.contains(&self.sender_id)
+    }
+}
+
+/// A packet's header and the payload following it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet<'a> {
+    pub header: PacketHeader,
+    /// Plaintext before encrypting and after decrypting, otherwise the ciphertext, MAC, and nonce
+    pub payload: &'a [u8],
+}
+
+impl<'a> Packet<'a> {
+    /// Number of bytes [`Packet::serialize_into`] writes
+    pub const fn serialized_len(&self) -> usize {
+        HEADER_SIZE + self.payload.len()
+    }
+
+    /// Writes the magic word, header, and payload to the front of `buf`, returning the number of bytes written.
+    /// Returns `None` if `buf` is too small to hold them.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Option<usize> {
+        let len = self.serialized_len();
+        let buf = buf.get_mut(..len)?;
+        let (magic_word, rest) = buf.split_at_mut(MAGIC_WORD_SIZE);
+        let (version, rest) = rest.split_at_mut(VERSION_SIZE);
+        let (packet_type, rest) = rest.split_at_mut(PACKET_TYPE_SIZE);
+        let (sender_id, rest) = rest.split_at_mut(SENDER_ID_SIZE);
+        let (destination, rest) = rest.split_at_mut(DESTINATION_SIZE);
+        let (sequence, rest) = rest.split_at_mut(SEQUENCE_SIZE);
+        let (timestamp, rest) = rest.split_at_mut(TIMESTAMP_SIZE);
+        let (hops, rest) = rest.split_at_mut(HOPS_SIZE);
+        let (fragment, payload) = rest.split_at_mut(FRAGMENT_SIZE);
+
+        let header = &self.header;
+        magic_word.copy_from_slice(&MAGIC_WORD.to_le_bytes());
+        version[0] = header.version;
+        packet_type[0] = header.packet_type as u8;
+        sender_id.copy_from_slice(&header.sender_id.to_le_bytes());
+        destination.copy_from_slice(&header.destination.to_le_bytes());
+        sequence.copy_from_slice(&header.sequence.to_le_bytes());
+        timestamp.copy_from_slice(&header.timestamp.to_le_bytes());
+        hops[0] = header.hops;
+        fragment.copy_from_slice(&[header.fragment, header.fragment_count]);
+        payload.copy_from_slice(self.payload);
+
+        Some(len)
+    }
+
+    /// Reads a packet written by [`Packet::serialize_into`], with everything after the header as its payload.
+    /// Returns `None` if `buf` is too short, doesn't start with the magic word, or has an unknown packet type.
+    pub fn deserialize_from(buf: &'a [u8]) -> Option<Self> {
+        let (magic_word, rest) = buf.split_at_checked(MAGIC_WORD_SIZE)?;
+        if magic_word != MAGIC_WORD.to_le_bytes() {
+            return None;
+        }
+
+        let (&version, rest) = rest.split_first()?;
+        let (&packet_type, rest) = rest.split_first()?;
+        let (sender_id, rest) = rest.split_first_chunk::<SENDER_ID_SIZE>()?;
+        let (destination, rest) = rest.split_first_chunk::<DESTINATION_SIZE>()?;
+        let (sequence, rest) = rest.split_first_chunk::<SEQUENCE_SIZE>()?;
+        let (timestamp, rest) = rest.split_first_chunk::<TIMESTAMP_SIZE>()?;
+        let (&[hops], rest) = rest.split_first_chunk::<HOPS_SIZE>()?;
+        let (&[fragment, fragment_count], payload) = rest.split_first_chunk::<FRAGMENT_SIZE>()?;
+
+        Some(Self {
+            header: PacketHeader {
+                version,
+                packet_type: PacketType::try_from(packet_type).ok()?,
+                sender_id: u16::from_le_bytes(*sender_id),
+                destination: u16::from_le_bytes(*destination),
+                sequence: u32::from_le_bytes(*sequence),
+                timestamp: u32::from_le_bytes(*timestamp),
+                hops,
+                fragment,
+                fragment_count,
+            },
+            payload,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(crate) const HEADER: PacketHeader = PacketHeader {
+        version: PROTO_VERSION,
+        packet_type: PacketType::Data,
+        sender_id: 0x1A2B,
+        destination: BROADCAST_ID,
+        sequence: 0x0102_0304,
+        timestamp: 1_700_000_000,
+        hops: 3,
+        fragment: 1,
+        fragment_count: 2,
+    };
+
+    #[test]
+    fn crc_is_crc16_ibm_3740() {
+        // The standard check value, the CRC of the ASCII digits 1 to 9
+        assert_eq!(crc(b"123456789"), 0x29B1_u16.to_le_bytes());
+        assert_eq!(crc(b""), 0xFFFF_u16.to_le_bytes());
+    }
+
+    #[test]
+    fn crc_matches_only_an_unchanged_packet() {
+        assert!(crc_matches(b"123456789\xB1\x29"));
+        assert!(!crc_matches(b"123456788\xB1\x29"));
+        assert!(!crc_matches(b"123456789\xB1\x28"));
+        // Too short to have a CRC
+        assert!(!crc_matches(b"\xB1"));
+        assert!(!crc_matches(b""));
+    }
+
+    #[test]
+    fn packet_round_trips() {
+        let packet = Packet {
+            header: HEADER,
+            payload: b"hello",
+        };
+        let mut buf = [0; 64];
+
+        let len = packet.serialize_into(&mut buf).unwrap();
+
+        assert_eq!(len, HEADER_SIZE + 5);
+        assert_eq!(Packet::deserialize_from(&buf[..len]), Some(packet));
+    }
+
+    #[test]
+    fn empty_payload_round_trips() {
+        let packet = Packet {
+            header: HEADER,
+            payload: &[],
+        };
+        let mut buf = [0; HEADER_SIZE];
+
+        assert_eq!(packet.serialize_into(&mut buf), Some(HEADER_SIZE));
+        assert_eq!(Packet::deserialize_from(&buf), Some(packet));
+    }
+
+    #[test]
+    fn every_packet_type_round_trips() {
+        for packet_type in [
+            PacketType::Data,
+            PacketType::Ack,
+            PacketType::TimeSync,
+            PacketType::Test,
+        ] {
+            let packet = Packet {
+                header: PacketHeader {
+                    packet_type,
+                    destination: 0x0042,
+                    timestamp: UNSYNCED_TIMESTAMP,
+                    hops: 0,
+                    fragment: 0,
+                    fragment_count: 1,
+                    ..HEADER
+                },
+                payload: &[0xFF, 0x00, 0x7F],
+            };
+            let mut buf = [0; 64];
+
+            let len = packet.serialize_into(&mut buf).unwrap();
+
+            assert_eq!(len, packet.serialized_len());
+            assert_eq!(Packet::deserialize_from(&buf[..len]), Some(packet));
+        }
+    }
+
+    #[test]
+    fn header_is_laid_out_little_endian_after_magic_word() {
+        let packet = Packet {
+            header: HEADER,
+            payload: b"hi",
+        };
+        let mut buf = [0; HEADER_SIZE + 2];
+        packet.serialize_into(&mut buf).unwrap();
+
+        let (magic_word, rest) = buf.split_at(MAGIC_WORD_SIZE);
+        assert_eq!(magic_word, MAGIC_WORD.to_le_bytes());
+        assert_eq!(
+            rest,
+            [
+                PROTO_VERSION,
+                PacketType::Data as u8,
+                0x2B,
+                0x1A,
+                0xFF,
+                0xFF,
+                0x04,
+                0x03,
+                0x02,
+                0x01,
+                0x00,
+                0xF1,
+                0x53,
+                0x65,
+                3,
+                1,
+                2,
+                b'h',
+                b'i',
+            ]
+        );
+    }
+
+    #[test]
+    fn deserialize_rejects_short_buffer() {
+        let packet = Packet {
+            header: HEADER,
+            payload: &[],
+        };
+        let mut buf = [0; HEADER_SIZE];
+        packet.serialize_into(&mut buf).unwrap();
+
+        for len in 0..HEADER_SIZE {
+            assert_eq!(Packet::deserialize_from(&buf[..len]), None);
+        }
+    }
+
+    #[test]
+    fn deserialize_rejects_bad_magic_word_and_unknown_type() {
+        let packet = Packet {
+            header: HEADER,
+            payload: b"hello",
+        };
+        let mut buf = [0; HEADER_SIZE + 5];
+        packet.serialize_into(&mut buf).unwrap();
+
+        let mut bad_magic = buf;
+        bad_magic[MAGIC_WORD_SIZE - 1] ^= 1;
+        assert_eq!(Packet::deserialize_from(&bad_magic), None);
+
+        let mut bad_type = buf;
+        bad_type[MAGIC_WORD_SIZE + VERSION_SIZE] = 4;
+        assert_eq!(Packet::deserialize_from(&bad_type), None);
+    }
+
+    #[test]
+    fn bogus_version_is_unsupported() {
+        for version in [0, PROTO_VERSION - 1, PROTO_VERSION + 1, u8::MAX] {
+            let packet = Packet {
+                header: PacketHeader { version, ..HEADER },
+                payload: b"garbage to an older firmware",
+            };
+            let mut buf = [0; 64];
+            let len = packet.serialize_into(&mut buf).unwrap();
+
+            // Still readable, so the version can be logged, but not handled
+            let header = Packet::deserialize_from(&buf[..len]).unwrap().header;
+            assert_eq!(header.version, version);
+            assert!(!header.is_supported_version());
+        }
+
+        assert!(HEADER.is_supported_version());
+    }
+
+    /// A device in the middle of a chain, hearing only the devices on either side of it
+    struct Relay {
+        id: u16,
+        recent_packets: dedup::RecentPackets<(u16, u32), 8>,
+    }
+
+    impl Relay {
+        fn new(id: u16) -> Self {
+            Self {
+                id,
+                recent_packets: dedup::RecentPackets::new(embassy_time::Duration::from_secs(30)),
+            }
+        }
+
+        /// Receives `frame` like the firmware does, returning the payload if it's new and the frame to pass it on
+        /// with if it should be.
+        fn hear(
+            &mut self,
+            cipher: &ascon_aead::AsconAead128,
+            rng: &mut random::TestRng,
+            frame: &[u8],
+        ) -> (Option<std::vec::Vec<u8>>, Option<heapless::Vec<u8, 256>>) {
+            assert!(crc_matches(frame));
+            let mut buf =
+                heapless::Vec::<u8, 256>::from_slice(&frame[..frame.len() - CRC_SIZE]).unwrap();
+            crypto::decrypt_in_place(cipher, &mut buf).unwrap();
+            let packet = Packet::deserialize_from(&buf).unwrap();
+
+            let key = (packet.header.sender_id, packet.header.sequence);
+            if packet.header.sender_id == self.id || self.recent_packets.is_duplicate(&key) {
+                return (None, None);
+            }
+            self.recent_packets.insert(key);
+
+            let relayed = packet.header.relayed_by(self.id).map(|header| {
+                let mut relayed = heapless::Vec::new();
+                crypto::build_outgoing(
+                    cipher,
+                    rng,
+                    &mut crypto::RecentNonces::<4>::new(),
+                    &Packet {
+                        header,
+                        payload: packet.payload,
+                    },
+                    &mut relayed,
+                )
+                .unwrap();
+                relayed
+            });
+            (Some(packet.payload.to_vec()), relayed)
+        }
+    }
+
+    #[test]
+    fn message_is_relayed_three_times_down_a_chain() {
+        use ascon_aead::aead::KeyInit;
+
+        let cipher = ascon_aead::AsconAead128::new(ascon_aead::AsconAead128Key::from_slice(
+            b"0123456789abcdef",
+        ));
+        let mut rng = random::TestRng::new(59);
+        // Each hears only the one before it and the one after it. The three in the middle relay it, as many times as
+        // a packet can be, so it reaches the last one four hops from its sender.
+        let mut chain = [0x0A, 0x0B, 0x0C, 0x0D, 0x0E].map(Relay::new);
+        let mut frame = heapless::Vec::<u8, 256>::new();
+        crypto::build_outgoing(
+            &cipher,
+            &mut rng,
+            &mut crypto::RecentNonces::<4>::new(),
+            &Packet {
+                header: PacketHeader {
+                    sender_id: 0x0A,
+                    hops: 3,
+                    fragment: 0,
+                    fragment_count: 1,
+                    ..HEADER
+                },
+                payload: b"HELP",
+            },
+            &mut frame,
+        )
+        .unwrap();
+
+        for hop in 1..chain.len() {
+            let (received, relayed) = chain[hop].hear(&cipher, &mut rng, &frame);
+            assert_eq!(received.as_deref(), Some(&b"HELP"[..]));
+
+            // Heard back by the device it came from, which already has it
+            if let Some(relayed) = &relayed {
+                assert_eq!(
+                    chain[hop - 1].hear(&cipher, &mut rng, relayed),
+                    (None, None)
+                );
+            }
+
+            if hop + 1 < chain.len() {
+                frame = relayed.expect("dropped before the end of the chain");
+            } else {
+                // Out of hops by the last device in the chain
+                assert!(relayed.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn packet_for_relaying_device_isnt_relayed() {
+        let header = PacketHeader {
+            destination: 0x0042,
+            hops: 3,
+            ..HEADER
+        };
+
+        assert_eq!(header.relayed_by(0x0042), None);
+        assert_eq!(
+            header.relayed_by(0x0043),
+            Some(PacketHeader { hops: 2, ..header })
+        );
+        assert_eq!(PacketHeader { hops: 0, ..header }.relayed_by(0x0043), None);
+    }
+
+    #[test]
+    fn broadcast_is_shown_and_relayed() {
+        let header = PacketHeader {
+            destination: BROADCAST_ID,
+            hops: 3,
+            ..HEADER
+        };
+
+        assert!(header.is_for(0x0042));
+        assert_eq!(
+            header.relayed_by(0x0042),
+            Some(PacketHeader { hops: 2, ..header })
+        );
+    }
+
+    #[test]
+    fn packet_for_this_device_is_shown_but_not_relayed() {
+        let header = PacketHeader {
+            destination: 0x0042,
+            hops: 3,
+            ..HEADER
+        };
+
+        assert!(header.is_for(0x0042));
+        assert_eq!(header.relayed_by(0x0042), None);
+    }
+
+    #[test]
+    fn packet_for_another_device_is_relayed_but_not_shown() {
+        let header = PacketHeader {
+            destination: 0x0043,
+            hops: 3,
+            ..HEADER
+        };
+
+        assert!(!header.is_for(0x0042));
+        assert_eq!(
+            header.relayed_by(0x0042),
+            Some(PacketHeader { hops: 2, ..header })
+        );
+    }
+
+    #[test]
+    fn empty_allow_list_allows_everyone() {
+        for sender_id in [0x0000, 0x1A2B, 0xFFFE] {
+            assert!(
+                PacketHeader {
+                    sender_id,
+                    ..HEADER
+                }
+                .is_from_allowed(&[])
+            );
+        }
+    }
+
+    #[test]
+    fn allow_list_only_allows_listed_senders() {
+        let allowed_senders = [0x1A2B, 0x3C4D];
+
+        assert!(
+            PacketHeader {
+                sender_id: 0x1A2B,
+                ..HEADER
+            }
+            .is_from_allowed(&allowed_senders)
+        );
+        assert!(
+            PacketHeader {
+                sender_id: 0x3C4D,
+                ..HEADER
+            }
+            .is_from_allowed(&allowed_senders)
+        );
+
+        let unlisted = PacketHeader {
+            sender_id: 0x5E6F,
+            ..HEADER
+        };
+        assert!(!unlisted.is_from_allowed(&allowed_senders));
+        // Still passed on for the rest of the fleet
+        assert!(unlisted.relayed_by(0x0042).is_some());
+    }
+
+    #[test]
+    fn serialize_rejects_small_buffer() {
+        let packet = Packet {
+            header: HEADER,
+            payload: b"hello",
+        };
+        let mut buf = [0; HEADER_SIZE + 4];
+
+        assert_eq!(packet.serialize_into(&mut buf), None);
+    }
+}
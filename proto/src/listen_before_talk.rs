@@ -0,0 +1,87 @@
+//! Checking the channel is still clear right before sending, after a random wait, so devices that decided to send at
+//! the same moment don't all go at once.
+
+use core::ops::Range;
+
+use rand_core::RngCore;
+
+use crate::random::random_u32_in_range;
+
+/// Milliseconds to wait before each check of the channel
+pub const BACKOFF_RANGE_MS: Range<u32> = 5..40;
+/// Times the channel is checked right before sending before giving up and holding the send off
+pub const MAX_ATTEMPTS: u8 = 3;
+
+/// Hands out a random wait before each check of the channel, until it's been checked [`MAX_ATTEMPTS`] times.
+pub struct ListenBeforeTalk {
+    attempts: u8,
+}
+
+impl ListenBeforeTalk {
+    pub const fn new() -> Self {
+        Self { attempts: 0 }
+    }
+
+    /// Milliseconds to wait before checking the channel again, or `None` once it's been checked [`MAX_ATTEMPTS`] times
+    /// and the send should be held off instead.
+    pub fn next_backoff_ms(&mut self, rng: &mut impl RngCore) -> Option<u32> {
+        if self.attempts >= MAX_ATTEMPTS {
+            return None;
+        }
+
+        self.attempts += 1;
+        Some(random_u32_in_range(rng, BACKOFF_RANGE_MS))
+    }
+
+    /// Number of checks handed out so far
+    pub const fn attempts(&self) -> u8 {
+        self.attempts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::random::TestRng;
+
+    #[test]
+    fn backs_off_up_to_the_cap_then_gives_up() {
+        let mut rng = TestRng::new(3);
+        let mut lbt = ListenBeforeTalk::new();
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let backoff_ms = lbt.next_backoff_ms(&mut rng).unwrap();
+            assert!(BACKOFF_RANGE_MS.contains(&backoff_ms));
+            assert_eq!(lbt.attempts(), attempt);
+        }
+
+        assert_eq!(lbt.next_backoff_ms(&mut rng), None);
+        // Stays given up rather than starting over
+        assert_eq!(lbt.next_backoff_ms(&mut rng), None);
+        assert_eq!(lbt.attempts(), MAX_ATTEMPTS);
+    }
+
+    #[test]
+    fn backoffs_are_random() {
+        let mut rng = TestRng::new(4);
+        let mut lbt = ListenBeforeTalk::new();
+
+        let first = lbt.next_backoff_ms(&mut rng).unwrap();
+        let rest = [
+            lbt.next_backoff_ms(&mut rng).unwrap(),
+            lbt.next_backoff_ms(&mut rng).unwrap(),
+        ];
+        assert!(rest.iter().any(|&backoff_ms| backoff_ms != first));
+    }
+
+    #[test]
+    fn each_send_starts_from_the_first_attempt() {
+        let mut rng = TestRng::new(5);
+        let mut lbt = ListenBeforeTalk::new();
+        while lbt.next_backoff_ms(&mut rng).is_some() {}
+
+        let mut next_send = ListenBeforeTalk::new();
+        assert!(next_send.next_backoff_ms(&mut rng).is_some());
+        assert_eq!(next_send.attempts(), 1);
+    }
+}
@@ -0,0 +1,166 @@
+//! Ordering outgoing messages by how urgently they need to go out.
+
+use heapless::Vec;
+
+/// How urgently an outgoing message needs to go out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Packets relayed for other devices, so they never hold up this device's own messages
+    Relay,
+    Normal,
+    /// Calls for help, sent before anything else waiting
+    Emergency,
+}
+
+/// Fixed-capacity queue handing out the most urgent item first, and the oldest first among equally urgent ones.
+///
+/// Pushing never blocks. Once it's full, the newest of the least urgent items is dropped to make room, or the pushed
+/// item itself if nothing waiting is less urgent than it.
+pub struct PriorityQueue<T, const N: usize> {
+    /// Oldest first
+    items: Vec<(Priority, T), N>,
+}
+
+impl<T, const N: usize> PriorityQueue<T, N> {
+    pub const fn new() -> Self {
+        Self { items: Vec::new() }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Adds `item`, returning whichever item had to be dropped if the queue was full.
+    pub fn push(&mut self, priority: Priority, item: T) -> Option<T> {
+        let mut dropped = None;
+        if self.items.is_full() {
+            // Newest of the least urgent, since the oldest have been waiting longest
+            let Some((index, &(lowest, _))) = self
+                .items
+                .iter()
+                .enumerate()
+                .rev()
+                .min_by_key(|(_, (priority, _))| *priority)
+            else {
+                // No room at all
+                return Some(item);
+            };
+            if lowest >= priority {
+                return Some(item);
+            }
+            dropped = Some(self.items.remove(index).1);
+        }
+
+        // Just made room if it was full
+        let _ = self.items.push((priority, item));
+        dropped
+    }
+
+    /// Every item waiting, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    /// The item [`Self::pop`] would hand out next with the same `ready`.
+    pub fn peek(&self, ready: impl Fn(Priority, &T) -> bool) -> Option<&T> {
+        self.next_index(ready).map(|index| &self.items[index].1)
+    }
+
+    /// Takes the most urgent item out of the queue, skipping any that `ready` says can't go yet.
+    pub fn pop(&mut self, ready: impl Fn(Priority, &T) -> bool) -> Option<T> {
+        self.next_index(ready)
+            .map(|index| self.items.remove(index).1)
+    }
+
+    fn next_index(&self, ready: impl Fn(Priority, &T) -> bool) -> Option<usize> {
+        // `max_by_key` picks the last of equal keys, so iterate newest first to get the oldest
+        self.items
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, (priority, item))| ready(*priority, item))
+            .max_by_key(|(_, (priority, _))| *priority)
+            .map(|(index, _)| index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pop_all<const N: usize>(queue: &mut PriorityQueue<u8, N>) -> std::vec::Vec<u8> {
+        core::iter::from_fn(|| queue.pop(|_, _| true)).collect()
+    }
+
+    #[test]
+    fn most_urgent_goes_first_then_oldest() {
+        let mut queue = PriorityQueue::<u8, 8>::new();
+        assert!(queue.is_empty());
+
+        assert_eq!(queue.push(Priority::Normal, 1), None);
+        assert_eq!(queue.push(Priority::Relay, 2), None);
+        assert_eq!(queue.push(Priority::Normal, 3), None);
+        assert_eq!(queue.push(Priority::Emergency, 4), None);
+        assert_eq!(queue.push(Priority::Relay, 5), None);
+        assert_eq!(queue.push(Priority::Emergency, 6), None);
+
+        assert_eq!(pop_all(&mut queue), [4, 6, 1, 3, 2, 5]);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn peek_matches_pop() {
+        let mut queue = PriorityQueue::<u8, 4>::new();
+        queue.push(Priority::Normal, 1);
+        queue.push(Priority::Emergency, 2);
+
+        assert_eq!(queue.peek(|_, _| true), Some(&2));
+        assert_eq!(queue.pop(|_, _| true), Some(2));
+        assert_eq!(queue.peek(|_, _| true), Some(&1));
+    }
+
+    #[test]
+    fn items_that_arent_ready_are_skipped() {
+        let mut queue = PriorityQueue::<u8, 4>::new();
+        queue.push(Priority::Emergency, 1);
+        queue.push(Priority::Normal, 2);
+
+        assert_eq!(queue.pop(|_, &item| item != 1), Some(2));
+        assert_eq!(queue.pop(|_, &item| item != 1), None);
+        assert_eq!(queue.pop(|_, _| true), Some(1));
+    }
+
+    #[test]
+    fn full_queue_drops_newest_of_least_urgent() {
+        let mut queue = PriorityQueue::<u8, 4>::new();
+        queue.push(Priority::Normal, 1);
+        queue.push(Priority::Relay, 2);
+        queue.push(Priority::Relay, 3);
+        queue.push(Priority::Normal, 4);
+
+        assert_eq!(queue.push(Priority::Emergency, 5), Some(3));
+        assert_eq!(queue.push(Priority::Normal, 6), Some(2));
+
+        assert_eq!(pop_all(&mut queue), [5, 1, 4, 6]);
+    }
+
+    #[test]
+    fn full_queue_drops_pushed_item_if_nothing_is_less_urgent() {
+        let mut queue = PriorityQueue::<u8, 2>::new();
+        queue.push(Priority::Emergency, 1);
+        queue.push(Priority::Normal, 2);
+
+        assert_eq!(queue.push(Priority::Normal, 3), Some(3));
+        assert_eq!(queue.push(Priority::Relay, 4), Some(4));
+
+        assert_eq!(pop_all(&mut queue), [1, 2]);
+    }
+
+    #[test]
+    fn zero_capacity_queue_drops_everything() {
+        let mut queue = PriorityQueue::<u8, 0>::new();
+
+        assert_eq!(queue.push(Priority::Emergency, 1), Some(1));
+        assert!(queue.is_empty());
+    }
+}
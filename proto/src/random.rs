@@ -0,0 +1,208 @@
+//! Random helpers generic over any [`RngCore`]. They're only as unpredictable as the RNG they're given, and add no
+//! cryptographic strength of their own.
+
+use core::ops::{Range, RangeInclusive};
+
+use rand_core::RngCore;
+
+/// Returns a uniformly distributed random number in `range`, or `range.start` if it's empty.
+///
+/// Uses Lemire's multiply-and-reject method, so every value is equally likely no matter the size of the range.
+#[allow(clippy::cast_possible_truncation)]
+pub fn random_u32_in_range(rng: &mut impl RngCore, range: Range<u32>) -> u32 {
+    if range.is_empty() {
+        return range.start;
+    }
+
+    let span = range.end - range.start;
+    let mut product = u64::from(rng.next_u32()) * u64::from(span);
+    // Products whose low half is below `2^32 % span` would make some results more likely than others and are
+    // rejected. That's always below `span`, so the division can usually be skipped.
+    if (product as u32) < span {
+        let threshold = span.wrapping_neg() % span;
+        while (product as u32) < threshold {
+            product = u64::from(rng.next_u32()) * u64::from(span);
+        }
+    }
+
+    range.start + (product >> 32) as u32
+}
+
+/// Returns a uniformly distributed random number in `range`, which can be its end, or its start if it's empty.
+pub fn random_u32_in_range_inclusive(rng: &mut impl RngCore, range: RangeInclusive<u32>) -> u32 {
+    let (start, end) = range.into_inner();
+    match end.checked_add(1) {
+        Some(end) => random_u32_in_range(rng, start..end),
+        // Every `u32` is in range
+        None if start == 0 => rng.next_u32(),
+        None => random_u32_in_range(rng, start - 1..end) + 1,
+    }
+}
+
+/// Returns `N` random bytes.
+pub fn fill_random<const N: usize>(rng: &mut impl RngCore) -> [u8; N] {
+    let mut bytes = [0; N];
+    rng.fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Deterministic RNG for tests, so failures can be reproduced. Nowhere near good enough for anything else.
+#[cfg(test)]
+pub(crate) struct TestRng(u64);
+
+#[cfg(test)]
+impl TestRng {
+    pub(crate) const fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+}
+
+#[cfg(test)]
+impl RngCore for TestRng {
+    // SplitMix64
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest);
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the given values in order, to drive the RNG down a particular path
+    struct SequenceRng<'a>(&'a [u32]);
+
+    impl RngCore for SequenceRng<'_> {
+        fn next_u32(&mut self) -> u32 {
+            let (&next, rest) = self.0.split_first().expect("ran out of values");
+            self.0 = rest;
+            next
+        }
+
+        fn next_u64(&mut self) -> u64 {
+            rand_core::impls::next_u64_via_u32(self)
+        }
+
+        fn fill_bytes(&mut self, dest: &mut [u8]) {
+            rand_core::impls::fill_bytes_via_next(self, dest);
+        }
+
+        fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+            self.fill_bytes(dest);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn range_is_roughly_flat() {
+        const BUCKETS: u32 = 10;
+        const SAMPLES: u32 = 100_000;
+        let mut rng = TestRng::new(21);
+        let mut counts = [0_u32; BUCKETS as usize];
+
+        for _ in 0..SAMPLES {
+            counts[random_u32_in_range(&mut rng, 0..BUCKETS) as usize] += 1;
+        }
+
+        // Each bucket expects 10,000, and a fair RNG stays well within 5% of that
+        let expected = SAMPLES / BUCKETS;
+        for (value, &count) in counts.iter().enumerate() {
+            assert!(
+                count.abs_diff(expected) < expected / 20,
+                "{value} came up {count} times, expected about {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn biased_samples_are_redrawn() {
+        // 2^32 % 3 is 1, so a product with a low half of 0 would make 0 more likely than 1 or 2
+        let mut rng = SequenceRng(&[0, u32::MAX]);
+
+        assert_eq!(random_u32_in_range(&mut rng, 0..3), 2);
+        assert!(rng.0.is_empty());
+    }
+
+    #[test]
+    fn offset_range_is_shifted_not_rescaled() {
+        let mut rng = SequenceRng(&[0, u32::MAX]);
+
+        assert_eq!(random_u32_in_range(&mut rng, 100..101), 100);
+        assert_eq!(random_u32_in_range(&mut rng, 100..200), 199);
+    }
+
+    #[test]
+    fn range_stays_in_bounds() {
+        let mut rng = TestRng::new(22);
+
+        for range in [0..1, 5..6, 10..20, 1..u32::MAX, u32::MAX - 3..u32::MAX] {
+            for _ in 0..1000 {
+                assert!(range.contains(&random_u32_in_range(&mut rng, range.clone())));
+            }
+        }
+    }
+
+    #[test]
+    fn inclusive_range_stays_in_bounds_and_reaches_its_end() {
+        let mut rng = TestRng::new(22);
+
+        for range in [1..=5, 7..=7, 0..=1, u32::MAX - 3..=u32::MAX] {
+            let mut reached_end = false;
+            for _ in 0..1000 {
+                let value = random_u32_in_range_inclusive(&mut rng, range.clone());
+                assert!(range.contains(&value));
+                reached_end |= value == *range.end();
+            }
+            assert!(reached_end, "never reached the end of {range:?}");
+        }
+    }
+
+    #[test]
+    fn inclusive_range_reaches_u32_max() {
+        let mut rng = SequenceRng(&[u32::MAX, u32::MAX]);
+
+        assert_eq!(
+            random_u32_in_range_inclusive(&mut rng, 0..=u32::MAX),
+            u32::MAX
+        );
+        assert_eq!(
+            random_u32_in_range_inclusive(&mut rng, 1..=u32::MAX),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn fill_random_fills_every_byte() {
+        let mut rng = SequenceRng(&[0x0403_0201, 0x0807_0605]);
+
+        assert_eq!(fill_random::<6>(&mut rng), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn empty_range_returns_its_start_without_drawing() {
+        let mut rng = SequenceRng(&[]);
+
+        let (start, end) = (5, 4);
+
+        assert_eq!(random_u32_in_range(&mut rng, start..start), start);
+        assert_eq!(random_u32_in_range(&mut rng, start..end), start);
+        assert_eq!(random_u32_in_range_inclusive(&mut rng, start..=end), start);
+    }
+}
@@ -0,0 +1,89 @@
+//! Signatures put in front of the text messages a device sends, so people reading them can tell who it was from
+//! without knowing sender IDs.
+
+use heapless::Vec;
+
+/// Goes between a device's signature and the message it signs
+pub const SEPARATOR: &str = ": ";
+
+/// `text` with `signature` in front of it, if there is one. The end of `text` is cut off, on a char boundary, if they
+/// don't fit in `max_len` bytes together. The signature always has to fit.
+pub fn signed<const N: usize>(signature: Option<&str>, text: &str, max_len: usize) -> Vec<u8, N> {
+    let mut data = Vec::new();
+    if let Some(signature) = signature {
+        data.extend_from_slice(signature.as_bytes()).unwrap();
+        data.extend_from_slice(SEPARATOR.as_bytes()).unwrap();
+    }
+    let room = max_len.min(N) - data.len();
+    let end = if text.len() <= room {
+        text.len()
+    } else {
+        log::warn!("Message too long to sign, cutting it to {room} bytes");
+        (0..=room)
+            .rev()
+            .find(|&end| text.is_char_boundary(end))
+            .unwrap_or(0)
+    };
+    data.extend_from_slice(&text.as_bytes()[..end]).unwrap();
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Room for "Green One: " and 9 bytes of text
+    const MAX_LEN: usize = 20;
+
+    fn sign(signature: Option<&str>, text: &str) -> Vec<u8, 32> {
+        signed(signature, text, MAX_LEN)
+    }
+
+    #[test]
+    fn unsigned_text_is_left_alone() {
+        assert_eq!(sign(None, "All clear"), b"All clear"[..]);
+    }
+
+    #[test]
+    fn signature_goes_in_front() {
+        assert_eq!(sign(Some("Green One"), "Hi"), b"Green One: Hi"[..]);
+    }
+
+    #[test]
+    fn text_that_just_fits_is_kept_whole() {
+        let data = sign(Some("Green One"), "123456789");
+
+        assert_eq!(data.len(), MAX_LEN);
+        assert_eq!(data, b"Green One: 123456789"[..]);
+    }
+
+    #[test]
+    fn text_one_byte_over_loses_its_last_byte() {
+        let data = sign(Some("Green One"), "1234567890");
+
+        assert_eq!(data, b"Green One: 123456789"[..]);
+    }
+
+    #[test]
+    fn char_crossing_the_limit_is_dropped_whole() {
+        // 'é' is two bytes, starting at the last byte that fits
+        let data = sign(Some("Green One"), "12345678é");
+
+        assert_eq!(data, b"Green One: 12345678"[..]);
+        assert!(core::str::from_utf8(&data).is_ok());
+    }
+
+    #[test]
+    fn unsigned_text_is_cut_at_the_limit() {
+        let data = sign(None, "This message is far too long");
+
+        assert_eq!(data, b"This message is far "[..]);
+    }
+
+    #[test]
+    fn limit_is_capped_by_capacity() {
+        let data: Vec<u8, 8> = signed(None, "123456789", MAX_LEN);
+
+        assert_eq!(data, b"12345678"[..]);
+    }
+}
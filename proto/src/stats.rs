@@ -0,0 +1,89 @@
+//! Counters of what a device's radio has done, and how they're laid out when read over BLE.
+
+/// What a device's radio has done since boot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketStats {
+    /// Packets transmitted, acks and each fragment included
+    pub sent: u32,
+    /// Packets received that decrypted successfully
+    pub received: u32,
+    /// Packets received that failed to decrypt, from devices with another key or tampered with
+    pub decrypt_failures: u32,
+    /// Sends held off because the channel was busy, when checking for activity or right before sending
+    pub busy_deferrals: u32,
+    /// Packets that failed to decrypt since the last one that didn't. Many in a row usually means this device was
+    /// given a different key than the rest of the fleet.
+    pub consecutive_decrypt_failures: u32,
+    /// Packets received that failed their CRC, garbled on the air. Never decrypted, so they aren't counted as decrypt
+    /// failures.
+    pub crc_failures: u32,
+}
+
+impl PacketStats {
+    pub const SER_SIZE: usize = 6 * size_of::<u32>();
+
+    pub const fn new() -> Self {
+        Self {
+            sent: 0,
+            received: 0,
+            decrypt_failures: 0,
+            busy_deferrals: 0,
+            consecutive_decrypt_failures: 0,
+            crc_failures: 0,
+        }
+    }
+
+    /// Each counter as a little endian `u32`, in the order they're declared.
+    pub fn to_bytes(&self) -> [u8; Self::SER_SIZE] {
+        let mut bytes = [0; Self::SER_SIZE];
+        for (chunk, counter) in bytes.chunks_exact_mut(size_of::<u32>()).zip([
+            self.sent,
+            self.received,
+            self.decrypt_failures,
+            self.busy_deferrals,
+            self.consecutive_decrypt_failures,
+            self.crc_failures,
+        ]) {
+            chunk.copy_from_slice(&counter.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn new_stats_are_zero() {
+        assert_eq!(PacketStats::new().to_bytes(), [0; PacketStats::SER_SIZE]);
+    }
+
+    #[test]
+    fn counters_are_laid_out_little_endian_in_order() {
+        let stats = PacketStats {
+            sent: 0x0403_0201,
+            received: 0x0807_0605,
+            decrypt_failures: 0x0C0B_0A09,
+            busy_deferrals: 0x100F_0E0D,
+            consecutive_decrypt_failures: 0x1413_1211,
+            crc_failures: 0x1817_1615,
+        };
+
+        let bytes = stats.to_bytes();
+        assert_eq!(PacketStats::SER_SIZE, 24);
+        assert_eq!(
+            bytes,
+            [
+                0x01, 0x02, 0x03, 0x04, // sent
+                0x05, 0x06, 0x07, 0x08, // received
+                0x09, 0x0A, 0x0B, 0x0C, // decrypt failures
+                0x0D, 0x0E, 0x0F, 0x10, // busy deferrals
+                0x11, 0x12, 0x13, 0x14, // consecutive decrypt failures
+                0x15, 0x16, 0x17, 0x18, // CRC failures
+            ]
+        );
+        // Appended last, so the counters before it kept their offsets
+        assert_eq!(bytes[20..24], 0x1817_1615_u32.to_le_bytes());
+    }
+}
@@ -0,0 +1,1316 @@
+//! How settings and the message history are laid out in flash. Every layout settings were ever stored in can still be
+//! read, so they survive firmware updates.
+
+use core::ops::Range;
+
+use embedded_storage_async::nor_flash::NorFlash;
+use sequential_storage::{
+    cache::NoCache,
+    map::{SerializationError, Value},
+};
+
+/// Stored in place of a station when none has been picked
+pub const NO_STATION: u8 = u8::MAX;
+/// Max number of bytes in a device's name
+pub const NAME_CAPACITY: usize = 20;
+/// Max number of bytes in the signature put in front of a device's messages
+pub const SIGNATURE_CAPACITY: usize = 16;
+/// Max number of senders on the allow list
+pub const ALLOW_LIST_CAPACITY: usize = 16;
+/// Max number of frequencies the radio hops between
+pub const HOP_CHANNELS_CAPACITY: usize = 8;
+/// Layout [`StoredInfo`] is serialized in, written as its first byte. Bump it whenever fields change, and migrate the
+/// old layout in [`StoredRecord::deserialize_from`].
+const FORMAT_VERSION: u8 = 13;
+/// Every field of [`FORMAT_VERSION`] but the hop channels, from before the radio could hop between frequencies
+const PRE_HOPPING_FORMAT_VERSION: u8 = 12;
+/// Every field of [`PRE_HOPPING_FORMAT_VERSION`] but the signature, from before messages could be signed
+const PRE_SIGNATURE_FORMAT_VERSION: u8 = 11;
+/// Every field of [`PRE_SIGNATURE_FORMAT_VERSION`] but the advertising interval and BLE TX power, from before they could
+/// be changed
+const PRE_ADVERTISING_FORMAT_VERSION: u8 = 10;
+/// Every field of [`PRE_ADVERTISING_FORMAT_VERSION`] but `adaptive_sf`, from before the spreading factor could step up
+const PRE_ADAPTIVE_SF_FORMAT_VERSION: u8 = 9;
+/// Every field of [`PRE_ADAPTIVE_SF_FORMAT_VERSION`] but the message expiry, from before old messages were taken down
+const PRE_MESSAGE_EXPIRY_FORMAT_VERSION: u8 = 8;
+/// Every field of [`PRE_MESSAGE_EXPIRY_FORMAT_VERSION`] but the BLE idle timeout, from before idle connections were dropped
+const PRE_IDLE_TIMEOUT_FORMAT_VERSION: u8 = 7;
+/// Every field of [`PRE_IDLE_TIMEOUT_FORMAT_VERSION`] but the battery thresholds, from before the device saved its battery
+const PRE_BATTERY_FORMAT_VERSION: u8 = 6;
+/// Every field of [`PRE_BATTERY_FORMAT_VERSION`] but the RX timeout, from before it could be changed
+const PRE_RX_TIMEOUT_FORMAT_VERSION: u8 = 5;
+/// Every field of [`PRE_RX_TIMEOUT_FORMAT_VERSION`] but the LoRa preset, from before there were presets
+const PRE_PRESET_FORMAT_VERSION: u8 = 4;
+/// Every field of [`PRE_PRESET_FORMAT_VERSION`] but the allow list, from before there was one
+const PRE_ALLOW_LIST_FORMAT_VERSION: u8 = 3;
+/// Every field of [`PRE_ALLOW_LIST_FORMAT_VERSION`] but `relay`, from before devices relayed packets
+const PRE_RELAY_FORMAT_VERSION: u8 = 2;
+/// Same fields as [`PRE_RELAY_FORMAT_VERSION`], but without the CRC at the end
+const UNCHECKED_FORMAT_VERSION: u8 = 1;
+/// Size of the first layout, which was only the encryption key and had no version
+const V0_SER_SIZE: usize = size_of::<u128>();
+/// Checks stored records weren't corrupted in flash
+const CRC: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+
+/// Why something couldn't be loaded from or stored in flash
+#[derive(Debug)]
+pub enum StorageError<E> {
+    /// Nothing has been stored yet, or it was erased
+    NotFound,
+    /// What's stored failed its CRC check, is in a layout this firmware doesn't know, or flash was left half written
+    Corrupt,
+    /// The flash itself failed, or there's no room left in it
+    Flash(sequential_storage::Error<E>),
+}
+
+impl<E> From<sequential_storage::Error<E>> for StorageError<E> {
+    fn from(err: sequential_storage::Error<E>) -> Self {
+        match err {
+            sequential_storage::Error::Corrupted { .. }
+            | sequential_storage::Error::SerializationError(
+                SerializationError::InvalidData | SerializationError::InvalidFormat,
+            ) => Self::Corrupt,
+            err => Self::Flash(err),
+        }
+    }
+}
+
+/// Stored settings, in any layout they've been written in
+#[derive(Debug, Clone)]
+pub enum StoredRecord {
+    /// Only the encryption key, from before the layout was versioned
+    V0 { encryption_key: u128 },
+    /// [`FORMAT_VERSION`]
+    Current(StoredInfo),
+}
+
+/// The device's settings as they're laid out in flash. Fields newer than the layout a record was written in are
+/// filled in from the defaults it's read with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredInfo {
+    pub encryption_key: u128,
+    pub boot_count: u16,
+    pub region: u8,
+    pub frequency_hz: u32,
+    pub tx_power_dbm: i8,
+    pub transmit_times: u8,
+    pub listen_mode: u8,
+    /// [`NO_STATION`] if none has been picked
+    pub station: u8,
+    pub debounce_ms: u16,
+    pub brightness: u8,
+    pub screen_timeout_secs: u16,
+    /// UTF-8, padded with zeroes after `name_len` bytes
+    pub name: [u8; NAME_CAPACITY],
+    /// 0 if the device has no name
+    pub name_len: u8,
+    pub relay: bool,
+    /// Only the first `allowed_len` are on the list
+    pub allowed_senders: [u16; ALLOW_LIST_CAPACITY],
+    pub allowed_len: u8,
+    pub lora_preset: u8,
+    pub rx_timeout_symbols: u16,
+    pub low_battery_mv: u16,
+    pub critical_battery_mv: u16,
+    pub idle_timeout_secs: u16,
+    pub message_expiry_mins: u16,
+    pub adaptive_sf: bool,
+    pub adv_interval_ms: u16,
+    pub ble_tx_power_dbm: i8,
+    /// UTF-8, padded with zeroes after `signature_len` bytes
+    pub signature: [u8; SIGNATURE_CAPACITY],
+    /// 0 if messages aren't signed
+    pub signature_len: u8,
+    /// Only the first `hop_channels_len` are hopped between
+    pub hop_channels_hz: [u32; HOP_CHANNELS_CAPACITY],
+    pub hop_channels_len: u8,
+}
+
+impl StoredInfo {
+    /// Size of the fields in [`PRE_RELAY_FORMAT_VERSION`], without the CRC after them
+    const PRE_RELAY_FIELDS_SIZE: usize = size_of::<u8>()
+        + size_of::<u128>()
+        + size_of::<u16>()
+        + size_of::<u8>()
+        + size_of::<u32>()
+        + size_of::<i8>()
+        + size_of::<u8>()
+        + size_of::<u8>()
+        + size_of::<u8>()
+        + size_of::<u16>()
+        + size_of::<u8>()
+        + size_of::<u16>()
+        + NAME_CAPACITY
+        + size_of::<u8>();
+    /// Size of the fields in [`PRE_ALLOW_LIST_FORMAT_VERSION`], without the CRC after them
+    const PRE_ALLOW_LIST_FIELDS_SIZE: usize = Self::PRE_RELAY_FIELDS_SIZE + size_of::<u8>();
+    /// Size of the fields in [`PRE_PRESET_FORMAT_VERSION`], without the CRC after them
+    const PRE_PRESET_FIELDS_SIZE: usize =
+        Self::PRE_ALLOW_LIST_FIELDS_SIZE + ALLOW_LIST_CAPACITY * size_of::<u16>() + size_of::<u8>();
+    /// Size of the fields in [`PRE_RX_TIMEOUT_FORMAT_VERSION`], without the CRC after them
+    const PRE_RX_TIMEOUT_FIELDS_SIZE: usize = Self::PRE_PRESET_FIELDS_SIZE + size_of::<u8>();
+    /// Size of the fields in [`PRE_BATTERY_FORMAT_VERSION`], without the CRC after them
+    const PRE_BATTERY_FIELDS_SIZE: usize = Self::PRE_RX_TIMEOUT_FIELDS_SIZE + size_of::<u16>();
+    /// Size of the fields in [`PRE_IDLE_TIMEOUT_FORMAT_VERSION`], without the CRC after them
+    const PRE_IDLE_TIMEOUT_FIELDS_SIZE: usize =
+        Self::PRE_BATTERY_FIELDS_SIZE + 2 * size_of::<u16>();
+    /// Size of the fields in [`PRE_MESSAGE_EXPIRY_FORMAT_VERSION`], without the CRC after them
+    const PRE_MESSAGE_EXPIRY_FIELDS_SIZE: usize =
+        Self::PRE_IDLE_TIMEOUT_FIELDS_SIZE + size_of::<u16>();
+    /// Size of the fields in [`PRE_ADAPTIVE_SF_FORMAT_VERSION`], without the CRC after them
+    const PRE_ADAPTIVE_SF_FIELDS_SIZE: usize =
+        Self::PRE_MESSAGE_EXPIRY_FIELDS_SIZE + size_of::<u16>();
+    /// Size of the fields in [`PRE_ADVERTISING_FORMAT_VERSION`], without the CRC after them
+    const PRE_ADVERTISING_FIELDS_SIZE: usize = Self::PRE_ADAPTIVE_SF_FIELDS_SIZE + size_of::<u8>();
+    /// Size of the fields in [`PRE_SIGNATURE_FORMAT_VERSION`], without the CRC after them
+    const PRE_SIGNATURE_FIELDS_SIZE: usize =
+        Self::PRE_ADVERTISING_FIELDS_SIZE + size_of::<u16>() + size_of::<i8>();
+    /// Size of the fields in [`PRE_HOPPING_FORMAT_VERSION`], without the CRC after them
+    const PRE_HOPPING_FIELDS_SIZE: usize =
+        Self::PRE_SIGNATURE_FIELDS_SIZE + SIGNATURE_CAPACITY + size_of::<u8>();
+    /// Size of the fields, without the CRC after them
+    const FIELDS_SIZE: usize =
+        Self::PRE_HOPPING_FIELDS_SIZE + HOP_CHANNELS_CAPACITY * size_of::<u32>() + size_of::<u8>();
+    pub const SER_SIZE: usize = Self::FIELDS_SIZE + size_of::<u32>();
+}
+
+impl StoredRecord {
+    pub fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        match self {
+            Self::V0 { encryption_key } => {
+                let bytes = buffer
+                    .get_mut(..V0_SER_SIZE)
+                    .ok_or(SerializationError::BufferTooSmall)?;
+                bytes.copy_from_slice(&encryption_key.to_le_bytes());
+                Ok(V0_SER_SIZE)
+            }
+            Self::Current(stored) => stored.serialize_into(buffer),
+        }
+    }
+
+    /// The settings in the record. Fields added since the layout was versioned are taken from `defaults`, and the
+    /// record is upgraded the next time it's stored.
+    pub fn into_stored_info(self, defaults: &StoredInfo) -> StoredInfo {
+        match self {
+            Self::V0 { encryption_key } => {
+                log::info!("Migrating unversioned stored info");
+                StoredInfo {
+                    encryption_key,
+                    ..defaults.clone()
+                }
+            }
+            Self::Current(stored) => stored,
+        }
+    }
+
+    /// Reads a record in the current layout or any older one. Fields older layouts don't have are taken from
+    /// `defaults`, and the record is upgraded the next time it's stored.
+    pub fn deserialize_from(
+        buffer: &[u8],
+        defaults: &StoredInfo,
+    ) -> Result<Self, SerializationError> {
+        // The unversioned layout is told apart by its length, since its first byte is part of the key
+        if buffer.len() == V0_SER_SIZE {
+            return Ok(Self::V0 {
+                encryption_key: u128::from_le_bytes(buffer.try_into().unwrap()),
+            });
+        }
+
+        match buffer.first() {
+            Some(&FORMAT_VERSION) => {
+                StoredInfo::deserialize_checked(buffer, StoredInfo::FIELDS_SIZE, defaults)
+                    .map(Self::Current)
+            }
+            Some(&PRE_HOPPING_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_HOPPING_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_SIGNATURE_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_SIGNATURE_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_ADVERTISING_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_ADVERTISING_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_ADAPTIVE_SF_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_ADAPTIVE_SF_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_MESSAGE_EXPIRY_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_MESSAGE_EXPIRY_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_IDLE_TIMEOUT_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_IDLE_TIMEOUT_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_BATTERY_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_BATTERY_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_RX_TIMEOUT_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_RX_TIMEOUT_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_PRESET_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_PRESET_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_ALLOW_LIST_FORMAT_VERSION) => StoredInfo::deserialize_checked(
+                buffer,
+                StoredInfo::PRE_ALLOW_LIST_FIELDS_SIZE,
+                defaults,
+            )
+            .map(Self::Current),
+            Some(&PRE_RELAY_FORMAT_VERSION) => {
+                StoredInfo::deserialize_checked(buffer, StoredInfo::PRE_RELAY_FIELDS_SIZE, defaults)
+                    .map(Self::Current)
+            }
+            Some(&UNCHECKED_FORMAT_VERSION) => buffer
+                .get(..StoredInfo::PRE_RELAY_FIELDS_SIZE)
+                .ok_or(SerializationError::BufferTooSmall)
+                .and_then(|fields| StoredInfo::deserialize_from(fields, defaults))
+                .map(Self::Current),
+            Some(version) => {
+                log::warn!("Stored info has unknown format version {version}");
+                Err(SerializationError::InvalidFormat)
+            }
+            None => Err(SerializationError::BufferTooSmall),
+        }
+    }
+}
+
+impl StoredInfo {
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < Self::SER_SIZE {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        // Serialize version first, then encryption key
+        let mut writer = FieldWriter { buffer, offset: 0 };
+        writer.write(&[FORMAT_VERSION]);
+        writer.write(&self.encryption_key.to_le_bytes());
+        writer.write(&self.boot_count.to_le_bytes());
+        writer.write(&[self.region]);
+        writer.write(&self.frequency_hz.to_le_bytes());
+        writer.write(&self.tx_power_dbm.to_le_bytes());
+        writer.write(&[self.transmit_times]);
+        writer.write(&[self.listen_mode]);
+        writer.write(&[self.station]);
+        writer.write(&self.debounce_ms.to_le_bytes());
+        writer.write(&[self.brightness]);
+        writer.write(&self.screen_timeout_secs.to_le_bytes());
+        writer.write(&self.name);
+        writer.write(&[self.name_len]);
+        writer.write(&[self.relay.into()]);
+        for sender_id in self.allowed_senders {
+            writer.write(&sender_id.to_le_bytes());
+        }
+        writer.write(&[self.allowed_len]);
+        writer.write(&[self.lora_preset]);
+        writer.write(&self.rx_timeout_symbols.to_le_bytes());
+        writer.write(&self.low_battery_mv.to_le_bytes());
+        writer.write(&self.critical_battery_mv.to_le_bytes());
+        writer.write(&self.idle_timeout_secs.to_le_bytes());
+        writer.write(&self.message_expiry_mins.to_le_bytes());
+        writer.write(&[self.adaptive_sf.into()]);
+        writer.write(&self.adv_interval_ms.to_le_bytes());
+        writer.write(&self.ble_tx_power_dbm.to_le_bytes());
+        writer.write(&self.signature);
+        writer.write(&[self.signature_len]);
+        for frequency_hz in self.hop_channels_hz {
+            writer.write(&frequency_hz.to_le_bytes());
+        }
+        writer.write(&[self.hop_channels_len]);
+
+        debug_assert_eq!(
+            writer.offset,
+            Self::FIELDS_SIZE,
+            "FIELDS_SIZE doesn't match the fields written"
+        );
+        // Covers every field before it, including the version
+        let crc = CRC.checksum(&writer.buffer[..Self::FIELDS_SIZE]);
+        writer.write(&crc.to_le_bytes());
+
+        Ok(Self::SER_SIZE)
+    }
+
+    /// Checks the CRC after the first `fields_size` bytes of `buffer`, then reads the fields before it.
+    fn deserialize_checked(
+        buffer: &[u8],
+        fields_size: usize,
+        defaults: &Self,
+    ) -> Result<Self, SerializationError> {
+        let (fields, crc) = buffer
+            .get(..fields_size + size_of::<u32>())
+            .ok_or(SerializationError::BufferTooSmall)?
+            .split_at(fields_size);
+        if CRC.checksum(fields) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            log::warn!("Stored info failed its CRC check, it's corrupted");
+            return Err(SerializationError::InvalidData);
+        }
+        Self::deserialize_from(fields, defaults)
+    }
+
+    /// Reads the fields of the current layout or an older one with fewer fields, starting with the version byte, told
+    /// apart by the length of `buffer`. Fields the layout doesn't have are taken from `defaults`. The CRC is checked
+    /// before this.
+    fn deserialize_from(buffer: &[u8], defaults: &Self) -> Result<Self, SerializationError> {
+        if buffer.len() < Self::PRE_RELAY_FIELDS_SIZE {
+            Err(SerializationError::BufferTooSmall)
+        } else {
+            // Skip the version, already checked
+            let mut reader = FieldReader { buffer, offset: 1 };
+            let mut stored = Self {
+                encryption_key: u128::from_le_bytes(reader.read()),
+                boot_count: u16::from_le_bytes(reader.read()),
+                region: u8::from_le_bytes(reader.read()),
+                frequency_hz: u32::from_le_bytes(reader.read()),
+                tx_power_dbm: i8::from_le_bytes(reader.read()),
+                transmit_times: u8::from_le_bytes(reader.read()),
+                listen_mode: u8::from_le_bytes(reader.read()),
+                station: u8::from_le_bytes(reader.read()),
+                debounce_ms: u16::from_le_bytes(reader.read()),
+                brightness: u8::from_le_bytes(reader.read()),
+                screen_timeout_secs: u16::from_le_bytes(reader.read()),
+                name: reader.read(),
+                name_len: u8::from_le_bytes(reader.read()),
+                ..defaults.clone()
+            };
+
+            // Fields newer layouts added keep their defaults in older ones
+            if buffer.len() >= Self::PRE_ALLOW_LIST_FIELDS_SIZE {
+                stored.relay = reader.read() != [0];
+            }
+            if buffer.len() >= Self::PRE_PRESET_FIELDS_SIZE {
+                for sender_id in &mut stored.allowed_senders {
+                    *sender_id = u16::from_le_bytes(reader.read());
+                }
+                stored.allowed_len = u8::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_RX_TIMEOUT_FIELDS_SIZE {
+                stored.lora_preset = u8::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_BATTERY_FIELDS_SIZE {
+                stored.rx_timeout_symbols = u16::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_IDLE_TIMEOUT_FIELDS_SIZE {
+                stored.low_battery_mv = u16::from_le_bytes(reader.read());
+                stored.critical_battery_mv = u16::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_MESSAGE_EXPIRY_FIELDS_SIZE {
+                stored.idle_timeout_secs = u16::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_ADAPTIVE_SF_FIELDS_SIZE {
+                stored.message_expiry_mins = u16::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_ADVERTISING_FIELDS_SIZE {
+                stored.adaptive_sf = reader.read() != [0];
+            }
+            if buffer.len() >= Self::PRE_SIGNATURE_FIELDS_SIZE {
+                stored.adv_interval_ms = u16::from_le_bytes(reader.read());
+                stored.ble_tx_power_dbm = i8::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::PRE_HOPPING_FIELDS_SIZE {
+                stored.signature = reader.read();
+                stored.signature_len = u8::from_le_bytes(reader.read());
+            }
+            if buffer.len() >= Self::FIELDS_SIZE {
+                for frequency_hz in &mut stored.hop_channels_hz {
+                    *frequency_hz = u32::from_le_bytes(reader.read());
+                }
+                stored.hop_channels_len = u8::from_le_bytes(reader.read());
+            }
+            // Every layout's size has to line up with the fields read for it, or newer fields are read from the CRC
+            debug_assert_eq!(
+                reader.offset,
+                buffer.len(),
+                "Layout size doesn't match the fields read"
+            );
+            Ok(stored)
+        }
+    }
+}
+
+/// Stores `stored` in `flash_range` as the latest record, superseding the last one.
+///
+/// The new record is appended before the old one stops being the latest, and `sequential_storage` only erases a page
+/// once everything still current in it has been copied out. Losing power partway through leaves either the old
+/// settings or the new ones loadable, never neither.
+pub async fn store_info<S: NorFlash>(
+    storage: &mut S,
+    flash_range: Range<u32>,
+    stored: &StoredInfo,
+) -> Result<(), StorageError<S::Error>> {
+    let mut serialized = [0; StoredInfo::SER_SIZE];
+    let len = stored
+        .serialize_into(&mut serialized)
+        .map_err(sequential_storage::Error::SerializationError)?;
+    let mut buffer = [0; StoredInfo::SER_SIZE.next_multiple_of(32)];
+
+    sequential_storage::map::store_item(
+        storage,
+        flash_range,
+        &mut NoCache::new(),
+        &mut buffer,
+        &(),
+        &&serialized[..len],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Loads the latest settings stored in `flash_range`, migrating older layouts by taking the fields they don't have
+/// from `defaults`. Fails with [`StorageError::NotFound`] if nothing has been stored, or [`StorageError::Corrupt`] if
+/// the latest record can't be read, so callers can tell a fresh device from one whose settings were lost.
+pub async fn load_info<S: NorFlash>(
+    storage: &mut S,
+    flash_range: Range<u32>,
+    defaults: &StoredInfo,
+) -> Result<StoredInfo, StorageError<S::Error>> {
+    let mut buffer = [0; StoredInfo::SER_SIZE.next_multiple_of(32)];
+    // Superseded records stay in flash until their page is reclaimed, so only the latest is read
+    let bytes = sequential_storage::map::fetch_item::<(), &[u8], _>(
+        storage,
+        flash_range,
+        &mut NoCache::new(),
+        &mut buffer,
+        &(),
+    )
+    .await?
+    .ok_or(StorageError::NotFound)?;
+    let record = StoredRecord::deserialize_from(bytes, defaults)
+        .map_err(sequential_storage::Error::SerializationError)?;
+    Ok(record.into_stored_info(defaults))
+}
+
+/// Stores `message` as the newest in the history kept in `flash_range`, replacing the oldest once there are `C`.
+/// `sequence` must be one more than the last message's, starting from what [`load_history`] returned. `buffer` must
+/// fit a [`HistoryRecord`].
+pub async fn store_message<S: NorFlash, const N: usize, const C: usize>(
+    storage: &mut S,
+    flash_range: Range<u32>,
+    buffer: &mut [u8],
+    sequence: u32,
+    message: &StoredMessage<N>,
+) -> Result<(), StorageError<S::Error>> {
+    // Each record is kept under the key `sequence % C`, so each new message replaces the oldest
+    let slot = u8::try_from(sequence % u32::try_from(C).unwrap()).unwrap();
+    let record = HistoryRecord {
+        sequence,
+        message: message.clone(),
+    };
+
+    sequential_storage::map::store_item(
+        storage,
+        flash_range,
+        &mut NoCache::new(),
+        buffer,
+        &slot,
+        &record,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Loads the history kept in `flash_range`, oldest first, and the sequence number to store the next message with.
+/// Records that can't be read are skipped. `buffer` must fit a [`HistoryRecord`].
+pub async fn load_history<S: NorFlash, const N: usize, const C: usize>(
+    storage: &mut S,
+    flash_range: Range<u32>,
+    buffer: &mut [u8],
+) -> (heapless::Vec<StoredMessage<N>, C>, u32) {
+    let mut cache = NoCache::new();
+    // Latest record under each key
+    let mut slots: [Option<HistoryRecord<N>>; C] = core::array::from_fn(|_| None);
+
+    if let Ok(mut iter) = sequential_storage::map::fetch_all_items::<u8, _, _>(
+        storage,
+        flash_range,
+        &mut cache,
+        buffer,
+    )
+    .await
+    {
+        loop {
+            match iter.next::<HistoryRecord<N>>(buffer).await {
+                Ok(Some((slot, record))) => {
+                    if let Some(slot) = slots.get_mut(usize::from(slot)) {
+                        *slot = Some(record);
+                    }
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    log::warn!("Stopped loading history early: {err:?}");
+                    break;
+                }
+            }
+        }
+    }
+
+    let mut records: heapless::Vec<HistoryRecord<N>, C> = slots.into_iter().flatten().collect();
+    records.sort_unstable_by_key(|record| record.sequence);
+    let next_sequence = records
+        .last()
+        .map_or(0, |record| record.sequence.wrapping_add(1));
+
+    (
+        records.into_iter().map(|record| record.message).collect(),
+        next_sequence,
+    )
+}
+
+/// Writes serialized fields one after another
+struct FieldWriter<'b> {
+    buffer: &'b mut [u8],
+    offset: usize,
+}
+
+impl FieldWriter<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.buffer[self.offset..self.offset + bytes.len()].copy_from_slice(bytes);
+        self.offset += bytes.len();
+    }
+}
+
+/// Reads serialized fields one after another
+struct FieldReader<'b> {
+    buffer: &'b [u8],
+    offset: usize,
+}
+
+impl FieldReader<'_> {
+    fn read<const N: usize>(&mut self) -> [u8; N] {
+        let bytes = self.buffer[self.offset..self.offset + N]
+            .try_into()
+            .unwrap();
+        self.offset += N;
+        bytes
+    }
+}
+
+/// A received message kept in flash, so the history view still has it after a reboot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredMessage<const N: usize> {
+    pub text: heapless::String<N>,
+    pub sender_id: u16,
+    /// The device's boot count when it was received. There's no clock, so this and `uptime_secs` are all there is to
+    /// tell when.
+    pub boot_count: u16,
+    /// Seconds since boot when it was received
+    pub uptime_secs: u32,
+}
+
+/// A [`StoredMessage`] and its place in the order messages were stored in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRecord<const N: usize> {
+    pub sequence: u32,
+    pub message: StoredMessage<N>,
+}
+
+impl<const N: usize> HistoryRecord<N> {
+    pub const SER_SIZE: usize = size_of::<u32>()
+        + size_of::<u16>()
+        + size_of::<u16>()
+        + size_of::<u32>()
+        + size_of::<u8>()
+        + N;
+}
+
+impl<'a, const N: usize> Value<'a> for HistoryRecord<N> {
+    #[allow(clippy::cast_possible_truncation)]
+    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
+        if buffer.len() < Self::SER_SIZE {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        let text = self.message.text.as_bytes();
+        let mut padded_text = [0; N];
+        padded_text[..text.len()].copy_from_slice(text);
+
+        let mut writer = FieldWriter { buffer, offset: 0 };
+        writer.write(&self.sequence.to_le_bytes());
+        writer.write(&self.message.sender_id.to_le_bytes());
+        writer.write(&self.message.boot_count.to_le_bytes());
+        writer.write(&self.message.uptime_secs.to_le_bytes());
+        // The text's length is stored in a byte
+        const { assert!(N <= u8::MAX as usize) };
+        writer.write(&[text.len() as u8]);
+        writer.write(&padded_text);
+        debug_assert_eq!(
+            writer.offset,
+            Self::SER_SIZE,
+            "SER_SIZE doesn't match the fields written"
+        );
+
+        Ok(Self::SER_SIZE)
+    }
+
+    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
+    where
+        Self: Sized,
+    {
+        if buffer.len() < Self::SER_SIZE {
+            return Err(SerializationError::BufferTooSmall);
+        }
+
+        let mut reader = FieldReader { buffer, offset: 0 };
+        let sequence = u32::from_le_bytes(reader.read());
+        let sender_id = u16::from_le_bytes(reader.read());
+        let boot_count = u16::from_le_bytes(reader.read());
+        let uptime_secs = u32::from_le_bytes(reader.read());
+        let [text_len] = reader.read();
+        let padded_text: [u8; N] = reader.read();
+        let text = padded_text
+            .get(..text_len.into())
+            .and_then(|text| core::str::from_utf8(text).ok())
+            .and_then(|text| text.try_into().ok())
+            .ok_or(SerializationError::InvalidData)?;
+
+        Ok(Self {
+            sequence,
+            message: StoredMessage {
+                text,
+                sender_id,
+                boot_count,
+                uptime_secs,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+    use embedded_storage_async::nor_flash::{
+        ErrorType, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+    };
+
+    use super::*;
+
+    /// NOR flash kept in RAM. Erasing sets bytes to `0xFF`, and writing can only clear bits. Once `operations_left`
+    /// runs out, the next erase or write only gets halfway before failing, as if power was lost partway through.
+    #[derive(Clone)]
+    struct MockFlash {
+        bytes: std::vec::Vec<u8>,
+        operations_left: Option<usize>,
+    }
+
+    #[derive(Debug)]
+    struct PowerLost;
+
+    impl NorFlashError for PowerLost {
+        fn kind(&self) -> NorFlashErrorKind {
+            NorFlashErrorKind::Other
+        }
+    }
+
+    impl MockFlash {
+        /// Two pages, the least `sequential_storage` can use
+        const RANGE: Range<u32> = 0..2 * Self::ERASE_SIZE as u32;
+
+        fn new() -> Self {
+            Self {
+                bytes: std::vec![0xFF; Self::RANGE.end as usize],
+                operations_left: None,
+            }
+        }
+
+        /// How many of the `len` bytes an erase or write gets through before power is lost, if it is
+        fn bytes_before_power_loss(&mut self, len: usize) -> Option<usize> {
+            match &mut self.operations_left {
+                Some(0) => Some(len / 2 / Self::WRITE_SIZE * Self::WRITE_SIZE),
+                Some(left) => {
+                    *left -= 1;
+                    None
+                }
+                None => None,
+            }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = PowerLost;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        async fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), PowerLost> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.bytes[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 4096;
+
+        async fn erase(&mut self, from: u32, to: u32) -> Result<(), PowerLost> {
+            let (from, to) = (from as usize, to as usize);
+            let erased = self.bytes_before_power_loss(to - from);
+            self.bytes[from..from + erased.unwrap_or(to - from)].fill(0xFF);
+            erased.map_or(Ok(()), |_| Err(PowerLost))
+        }
+
+        async fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), PowerLost> {
+            let written = self.bytes_before_power_loss(bytes.len());
+            let offset = offset as usize;
+            for (byte, new) in self.bytes[offset..]
+                .iter_mut()
+                .zip(&bytes[..written.unwrap_or(bytes.len())])
+            {
+                *byte &= new;
+            }
+            written.map_or(Ok(()), |_| Err(PowerLost))
+        }
+    }
+
+    /// Settings with every field set, and none at the values [`defaults`] gives them
+    fn stored() -> StoredInfo {
+        let mut name = [0; NAME_CAPACITY];
+        name[..5].copy_from_slice(b"lewoc");
+        let mut signature = [0; SIGNATURE_CAPACITY];
+        signature[..3].copy_from_slice(b"Ben");
+        let mut allowed_senders = [0; ALLOW_LIST_CAPACITY];
+        allowed_senders[..2].copy_from_slice(&[0x1234, 0x4321]);
+        let mut hop_channels_hz = [0; HOP_CHANNELS_CAPACITY];
+        hop_channels_hz[..3].copy_from_slice(&[902_300_000, 903_900_000, 905_500_000]);
+        StoredInfo {
+            encryption_key: 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210,
+            boot_count: 42,
+            region: 1,
+            frequency_hz: 915_000_000,
+            tx_power_dbm: -3,
+            transmit_times: 4,
+            listen_mode: 1,
+            station: 2,
+            debounce_ms: 25,
+            brightness: 60,
+            screen_timeout_secs: 90,
+            name,
+            name_len: 5,
+            relay: false,
+            allowed_senders,
+            allowed_len: 2,
+            lora_preset: 2,
+            rx_timeout_symbols: 300,
+            low_battery_mv: 3500,
+            critical_battery_mv: 3300,
+            idle_timeout_secs: 120,
+            message_expiry_mins: 45,
+            adaptive_sf: true,
+            adv_interval_ms: 250,
+            ble_tx_power_dbm: -8,
+            signature,
+            signature_len: 3,
+            hop_channels_hz,
+            hop_channels_len: 3,
+        }
+    }
+
+    /// What fields older layouts don't have are read as
+    fn defaults() -> StoredInfo {
+        StoredInfo {
+            encryption_key: 0,
+            boot_count: 0,
+            region: 0,
+            frequency_hz: 868_000_000,
+            tx_power_dbm: 14,
+            transmit_times: 1,
+            listen_mode: 0,
+            station: NO_STATION,
+            debounce_ms: 10,
+            brightness: 100,
+            screen_timeout_secs: 30,
+            name: [0; NAME_CAPACITY],
+            name_len: 0,
+            relay: true,
+            allowed_senders: [0; ALLOW_LIST_CAPACITY],
+            allowed_len: 0,
+            lora_preset: 0,
+            rx_timeout_symbols: 100,
+            low_battery_mv: 3600,
+            critical_battery_mv: 3400,
+            idle_timeout_secs: 60,
+            message_expiry_mins: 30,
+            adaptive_sf: false,
+            adv_interval_ms: 100,
+            ble_tx_power_dbm: 0,
+            signature: [0; SIGNATURE_CAPACITY],
+            signature_len: 0,
+            hop_channels_hz: [0; HOP_CHANNELS_CAPACITY],
+            hop_channels_len: 0,
+        }
+    }
+
+    #[test]
+    fn serialized_info_is_ser_size() {
+        let mut buffer = [0xff; StoredInfo::SER_SIZE + 8];
+
+        let len = StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+
+        assert_eq!(len, StoredInfo::SER_SIZE);
+        assert_eq!(buffer[0], FORMAT_VERSION);
+        // Nothing is written past it
+        assert!(
+            buffer[StoredInfo::SER_SIZE..]
+                .iter()
+                .all(|&byte| byte == 0xff)
+        );
+    }
+
+    #[test]
+    fn info_round_trips() {
+        let mut buffer = [0; StoredInfo::SER_SIZE];
+        StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+
+        let record = StoredRecord::deserialize_from(&buffer, &defaults()).unwrap();
+
+        assert!(matches!(record, StoredRecord::Current(info) if info == stored()));
+    }
+
+    #[test]
+    fn info_needs_room_for_every_field() {
+        let mut buffer = [0; StoredInfo::SER_SIZE];
+
+        assert!(matches!(
+            StoredRecord::Current(stored()).serialize_into(&mut buffer[..StoredInfo::SER_SIZE - 1]),
+            Err(SerializationError::BufferTooSmall)
+        ));
+
+        StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+        assert!(matches!(
+            StoredRecord::deserialize_from(&buffer[..StoredInfo::SER_SIZE - 1], &defaults()),
+            Err(SerializationError::BufferTooSmall)
+        ));
+        assert!(matches!(
+            StoredRecord::deserialize_from(&[], &defaults()),
+            Err(SerializationError::BufferTooSmall)
+        ));
+    }
+
+    /// `stored()` serialized in an older layout: the version, the first `fields_size` bytes of its fields, then their
+    /// CRC
+    fn older_layout(version: u8, fields_size: usize) -> std::vec::Vec<u8> {
+        let mut buffer = [0; StoredInfo::SER_SIZE];
+        StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+        buffer[0] = version;
+
+        let mut older = buffer[..fields_size].to_vec();
+        older.extend_from_slice(&CRC.checksum(&older).to_le_bytes());
+        older
+    }
+
+    #[test]
+    fn v0_keeps_its_key_with_defaults() {
+        let key = stored().encryption_key;
+
+        let record = StoredRecord::deserialize_from(&key.to_le_bytes(), &defaults()).unwrap();
+
+        assert!(matches!(record, StoredRecord::V0 { encryption_key } if encryption_key == key));
+        assert_eq!(
+            record.into_stored_info(&defaults()),
+            StoredInfo {
+                encryption_key: key,
+                ..defaults()
+            }
+        );
+    }
+
+    #[test]
+    fn v0_round_trips() {
+        let mut buffer = [0; V0_SER_SIZE];
+        let len = StoredRecord::V0 {
+            encryption_key: 0x1234,
+        }
+        .serialize_into(&mut buffer)
+        .unwrap();
+        assert_eq!(len, V0_SER_SIZE);
+
+        let stored = StoredRecord::deserialize_from(&buffer, &defaults())
+            .unwrap()
+            .into_stored_info(&defaults());
+        assert_eq!(stored.encryption_key, 0x1234);
+    }
+
+    #[test]
+    fn layout_before_hopping_takes_hop_channels_from_defaults() {
+        let buffer = older_layout(
+            PRE_HOPPING_FORMAT_VERSION,
+            StoredInfo::PRE_HOPPING_FIELDS_SIZE,
+        );
+
+        let stored = StoredRecord::deserialize_from(&buffer, &defaults())
+            .unwrap()
+            .into_stored_info(&defaults());
+
+        assert_eq!(
+            stored,
+            StoredInfo {
+                hop_channels_hz: defaults().hop_channels_hz,
+                hop_channels_len: defaults().hop_channels_len,
+                ..stored()
+            }
+        );
+    }
+
+    #[test]
+    fn oldest_layouts_keep_their_fields_with_defaults_for_the_rest() {
+        let expected = StoredInfo {
+            encryption_key: stored().encryption_key,
+            boot_count: stored().boot_count,
+            region: stored().region,
+            frequency_hz: stored().frequency_hz,
+            tx_power_dbm: stored().tx_power_dbm,
+            transmit_times: stored().transmit_times,
+            listen_mode: stored().listen_mode,
+            station: stored().station,
+            debounce_ms: stored().debounce_ms,
+            brightness: stored().brightness,
+            screen_timeout_secs: stored().screen_timeout_secs,
+            name: stored().name,
+            name_len: stored().name_len,
+            ..defaults()
+        };
+
+        let checked = older_layout(PRE_RELAY_FORMAT_VERSION, StoredInfo::PRE_RELAY_FIELDS_SIZE);
+        let stored_checked = StoredRecord::deserialize_from(&checked, &defaults())
+            .unwrap()
+            .into_stored_info(&defaults());
+        assert_eq!(stored_checked, expected);
+
+        // From before there was a CRC
+        let mut unchecked = checked;
+        unchecked[0] = UNCHECKED_FORMAT_VERSION;
+        unchecked.truncate(StoredInfo::PRE_RELAY_FIELDS_SIZE);
+        let stored_unchecked = StoredRecord::deserialize_from(&unchecked, &defaults())
+            .unwrap()
+            .into_stored_info(&defaults());
+        assert_eq!(stored_unchecked, expected);
+    }
+
+    #[test]
+    fn unknown_version_is_invalid_format() {
+        let mut buffer = [0; StoredInfo::SER_SIZE];
+        StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+        buffer[0] = FORMAT_VERSION + 1;
+
+        assert!(matches!(
+            StoredRecord::deserialize_from(&buffer, &defaults()),
+            Err(SerializationError::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn flipped_byte_fails_crc() {
+        let mut buffer = [0; StoredInfo::SER_SIZE];
+        StoredRecord::Current(stored())
+            .serialize_into(&mut buffer)
+            .unwrap();
+
+        // Every byte after the version, the CRC included
+        for pos in 1..StoredInfo::SER_SIZE {
+            let mut corrupted = buffer;
+            corrupted[pos] ^= 0x04;
+
+            assert!(
+                matches!(
+                    StoredRecord::deserialize_from(&corrupted, &defaults()),
+                    Err(SerializationError::InvalidData)
+                ),
+                "flipping byte {pos} wasn't caught"
+            );
+        }
+    }
+
+    #[test]
+    fn flipped_byte_in_older_layout_fails_crc() {
+        let mut buffer = older_layout(PRE_RELAY_FORMAT_VERSION, StoredInfo::PRE_RELAY_FIELDS_SIZE);
+        buffer[1] ^= 0x80;
+
+        assert!(matches!(
+            StoredRecord::deserialize_from(&buffer, &defaults()),
+            Err(SerializationError::InvalidData)
+        ));
+    }
+
+    const TEXT_LEN: usize = 32;
+    const HISTORY_LEN: usize = 4;
+
+    fn messages(count: u16) -> std::vec::Vec<StoredMessage<TEXT_LEN>> {
+        let texts = [
+            "All good at Bridge",
+            "",
+            "HELP Summit ↑",
+            "Heading down now",
+        ];
+        (0..count)
+            .map(|i| StoredMessage {
+                text: texts[usize::from(i) % texts.len()].try_into().unwrap(),
+                sender_id: 0x1000 + i,
+                boot_count: 3,
+                uptime_secs: 60 * u32::from(i),
+            })
+            .collect()
+    }
+
+    async fn store_all(flash: &mut MockFlash, messages: &[StoredMessage<TEXT_LEN>]) {
+        let mut buffer = [0; HistoryRecord::<TEXT_LEN>::SER_SIZE.next_multiple_of(32)];
+        for (sequence, message) in (0..).zip(messages) {
+            store_message::<_, _, HISTORY_LEN>(
+                flash,
+                MockFlash::RANGE,
+                &mut buffer,
+                sequence,
+                message,
+            )
+            .await
+            .unwrap();
+        }
+    }
+
+    async fn load_all(
+        flash: &mut MockFlash,
+    ) -> (heapless::Vec<StoredMessage<TEXT_LEN>, HISTORY_LEN>, u32) {
+        let mut buffer = [0; HistoryRecord::<TEXT_LEN>::SER_SIZE.next_multiple_of(32)];
+        load_history(flash, MockFlash::RANGE, &mut buffer).await
+    }
+
+    #[test]
+    fn history_record_round_trips() {
+        for message in messages(4) {
+            let record = HistoryRecord {
+                sequence: 0xDEAD_BEEF,
+                message,
+            };
+            let mut buffer = [0; HistoryRecord::<TEXT_LEN>::SER_SIZE];
+
+            assert_eq!(
+                record.serialize_into(&mut buffer).unwrap(),
+                HistoryRecord::<TEXT_LEN>::SER_SIZE
+            );
+            assert_eq!(HistoryRecord::deserialize_from(&buffer).unwrap(), record);
+        }
+    }
+
+    #[test]
+    fn several_messages_round_trip_through_flash() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            let (history, next_sequence) = load_all(&mut flash).await;
+            assert!(history.is_empty());
+            assert_eq!(next_sequence, 0);
+
+            let messages = messages(3);
+            store_all(&mut flash, &messages).await;
+
+            let (history, next_sequence) = load_all(&mut flash).await;
+            assert_eq!(history.as_slice(), messages.as_slice());
+            assert_eq!(next_sequence, 3);
+        });
+    }
+
+    #[test]
+    fn oldest_messages_are_replaced_once_history_is_full() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            let messages = messages(6);
+            store_all(&mut flash, &messages).await;
+
+            let (history, next_sequence) = load_all(&mut flash).await;
+            assert_eq!(history.as_slice(), &messages[2..]);
+            assert_eq!(next_sequence, 6);
+        });
+    }
+
+    /// Stores `bytes` as the latest settings record, whatever they are
+    async fn store_raw(flash: &mut MockFlash, bytes: &[u8]) {
+        let mut buffer = [0; StoredInfo::SER_SIZE.next_multiple_of(32)];
+        sequential_storage::map::store_item(
+            flash,
+            MockFlash::RANGE,
+            &mut NoCache::new(),
+            &mut buffer,
+            &(),
+            &bytes,
+        )
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn stored_info_loads() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            store_info(&mut flash, MockFlash::RANGE, &defaults())
+                .await
+                .unwrap();
+            store_info(&mut flash, MockFlash::RANGE, &stored())
+                .await
+                .unwrap();
+
+            let loaded = load_info(&mut flash, MockFlash::RANGE, &defaults()).await;
+
+            assert_eq!(loaded.unwrap(), stored());
+        });
+    }
+
+    #[test]
+    fn nothing_stored_is_not_found() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+
+            let loaded = load_info(&mut flash, MockFlash::RANGE, &defaults()).await;
+
+            assert!(matches!(loaded, Err(StorageError::NotFound)));
+        });
+    }
+
+    #[test]
+    fn erased_info_is_not_found() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            store_info(&mut flash, MockFlash::RANGE, &stored())
+                .await
+                .unwrap();
+            sequential_storage::erase_all(&mut flash, MockFlash::RANGE)
+                .await
+                .unwrap();
+
+            let loaded = load_info(&mut flash, MockFlash::RANGE, &defaults()).await;
+
+            assert!(matches!(loaded, Err(StorageError::NotFound)));
+        });
+    }
+
+    #[test]
+    fn failed_crc_is_corrupt() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            let mut buffer = [0; StoredInfo::SER_SIZE];
+            stored().serialize_into(&mut buffer).unwrap();
+            buffer[StoredInfo::SER_SIZE - 1] ^= 1;
+            store_raw(&mut flash, &buffer).await;
+
+            let loaded = load_info(&mut flash, MockFlash::RANGE, &defaults()).await;
+
+            assert!(matches!(loaded, Err(StorageError::Corrupt)));
+        });
+    }
+
+    #[test]
+    fn unknown_layout_is_corrupt() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            let mut buffer = [0; StoredInfo::SER_SIZE];
+            stored().serialize_into(&mut buffer).unwrap();
+            buffer[0] = FORMAT_VERSION + 1;
+            store_raw(&mut flash, &buffer).await;
+
+            let loaded = load_info(&mut flash, MockFlash::RANGE, &defaults()).await;
+
+            assert!(matches!(loaded, Err(StorageError::Corrupt)));
+        });
+    }
+
+    #[test]
+    fn failing_flash_is_a_flash_error() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            flash.operations_left = Some(0);
+
+            let result = store_info(&mut flash, MockFlash::RANGE, &stored()).await;
+
+            assert!(matches!(
+                result,
+                Err(StorageError::Flash(
+                    sequential_storage::Error::Storage { .. }
+                ))
+            ));
+        });
+    }
+
+    #[test]
+    fn interrupted_store_leaves_old_or_new_info() {
+        block_on(async {
+            let mut flash = MockFlash::new();
+            let mut old = defaults();
+            store_info(&mut flash, MockFlash::RANGE, &old)
+                .await
+                .unwrap();
+
+            // Enough updates to fill both pages, so some of them have to reclaim a page first
+            for boot_count in 1..60 {
+                let new = StoredInfo {
+                    boot_count,
+                    ..stored()
+                };
+
+                // Lose power at every erase or write the update does, until it gets through all of them
+                for operations in 0.. {
+                    let mut interrupted = flash.clone();
+                    interrupted.operations_left = Some(operations);
+                    if store_info(&mut interrupted, MockFlash::RANGE, &new)
+                        .await
+                        .is_ok()
+                    {
+                        break;
+                    }
+
+                    interrupted.operations_left = None;
+                    let loaded = load_info(&mut interrupted, MockFlash::RANGE, &defaults())
+                        .await
+                        .unwrap_or_else(|err| {
+                            panic!(
+                                "update {boot_count} lost power at operation {operations}, leaving nothing: {err:?}"
+                            )
+                        });
+                    assert!(
+                        loaded == old || loaded == new,
+                        "update {boot_count} interrupted at operation {operations} left other settings"
+                    );
+                }
+
+                store_info(&mut flash, MockFlash::RANGE, &new)
+                    .await
+                    .unwrap();
+                old = new;
+            }
+        });
+    }
+}
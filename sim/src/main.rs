@@ -1,32 +1,215 @@
-use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
+use common::Button;
+use embedded_graphics::{
+    mono_font::{MonoTextStyle, ascii::FONT_6X10},
+    pixelcolor::Rgb565,
+    prelude::*,
+    text::{Baseline, Text},
+};
 use embedded_graphics_simulator::{
-    BinaryColorTheme, OutputSettingsBuilder, SimulatorDisplay, Window,
+    BinaryColorTheme, OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
+    sdl2::Keycode,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Sender of the fake messages the sim receives
+const FAKE_SENDER_ID: u16 = 0x1A2B;
+/// Emergencies swap their colors this often, like on the device
+const EMERGENCY_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What's on the simulated screen, redrawn from scratch whenever it changes
+struct Screen {
+    status: graphics::Status,
+    message: String,
+    style: graphics::MessageStyle,
+    /// How every message is laid out, whatever its style, so the options can be compared on the same message
+    alignment: graphics::MessageAlignment,
+    wrap: graphics::Wrap,
+    /// Last simulated button press, labeled along the bottom
+    pressed: Option<Button>,
+    /// Emergency text and whether its colors are swapped, covering everything until a button is pressed
+    emergency: Option<(String, bool)>,
+}
+
+impl Screen {
+    fn draw<D: DrawTarget<Color = Rgb565>>(&self, target: &mut D) -> Result<(), D::Error> {
+        if let Some((text, inverted)) = &self.emergency {
+            return graphics::draw_emergency(target, text, *inverted);
+        }
+
+        graphics::fill(target)?;
+        graphics::draw_status_bar(target, &self.status)?;
+        let style = graphics::MessageStyle {
+            alignment: self.alignment,
+            wrap: self.wrap,
+            ..self.style
+        };
+        graphics::draw_message_styled(target, &self.message, &style)?;
+
+        if let Some(button) = self.pressed {
+            let bottom = common::ROTATED_HEIGHT as i32 - FONT_6X10.character_size.height as i32;
+            Text::with_baseline(
+                &format!("Pressed {button:?}"),
+                Point::new(2, bottom),
+                MonoTextStyle::new(&FONT_6X10, Rgb565::CSS_LIGHT_GRAY),
+                Baseline::Top,
+            )
+            .draw(target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Shows what the device does once a press of `button` has been delivered. Like on the device, a press while an
+    /// emergency is up only acknowledges it.
+    fn press(&mut self, button: Button) {
+        if self.emergency.take().is_some() {
+            return;
+        }
+
+        self.message = format!("{}{}", common::delivery_prefix(true), button.message());
+        self.style = graphics::MessageStyle::DEFAULT;
+        self.pressed = Some(button);
+    }
+
+    /// Shows a message as if it was received over LoRa from [`FAKE_SENDER_ID`], at the signal strength shown in the
+    /// status bar.
+    fn receive(&mut self, text: &str) {
+        let signal_bars = self.status.signal_bars.unwrap_or_default();
+        self.status.signal_bars = Some(signal_bars);
+        let prefix = common::SenderPrefix {
+            sender_id: FAKE_SENDER_ID,
+            signal_bars,
+            direct: false,
+        };
+        self.message = format!("{prefix}{text}");
+        self.style = graphics::MessageStyle::DEFAULT;
+    }
+
+    /// Shows a call for help as if it was received over LoRa from [`FAKE_SENDER_ID`].
+    fn receive_help(&mut self, station: Option<common::Station>) {
+        let help = common::HelpNeeded {
+            sender_id: FAKE_SENDER_ID,
+            station,
+        };
+        self.emergency = Some((help.to_string(), false));
+    }
+
+    /// Steps through the ways messages can be laid out: justified, left aligned, then left aligned with short words
+    /// kept on the same line as the next one.
+    fn cycle_layout(&mut self) {
+        use graphics::{MessageAlignment, Wrap};
+
+        (self.alignment, self.wrap) = match (self.alignment, self.wrap) {
+            (MessageAlignment::Justified, _) => (MessageAlignment::Left, Wrap::Words),
+            (MessageAlignment::Left, Wrap::Words) => (MessageAlignment::Left, Wrap::KeepShortWords),
+            (MessageAlignment::Left, Wrap::KeepShortWords) => {
+                (MessageAlignment::Justified, Wrap::Words)
+            }
+        };
+    }
+
+    /// Steps the fake signal strength up a bar, wrapping around to no signal after full bars.
+    fn cycle_signal_bars(&mut self) {
+        self.status.signal_bars = match self.status.signal_bars {
+            None => Some(0),
+            Some(graphics::SIGNAL_BARS..) => None,
+            Some(bars) => Some(bars + 1),
+        };
+    }
+}
+
+/// The simulated button a key stands in for, if any
+fn button_for(keycode: Keycode) -> Option<Button> {
+    match keycode {
+        Keycode::G => Some(Button::Good),
+        Keycode::H => Some(Button::Help),
+        _ => None,
+    }
+}
 
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
     let mut display: SimulatorDisplay<Rgb565> =
-        SimulatorDisplay::new(Size::new(common::DISPLAY_WIDTH, common::DISPLAY_HEIGHT));
+        SimulatorDisplay::new(Size::new(common::ROTATED_WIDTH, common::ROTATED_HEIGHT));
 
     let output_settings = OutputSettingsBuilder::new()
         .theme(BinaryColorTheme::Default)
         .build();
 
-    let mut window = Window::new("LEWOC Window Sim", &output_settings);
+    let mut window = Window::new(
+        "LEWOC Window Sim - G: Good, H: Help, M: receive message, E: receive Help, S: signal, B: BLE, W: wrap",
+        &output_settings,
+    );
+    window.update(&display);
+
+    graphics::draw_splash(
+        &mut display,
+        "0001",
+        env!("CARGO_PKG_VERSION"),
+        Some(common::Station::SanFrancisco),
+    )?;
     window.update(&display);
+    std::thread::sleep(Duration::from_secs(2));
 
-    graphics::draw_message(&mut display, "Hey Andria!");
+    // Status text from the device itself is dimmer and smaller
+    let mut screen = Screen {
+        status: graphics::Status {
+            signal_bars: Some(3),
+            ble_connected: true,
+            station: Some(common::Station::SanFrancisco),
+            emergency_broadcast: false,
+            low_battery: false,
+            key_mismatch: false,
+            message_age: None,
+        },
+        message: "Press G for Good or H for Help".to_string(),
+        style: graphics::MessageStyle::SYSTEM,
+        alignment: graphics::MessageAlignment::default(),
+        wrap: graphics::Wrap::default(),
+        pressed: None,
+        emergency: None,
+    };
+    screen.draw(&mut display)?;
     window.update(&display);
 
+    let mut next_blink = Instant::now() + EMERGENCY_BLINK_INTERVAL;
     loop {
         for event in window.events() {
             match event {
-                embedded_graphics_simulator::SimulatorEvent::Quit => std::process::exit(0),
+                SimulatorEvent::Quit => std::process::exit(0),
+                SimulatorEvent::KeyDown {
+                    keycode,
+                    repeat: false,
+                    ..
+                } => {
+                    match keycode {
+                        Keycode::M => {
+                            screen.receive("Train's delayed 10 min at South San Francisco");
+                        }
+                        Keycode::W => screen.cycle_layout(),
+                        Keycode::S => screen.cycle_signal_bars(),
+                        Keycode::B => screen.status.ble_connected = !screen.status.ble_connected,
+                        Keycode::E => screen.receive_help(Some(common::Station::Millbrae)),
+                        keycode => match button_for(keycode) {
+                            Some(button) => screen.press(button),
+                            None => continue,
+                        },
+                    }
+                    screen.draw(&mut display)?;
+                }
                 _ => {}
             }
         }
 
+        if Instant::now() >= next_blink {
+            next_blink = Instant::now() + EMERGENCY_BLINK_INTERVAL;
+            if let Some((_, inverted)) = &mut screen.emergency {
+                *inverted = !*inverted;
+                screen.draw(&mut display)?;
+            }
+        }
+        window.update(&display);
+
         std::thread::sleep(Duration::from_millis(100));
     }
 }
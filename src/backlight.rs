@@ -0,0 +1,134 @@
+//! Drives the display's backlight with PWM, dimming it once the device has been left alone for a while, then turning
+//! it off and blanking the screen.
+//!
+//! Both cores can wake the backlight or change its brightness, so the signals here use a
+//! [`CriticalSectionRawMutex`].
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use embassy_futures::select::{Either4, select4};
+use embassy_rp::pwm::{self, Pwm};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+
+/// Brightness is a percentage of the backlight's full brightness
+pub const MAX_BRIGHTNESS: u8 = 100;
+pub const DEFAULT_BRIGHTNESS: u8 = MAX_BRIGHTNESS;
+/// How long the device has to be idle before the screen is blanked, in seconds
+pub const DEFAULT_SCREEN_TIMEOUT_SECS: u16 = 120;
+/// The backlight dims after this long without a button press or message
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// While idle, the configured brightness is divided by this
+const IDLE_DIVISOR: u8 = 10;
+/// The PWM counter wraps after this, which at the 150MHz system clock is fast enough (15kHz) not to flicker
+const PWM_TOP: u16 = 9_999;
+
+static BRIGHTNESS_SIGNAL: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+static WAKE_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+static SCREEN_TIMEOUT_SIGNAL: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+/// Whether the backlight stays dimmed even while there's activity, to save the battery
+static POWER_SAVING: AtomicBool = AtomicBool::new(false);
+/// Signaled with `true` when the screen should be blanked after being idle for the screen timeout, and `false` once
+/// it wakes back up
+pub static BLANK_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Changes the backlight's brightness, clamped to [`MAX_BRIGHTNESS`]. Also wakes it, so the change can be seen.
+pub fn set_brightness(brightness: u8) {
+    BRIGHTNESS_SIGNAL.signal(brightness.min(MAX_BRIGHTNESS));
+}
+
+/// Changes how long the device has to be idle before the screen is blanked, in seconds. 0 never blanks it.
+pub fn set_screen_timeout(secs: u16) {
+    SCREEN_TIMEOUT_SIGNAL.signal(secs);
+}
+
+/// Keeps the backlight dimmed as if idle, even while there's activity, until turned back off.
+pub fn set_power_saving(power_saving: bool) {
+    POWER_SAVING.store(power_saving, Ordering::Relaxed);
+    // Applied the next time the backlight is updated
+    WAKE_SIGNAL.signal(());
+}
+
+/// Brings the backlight back to full brightness and restarts the idle timeouts. Called on any activity.
+pub fn wake() {
+    WAKE_SIGNAL.signal(());
+}
+
+/// Drives the backlight at [`DEFAULT_BRIGHTNESS`] until told otherwise with [`set_brightness`]. It's dimmed after
+/// [`IDLE_TIMEOUT`] without a call to [`wake`], and turned off after the screen timeout, if there is one.
+pub async fn run(mut pwm: Pwm<'_>) -> ! {
+    let mut config = pwm::Config::default();
+    config.top = PWM_TOP;
+
+    let mut brightness = DEFAULT_BRIGHTNESS;
+    let mut screen_timeout: Option<Duration> = None;
+    let mut last_activity = Instant::now();
+    let mut blanked = false;
+
+    loop {
+        let now = Instant::now();
+        let dim_at = last_activity + IDLE_TIMEOUT;
+        let blank_at = screen_timeout.map(|timeout| last_activity + timeout);
+
+        let dimmed = now >= dim_at || POWER_SAVING.load(Ordering::Relaxed);
+        let should_blank = blank_at.is_some_and(|at| now >= at);
+        if should_blank != blanked {
+            if should_blank {
+                log::debug!("Blanking idle screen");
+            }
+            blanked = should_blank;
+            BLANK_SIGNAL.signal(blanked);
+        }
+
+        let percent = if blanked {
+            0
+        } else if dimmed {
+            brightness / IDLE_DIVISOR
+        } else {
+            brightness
+        };
+        config.compare_b = duty_cycle(percent);
+        pwm.set_config(&config);
+
+        // Next time the backlight changes without being told to
+        let next_change = [(!dimmed).then_some(dim_at), blank_at.filter(|_| !blanked)]
+            .into_iter()
+            .flatten()
+            .min();
+        let idle_timeout = async move {
+            match next_change {
+                Some(at) => Timer::at(at).await,
+                None => core::future::pending().await,
+            }
+        };
+
+        match select4(
+            BRIGHTNESS_SIGNAL.wait(),
+            WAKE_SIGNAL.wait(),
+            SCREEN_TIMEOUT_SIGNAL.wait(),
+            idle_timeout,
+        )
+        .await
+        {
+            Either4::First(new_brightness) => {
+                log::info!("Backlight brightness set to {new_brightness}%");
+                brightness = new_brightness;
+                last_activity = Instant::now();
+            }
+            Either4::Second(()) => last_activity = Instant::now(),
+            Either4::Third(secs) => {
+                log::info!("Screen timeout set to {secs}s");
+                screen_timeout = (secs > 0).then_some(Duration::from_secs(secs.into()));
+            }
+            Either4::Fourth(()) => {}
+        }
+    }
+}
+
+/// Compare value that keeps the backlight on for `percent` of each PWM period
+#[allow(clippy::cast_possible_truncation)]
+fn duty_cycle(percent: u8) -> u16 {
+    // At most PWM_TOP + 1, so always fits
+    (u32::from(percent.min(MAX_BRIGHTNESS)) * (u32::from(PWM_TOP) + 1) / u32::from(MAX_BRIGHTNESS))
+        as u16
+}
@@ -0,0 +1,92 @@
+//! Keeps track of the battery's charge, measured from its voltage.
+//!
+//! The RP2350 has no fuel gauge, so the battery's voltage is read off an ADC pin, averaging [`SAMPLES`] samples to
+//! smooth out noise. See [`common::battery`] for how it's wired and how charge is estimated from it.
+//!
+//! Once the voltage drops below the low or critical threshold, the device cuts back on what it transmits so it stays
+//! useful for calls for help as long as possible. See [`BatteryLevel`].
+
+use core::{cell::Cell, ops::RangeInclusive};
+
+use embassy_rp::adc::{self, Adc};
+use embassy_sync::{
+    blocking_mutex::{
+        Mutex,
+        raw::{CriticalSectionRawMutex, RawMutex},
+    },
+    signal::Signal,
+};
+use embassy_time::{Duration, Timer};
+
+pub use common::battery::{BatteryLevel, BatteryReading};
+
+use crate::{backlight, display};
+
+/// Number of ADC samples averaged into each reading
+const SAMPLES: u32 = 8;
+/// How often the battery is measured
+const READ_INTERVAL: Duration = Duration::from_secs(60);
+/// Allowed low and critical thresholds, in mV
+pub const THRESHOLD_RANGE_MV: RangeInclusive<u16> = 3000..=4200;
+/// About 10% charge left
+pub const DEFAULT_LOW_MV: u16 = 3700;
+/// About 2% charge left
+pub const DEFAULT_CRITICAL_MV: u16 = 3450;
+
+static LEVEL: Mutex<CriticalSectionRawMutex, Cell<BatteryLevel>> =
+    Mutex::new(Cell::new(BatteryLevel::Normal));
+
+/// The battery's level as of the last reading.
+pub fn level() -> BatteryLevel {
+    LEVEL.lock(Cell::get)
+}
+
+/// Measures the battery once, averaging [`SAMPLES`] ADC samples.
+#[allow(clippy::cast_possible_truncation)]
+pub async fn read(
+    adc: &mut Adc<'_, adc::Async>,
+    channel: &mut adc::Channel<'_>,
+) -> Result<BatteryReading, adc::Error> {
+    let mut total = 0;
+    for _ in 0..SAMPLES {
+        total += u32::from(adc.read(channel).await?);
+    }
+    // An average of `u16`s, so always fits
+    Ok(BatteryReading::from_adc((total / SAMPLES) as u16))
+}
+
+/// Measures the battery every [`READ_INTERVAL`], signaling each reading on `signal`. Crossing `low_mv` or
+/// `critical_mv` changes the [`level`], shown on the status bar.
+pub async fn run<M: RawMutex>(
+    mut adc: Adc<'_, adc::Async>,
+    mut channel: adc::Channel<'_>,
+    signal: &Signal<M, BatteryReading>,
+    low_mv: u16,
+    critical_mv: u16,
+) -> ! {
+    loop {
+        match read(&mut adc, &mut channel).await {
+            Ok(reading) => {
+                log::debug!(
+                    "Battery at {}% ({} mV)",
+                    reading.percent,
+                    reading.millivolts
+                );
+                let previous = level();
+                let level = previous.next(reading.millivolts, low_mv, critical_mv);
+                if level != previous {
+                    log::warn!("Battery level now {level:?} at {} mV", reading.millivolts);
+                    LEVEL.lock(|cell| cell.set(level));
+                    display::update_status(|status| {
+                        status.low_battery = level != BatteryLevel::Normal;
+                    });
+                    backlight::set_power_saving(level == BatteryLevel::Critical);
+                }
+                signal.signal(reading);
+            }
+            Err(err) => log::error!("Error reading battery voltage: {err:?}"),
+        }
+
+        Timer::after(READ_INTERVAL).await;
+    }
+}
@@ -1,43 +1,393 @@
-use embassy_futures::join::join;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, signal::Signal};
-use embassy_time::Duration;
+use core::{cell::Cell, num::NonZeroU128, ops::RangeInclusive};
+
+use common::{Command, CommandError, FACTORY_RESET_CONFIRMATION, Region, Station};
+use embassy_futures::{
+    join::{join, join_array, join3},
+    select::{Either, Either4, select, select4},
+};
+use embassy_sync::{
+    blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex},
+    mutex::Mutex,
+    signal::Signal,
+    watch::Watch,
+};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_storage_async::nor_flash::NorFlash;
 use rand_core::{CryptoRng, RngCore};
 use trouble_host::prelude::*;
 
-use crate::storage::{Info, load_info};
+use crate::backlight::{self, MAX_BRIGHTNESS};
+use crate::battery::{self, BatteryReading};
+use crate::clock;
+use crate::input::DEBOUNCE_RANGE_MS;
+use crate::led;
+use crate::lora::{
+    self, ListenMode, LoraPreset, MAX_SENDABLE_MSG_LEN, MAX_TX_POWER_DBM, MIN_TX_POWER_DBM,
+    RX_TIMEOUT_SYMBOLS_RANGE, TEST_RESULT_CAPACITY, TRANSMIT_TIMES_RANGE,
+};
+use crate::stats::{self, PacketStats};
+use crate::storage::{
+    ALLOW_LIST_CAPACITY, HOP_CHANNELS_CAPACITY, Info, NAME_CAPACITY, NO_STATION,
+    SIGNATURE_CAPACITY, StorageError, erase_history, erase_info, load_info, store_info,
+};
+use proto::{BROADCAST_ID, MAX_MSG_LEN, UNSYNCED_TIMESTAMP};
+
+/// Max number of centrals connected at once. Each one costs a GATT event loop of its own, which holds a copy of the
+/// largest notification (the 512 byte inbox message), on top of trouble-host's state for the connection and its L2CAP
+/// channels.
+const CONNECTIONS_MAX: usize = 3;
+
+/// Max number of L2CAP channels, a signaling and an ATT channel for each connection
+const L2CAP_CHANNELS_MAX: usize = 2 * CONNECTIONS_MAX;
+
+/// Size of the L2CAP header in front of each ATT PDU
+const L2CAP_HEADER_SIZE: usize = 4;
+/// Size of the opcode and handle in front of the value in write requests and notifications
+const ATT_VALUE_HEADER_SIZE: usize = 3;
+/// Default ATT MTU, used until the central asks for a larger one
+const DEFAULT_ATT_MTU: u16 = 23;
+
+/// Largest ATT MTU offered when a central exchanges MTUs, bounded by the packet pool's packet size.
+///
+/// Only a central can start the exchange. It gets the smaller of this and what it asks for, or stays on
+/// [`DEFAULT_ATT_MTU`] if it never asks, in which case long values are split across several PDUs like before.
+const ATT_MTU: usize = DefaultPacketPool::MTU - L2CAP_HEADER_SIZE;
+// A message that fits in one LoRa packet can be written or notified in a single PDU
+const _: () = assert!(MAX_MSG_LEN + ATT_VALUE_HEADER_SIZE <= ATT_MTU);
+
+/// Seconds a central can go without reading or writing anything before it's disconnected by default
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u16 = 300;
+
+/// Milliseconds between advertisements the BLE spec allows for connectable advertising
+pub const ADV_INTERVAL_RANGE_MS: RangeInclusive<u16> = 20..=10_240;
+pub const DEFAULT_ADV_INTERVAL_MS: u16 = 160;
+/// BLE TX powers in dBm that can be picked, from the lowest to the highest [`TxPower`]
+pub const BLE_TX_POWER_RANGE_DBM: RangeInclusive<i8> = -40..=20;
+pub const DEFAULT_BLE_TX_POWER_DBM: i8 = 0;
 
-/// Max number of connections
-const CONNECTIONS_MAX: usize = 1;
+/// How long to wait after accepting a write that reboots the device, so the reply reaches the central first
+const REBOOT_DELAY: Duration = Duration::from_millis(500);
+/// How long to wait before advertising again after advertising failed
+const ADV_RETRY_DELAY: Duration = Duration::from_secs(1);
+/// How long to wait before rebooting to apply a new encryption key, so the reply reaches the central and the alert
+/// saying so can be read
+const KEY_REBOOT_DELAY: Duration = Duration::from_secs(2);
 
-/// Max number of L2CAP channels.
-const L2CAP_CHANNELS_MAX: usize = 2; // Signal + att
+/// Max number of bytes in each device info string
+const DEVICE_INFO_CAPACITY: usize = 32;
+const _: () = assert!(env!("CARGO_PKG_VERSION").len() <= DEVICE_INFO_CAPACITY);
+const _: () = assert!(crate::ID.len() <= DEVICE_INFO_CAPACITY);
+const _: () = assert!(MAX_SENDABLE_MSG_LEN <= u16::MAX as usize);
+
+/// Max number of bytes the message characteristic can hold, the most an ATT attribute allows
+pub const MESSAGE_CAPACITY: usize = 512;
 
 // GATT Server definition
 #[gatt_server]
 struct Server {
     service: CustomService,
+    battery_service: BatteryService,
+    device_info_service: DeviceInfoService,
 }
 
 // TODO: share code between FE and FW
 const SERVICE_UUID: u128 = 0xFB94_E026_23E5_4BD9_97D6_74F2_5D57_9393;
 const CHARACTERISTIC_UUID: u128 = 0x9354_50A0_FAC2_4B9E_82FF_13E4_9971_0728;
+const REGION_UUID: u128 = 0xE5F2_2165_F11F_4C23_B01E_158C_E5AC_0D76;
+const FREQUENCY_UUID: u128 = 0xBAEC_F35A_3CCB_4554_9CB2_BC2E_D104_BD60;
+const TX_POWER_UUID: u128 = 0xD237_2CF3_6F56_40DC_8C15_CAB6_C591_DF30;
+const TRANSMIT_TIMES_UUID: u128 = 0x5C0E_8A3B_91D4_4F6A_A2C7_3E1B_08F9_6D24;
+const LISTEN_MODE_UUID: u128 = 0x1F7B_4D92_C6A0_4E38_9B15_E2D8_7A03_C461;
+const ENCRYPTION_KEY_UUID: u128 = 0x6A3E_D18C_52F7_4B09_8E64_C0A9_37B5_12FD;
+const STATION_UUID: u128 = 0xA41C_7E05_3B8D_4F62_90D3_5E17_C28B_6A9F;
+const INBOX_UUID: u128 = 0x2D86_F3A1_0C5E_47B9_B6E2_98D4_1A7F_C350;
+const DEBOUNCE_UUID: u128 = 0x4E8B_2F61_D93A_4C07_B15E_6A2C_F8D4_9037;
+const BRIGHTNESS_UUID: u128 = 0xC5A2_8E17_4D3B_49F0_A6E9_1B7D_03F4_8C62;
+const SCREEN_TIMEOUT_UUID: u128 = 0x0B7E_93C4_58A1_4F26_9D0C_E4B3_71F8_A52D;
+const FACTORY_RESET_UUID: u128 = 0x93D0_4A7E_1F6C_4B25_8D3A_E6B1_07C9_52F8;
+const BATTERY_VOLTAGE_UUID: u128 = 0x7C19_B5E2_64AD_4E0F_A3B8_0D52_F96E_1C47;
+const PACKET_STATS_UUID: u128 = 0x3F62_A8D1_7C04_4E95_B2E7_19C5_D04A_86B3;
+const NAME_UUID: u128 = 0x58F3_C2A9_0E4D_4B71_86A5_D19E_3C07_B24F;
+const TIME_UUID: u128 = 0xE07A_4C93_2B6F_4D18_A5C1_8F3D_69B0_27E4;
+const RELAY_UUID: u128 = 0x86C4_1D7B_E359_4A02_9F6E_3B8A_C2D0_5971;
+const ALLOW_LIST_UUID: u128 = 0xD4A9_6E1F_0B37_4C58_8E2A_71F5_C9B3_604D;
+const EDIT_ALLOW_LIST_UUID: u128 = 0x2B5F_C870_9A14_4E6D_B3C1_E08D_5F27_A946;
+const LORA_PRESET_UUID: u128 = 0xF1E3_7A28_5C90_4B6D_A07F_24D9_8B1C_E356;
+const RX_TIMEOUT_UUID: u128 = 0x6D2B_E094_17C3_4A85_9F61_C8A3_0E5D_B72F;
+const BATTERY_THRESHOLDS_UUID: u128 = 0x3A81_F5C6_0D29_4E7B_B4E8_57A2_9C6D_10F3;
+const COMMAND_UUID: u128 = 0xB92E_6C05_F871_4D3A_8C4B_D6E1_205A_973F;
+const IDLE_TIMEOUT_UUID: u128 = 0x5E07_D3B9_A2C8_4F61_9B4D_8E15_F26A_C073;
+const TEST_RESULT_UUID: u128 = 0xC83F_0E61_9D2A_4B57_A6E4_3F9B_D178_25C0;
+const MAX_MESSAGE_LEN_UUID: u128 = 0x1A94_E7C2_5B08_4D3F_86A1_F0D7_3C52_9B6E;
+const MESSAGE_EXPIRY_UUID: u128 = 0x74B1_C9E3_0A5D_4F28_8E67_D2F4_19A3_C05B;
+const DESTINATION_UUID: u128 = 0x0F5C_82D7_B6E1_4A39_9D24_6C8B_E3F0_517A;
+const ADAPTIVE_SF_UUID: u128 = 0xA6D2_39F0_C41B_4E87_9B53_0E7C_F28D_164A;
+const ADV_INTERVAL_UUID: u128 = 0x4C7E_91A3_D0F2_4B68_85D9_2A6B_E1C3_F047;
+const BLE_TX_POWER_UUID: u128 = 0xE25A_0B86_3F19_4D7C_A4E0_C93D_58B2_716F;
+const SIGNATURE_UUID: u128 = 0x81F4_5D2C_6B3A_4E90_B7D1_4A08_E6C5_93F2;
+const HOP_CHANNELS_UUID: u128 = 0x2A5B_9CB2_D280_4474_88E1_3E47_7BC6_54E5;
+/// Name the device advertises as until it's given one
 const BT_NAME: &str = concat!("LEWOC-", env!("ID"));
+/// Bytes left for the name in an advertising packet, after the flags (3), service UUID (18) and the name's own
+/// length and type (2)
+const AD_NAME_ROOM: usize = 31 - 3 - 18 - 2;
+/// Company ID the status in scan responses is sent under, the one the Bluetooth SIG reserves for testing
+const ADV_STATUS_COMPANY_ID: u16 = 0xFFFF;
+/// Battery percent, RSSI of the last packet received, and [`adv_status`]'s flags
+const ADV_STATUS_SIZE: usize = 3;
+/// The status's AD structure, with its length, type, and company ID, fits in a scan response
+const _: () = assert!(2 + size_of::<u16>() + ADV_STATUS_SIZE <= 31);
+/// Advertising restarts this often, so scanners see a status no older than this
+const ADV_STATUS_REFRESH: Duration = Duration::from_secs(10);
+/// Number of senders on the allow list, then each of their IDs as a little endian `u16`, padded with zeroes
+const ALLOW_LIST_SIZE: usize = 1 + ALLOW_LIST_CAPACITY * size_of::<u16>();
+/// Number of frequencies hopped between, then each of them in Hz as a little endian `u32`, padded with zeroes
+const HOP_CHANNELS_SIZE: usize = 1 + HOP_CHANNELS_CAPACITY * size_of::<u32>();
+/// Written to edit the allow list, followed by the sender ID as a little endian `u16`
+const ALLOW_LIST_ADD: u8 = 0;
+const ALLOW_LIST_REMOVE: u8 = 1;
+/// Written alone to empty the allow list
+const ALLOW_LIST_CLEAR: u8 = 2;
 
 #[gatt_service(uuid = SERVICE_UUID)]
 struct CustomService {
     #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "message", read, value = "Message")]
     #[characteristic(uuid = CHARACTERISTIC_UUID, read, write, value = trouble_host::prelude::HeaplessString::default())]
-    message: trouble_host::prelude::HeaplessString<128>,
+    message: trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    /// Latest message received over LoRa, notified to the central as each one arrives
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "inbox", read, value = "Inbox")]
+    #[characteristic(uuid = INBOX_UUID, read, notify, value = trouble_host::prelude::HeaplessString::default())]
+    inbox: trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    /// Longest message, in bytes, that can be written to the message characteristic and sent
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "max_message_len", read, value = "Max Message Length")]
+    #[characteristic(uuid = MAX_MESSAGE_LEN_UUID, read, value = 0)]
+    max_message_len: u16,
+    /// [`Region`] as a `u8`, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "region", read, value = "Region")]
+    #[characteristic(uuid = REGION_UUID, read, write, value = 0)]
+    region: u8,
+    /// LoRa frequency in Hz, applied after a reboot. Must be in the region's band.
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "frequency", read, value = "Frequency (Hz)")]
+    #[characteristic(uuid = FREQUENCY_UUID, read, write, value = 0)]
+    frequency: u32,
+    /// Frequencies hopped between in place of the frequency, laid out as in [`HOP_CHANNELS_SIZE`], applied after a
+    /// reboot. Each must be in the region's band.
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "hop_channels", read, value = "Hop Channels")]
+    #[characteristic(uuid = HOP_CHANNELS_UUID, read, write, value = [0; HOP_CHANNELS_SIZE])]
+    hop_channels: [u8; HOP_CHANNELS_SIZE],
+    /// LoRa transmit power in dBm, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "tx_power", read, value = "TX Power (dBm)")]
+    #[characteristic(uuid = TX_POWER_UUID, read, write, value = 0)]
+    tx_power: i8,
+    /// Number of times each packet is transmitted, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "transmit_times", read, value = "Transmit Times")]
+    #[characteristic(uuid = TRANSMIT_TIMES_UUID, read, write, value = 0)]
+    transmit_times: u8,
+    /// [`ListenMode`] as a `u8`, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "listen_mode", read, value = "Listen Mode")]
+    #[characteristic(uuid = LISTEN_MODE_UUID, read, write, value = 0)]
+    listen_mode: u8,
+    /// [`LoraPreset`] as a `u8`, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "lora_preset", read, value = "LoRa Preset")]
+    #[characteristic(uuid = LORA_PRESET_UUID, read, write, value = 0)]
+    lora_preset: u8,
+    /// Symbols to wait for a packet after hearing channel activity, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "rx_timeout", read, value = "RX Timeout (symbols)")]
+    #[characteristic(uuid = RX_TIMEOUT_UUID, read, write, value = 0)]
+    rx_timeout_symbols: u16,
+    /// Low then critical battery voltage in mV, each a little endian `u16`, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "battery_thresholds", read, value = "Battery Thresholds (mV)")]
+    #[characteristic(uuid = BATTERY_THRESHOLDS_UUID, read, write, value = [0; 4])]
+    battery_thresholds: [u8; 4],
+    /// Little-endian `u128` encryption key shared by every device that should talk to each other. Write-only so it can't
+    /// be read back out, and the device reboots to apply it.
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "encryption_key", read, value = "Encryption Key")]
+    #[characteristic(uuid = ENCRYPTION_KEY_UUID, write, value = [0; 16])]
+    encryption_key: [u8; 16],
+    /// [`Station`] as a `u8`, or [`NO_STATION`] if none has been picked
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "station", read, value = "Station")]
+    #[characteristic(uuid = STATION_UUID, read, write, value = NO_STATION)]
+    station: u8,
+    /// Button debounce time in ms, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "debounce", read, value = "Button Debounce (ms)")]
+    #[characteristic(uuid = DEBOUNCE_UUID, read, write, value = 0)]
+    debounce_ms: u16,
+    /// Display backlight brightness, 0 to 100%, applied right away
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "brightness", read, value = "Brightness (%)")]
+    #[characteristic(uuid = BRIGHTNESS_UUID, read, write, value = 0)]
+    brightness: u8,
+    /// Seconds without activity before the screen is blanked, or 0 to never blank it, applied right away
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "screen_timeout", read, value = "Screen Timeout (s)")]
+    #[characteristic(uuid = SCREEN_TIMEOUT_UUID, read, write, value = 0)]
+    screen_timeout_secs: u16,
+    /// Seconds a central can go without reading or writing before it's disconnected, or 0 never to, applied right away
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "idle_timeout", read, value = "BLE Idle Timeout (s)")]
+    #[characteristic(uuid = IDLE_TIMEOUT_UUID, read, write, value = 0)]
+    idle_timeout_secs: u16,
+    /// Minutes a message stays on screen before it's replaced with an idle indicator, or 0 to keep it up, applied right
+    /// away
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "message_expiry", read, value = "Message Expiry (min)")]
+    #[characteristic(uuid = MESSAGE_EXPIRY_UUID, read, write, value = 0)]
+    message_expiry_mins: u16,
+    /// Milliseconds between advertisements, applied the next time the device advertises
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "adv_interval", read, value = "Advertising Interval (ms)")]
+    #[characteristic(uuid = ADV_INTERVAL_UUID, read, write, value = 0)]
+    adv_interval_ms: u16,
+    /// BLE transmit power in dBm, applied the next time the device advertises
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "ble_tx_power", read, value = "BLE TX Power (dBm)")]
+    #[characteristic(uuid = BLE_TX_POWER_UUID, read, write, value = 0)]
+    ble_tx_power: i8,
+    /// Name the device advertises as, or empty for the default, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "name", read, value = "Device Name")]
+    #[characteristic(uuid = NAME_UUID, read, write, value = trouble_host::prelude::HeaplessString::default())]
+    name: trouble_host::prelude::HeaplessString<NAME_CAPACITY>,
+    /// Put in front of messages written over BLE, or empty not to sign them, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "signature", read, value = "Message Signature")]
+    #[characteristic(uuid = SIGNATURE_UUID, read, write, value = trouble_host::prelude::HeaplessString::default())]
+    signature: trouble_host::prelude::HeaplessString<SIGNATURE_CAPACITY>,
+    /// 1 to rebroadcast packets from other devices, 0 not to, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "relay", read, value = "Relay")]
+    #[characteristic(uuid = RELAY_UUID, read, write, value = 0)]
+    relay: u8,
+    /// 1 to step the spreading factor up while messages go unacknowledged, 0 not to, applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "adaptive_sf", read, value = "Adaptive SF")]
+    #[characteristic(uuid = ADAPTIVE_SF_UUID, read, write, value = 0)]
+    adaptive_sf: u8,
+    /// Senders whose messages are shown, or everyone if it's empty, laid out as in [`ALLOW_LIST_SIZE`]
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "allow_list", read, value = "Allow List")]
+    #[characteristic(uuid = ALLOW_LIST_UUID, read, value = [0; ALLOW_LIST_SIZE])]
+    allow_list: [u8; ALLOW_LIST_SIZE],
+    /// [`ALLOW_LIST_ADD`] or [`ALLOW_LIST_REMOVE`] and a sender ID, or [`ALLOW_LIST_CLEAR`], applied after a reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "edit_allow_list", read, value = "Edit Allow List")]
+    #[characteristic(uuid = EDIT_ALLOW_LIST_UUID, write, value = [0; 3])]
+    edit_allow_list: [u8; 3],
+    /// Write [`FACTORY_RESET_CONFIRMATION`] to erase all settings and reboot
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "factory_reset", read, value = "Factory Reset")]
+    #[characteristic(uuid = FACTORY_RESET_UUID, write, value = 0)]
+    factory_reset: u32,
+    /// Write a [`Command`]'s opcode and arguments to have the device carry it out
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "command", read, value = "Command")]
+    #[characteristic(uuid = COMMAND_UUID, write, value = [0; Command::MAX_SIZE])]
+    command: [u8; Command::MAX_SIZE],
+    /// Result of each test packet sent by [`Command::SendTestPackets`], notified as it's sent
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "test_result", read, value = "Test Result")]
+    #[characteristic(uuid = TEST_RESULT_UUID, read, notify, value = trouble_host::prelude::HeaplessString::default())]
+    test_result: trouble_host::prelude::HeaplessString<TEST_RESULT_CAPACITY>,
+    /// Write the current time, in seconds, to make this device the fleet's time source
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "time", read, value = "Time")]
+    #[characteristic(uuid = TIME_UUID, write, value = 0)]
+    time: u32,
+    /// ID of the only device messages written from now on are sent to, or [`BROADCAST_ID`] for every device. Not stored
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "destination", read, value = "Destination")]
+    #[characteristic(uuid = DESTINATION_UUID, read, write, value = BROADCAST_ID)]
+    destination: u16,
+    /// Packets sent, received, failed to decrypt, and held off for a busy channel since boot, then failed to decrypt in a
+    /// row, then failed their CRC, each a little endian `u32`
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "packet_stats", read, value = "Packet Stats")]
+    #[characteristic(uuid = PACKET_STATS_UUID, read, notify, value = [0; PacketStats::SER_SIZE])]
+    packet_stats: [u8; PacketStats::SER_SIZE],
+}
+
+/// Standard Battery Service, plus the raw voltage for debugging
+#[gatt_service(uuid = service::BATTERY)]
+struct BatteryService {
+    /// Estimated charge, 0 to 100
+    #[descriptor(uuid = descriptors::VALID_RANGE, read, value = [0, 100])]
+    #[characteristic(uuid = characteristic::BATTERY_LEVEL, read, notify, value = 0)]
+    level: u8,
+    /// Battery voltage in mV
+    #[descriptor(uuid = descriptors::MEASUREMENT_DESCRIPTION, name = "voltage", read, value = "Battery Voltage (mV)")]
+    #[characteristic(uuid = BATTERY_VOLTAGE_UUID, read, notify, value = 0)]
+    voltage: u16,
+}
+
+/// Standard Device Information Service, so a central can tell which build is flashed on a device
+#[gatt_service(uuid = service::DEVICE_INFORMATION)]
+struct DeviceInfoService {
+    /// Cargo version of the firmware
+    #[characteristic(uuid = characteristic::FIRMWARE_REVISION_STRING, read, value = trouble_host::prelude::HeaplessString::default())]
+    firmware_revision: trouble_host::prelude::HeaplessString<DEVICE_INFO_CAPACITY>,
+    /// The `ID` the firmware was built with
+    #[characteristic(uuid = characteristic::SERIAL_NUMBER_STRING, read, value = trouble_host::prelude::HeaplessString::default())]
+    serial_number: trouble_host::prelude::HeaplessString<DEVICE_INFO_CAPACITY>,
+}
+
+/// What every connection being served shares
+struct Shared<'a, S> {
+    storage: &'a Mutex<NoopRawMutex, S>,
+    /// Changed by writes from any central
+    info: Mutex<NoopRawMutex, Info>,
+    msg_signal: &'a Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>>,
+    /// Each one is forwarded here from the signal it's given on, so every connection can notify it
+    received_msgs: Watch<
+        NoopRawMutex,
+        trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+        CONNECTIONS_MAX,
+    >,
+    battery_readings: Watch<NoopRawMutex, BatteryReading, CONNECTIONS_MAX>,
+    stats_changed: Watch<NoopRawMutex, (), CONNECTIONS_MAX>,
+    test_results: Watch<
+        NoopRawMutex,
+        trouble_host::prelude::HeaplessString<TEST_RESULT_CAPACITY>,
+        CONNECTIONS_MAX,
+    >,
+    passkey_signal: &'a Signal<CriticalSectionRawMutex, Option<u32>>,
+    factory_reset_signal: &'a Signal<NoopRawMutex, ()>,
+    /// Number of centrals connected
+    connected: Cell<usize>,
+    /// Connection slot whose pairing passkey is on the display, so another one's pairing ending doesn't take it down
+    pairing_slot: Cell<Option<usize>>,
+}
+
+impl<S> Shared<'_, S> {
+    /// Adds `change` to the number of centrals connected, showing whether any are.
+    fn update_connected(&self, change: isize) {
+        let connected = self.connected.get().saturating_add_signed(change);
+        self.connected.set(connected);
+        crate::display::update_status(|status| status.ble_connected = connected > 0);
+        led::set_connected(connected > 0);
+    }
+
+    /// Shows `passkey` for the pairing in connection slot `slot`, over any other slot's.
+    fn start_pairing(&self, slot: usize, passkey: u32) {
+        self.pairing_slot.set(Some(slot));
+        self.passkey_signal.signal(Some(passkey));
+    }
+
+    /// Takes the passkey down if it's the one for connection slot `slot`.
+    fn end_pairing(&self, slot: usize) {
+        if self.pairing_slot.get() == Some(slot) {
+            self.pairing_slot.set(None);
+            self.passkey_signal.signal(None);
+        }
+    }
 }
 
 /// Run the BLE stack.
+///
+/// Up to [`CONNECTIONS_MAX`] centrals can be connected at once, and the device keeps advertising until they all are.
+/// Messages written by any of them are signaled on `msg_signal`, and ones received over LoRa are taken from
+/// `received_msg_signal` and notified to all of them along with each reading from `battery_signal`. While pairing, the
+/// passkey to show on the display is signaled on `passkey_signal`, then `None` once pairing ends.
+///
+/// Signaling `factory_reset_signal`, from here or elsewhere, erases all settings and reboots.
+#[allow(clippy::too_many_arguments)]
 pub async fn run<C, RNG, S>(
     mut control: cyw43::Control<'static>,
     controller: C,
-    msg_signal: &'static Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<128>>,
+    msg_signal: &'static Signal<
+        NoopRawMutex,
+        trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    >,
+    received_msg_signal: &'static Signal<
+        NoopRawMutex,
+        trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    >,
+    battery_signal: &'static Signal<NoopRawMutex, BatteryReading>,
+    passkey_signal: &'static Signal<CriticalSectionRawMutex, Option<u32>>,
+    factory_reset_signal: &'static Signal<NoopRawMutex, ()>,
     random_generator: &mut RNG,
-    storage: &mut S,
+    storage: &Mutex<NoopRawMutex, S>,
 ) where
     C: Controller,
     RNG: RngCore + CryptoRng,
@@ -49,16 +399,20 @@ pub async fn run<C, RNG, S>(
 
     log::info!("Our address = {address}");
 
-    let mut info = (load_info(storage).await).map_or_else(
-        || {
-            log::info!("using default info");
-            Info::default()
-        },
-        |stored_info| {
+    let info = match load_info(&mut *storage.lock().await).await {
+        Ok(stored_info) => {
             log::info!("got stored info");
             stored_info
-        },
-    );
+        }
+        Err(StorageError::NotFound) => {
+            log::info!("using default info");
+            Info::default()
+        }
+        Err(err) => {
+            log::error!("Failed to load stored info, using default info: {err:?}");
+            Info::default()
+        }
+    };
 
     let mut resources: HostResources<DefaultPacketPool, CONNECTIONS_MAX, L2CAP_CHANNELS_MAX> =
         HostResources::new();
@@ -68,40 +422,136 @@ pub async fn run<C, RNG, S>(
         .set_io_capabilities(IoCapabilities::DisplayOnly);
 
     let Host {
-        mut peripheral,
-        runner,
-        ..
+        peripheral, runner, ..
     } = stack.build();
 
     log::info!("Starting advertising and GATT service");
+    // Copied out, since `info` is changed by writes while the server's up
+    let name = info.name.clone();
+    let name = name.as_deref().unwrap_or(BT_NAME);
     let server = Server::new_with_config(GapConfig::Peripheral(PeripheralConfig {
-        name: BT_NAME,
+        name,
         appearance: &appearance::DISPLAY,
     }))
     .unwrap();
+    show_config(&server, &info);
+    show_device_info(&server);
 
-    let _ = join(ble_task(runner), async {
-        loop {
-            control.gpio_set(0, true).await;
-            match advertise(&mut peripheral, &server).await {
-                Ok(conn) => {
-                    // set up tasks when the connection is established to a central, so they don't run when no one is connected.
-                    gatt_events_task(&mut control, storage, &mut info, msg_signal, &server, &conn)
-                        .await
-                        .unwrap();
-                }
-                Err(e) => {
-                    #[cfg(feature = "defmt")]
-                    let e = defmt::Debug2Format(&e);
-                    log::error!("[adv] error: {e:?}");
-                    panic!("[adv] error: {e:?}");
+    // Only one connection slot advertises at a time
+    let peripheral = Mutex::<NoopRawMutex, _>::new(peripheral);
+    let shared = Shared {
+        storage,
+        info: Mutex::new(info),
+        msg_signal,
+        received_msgs: Watch::new(),
+        battery_readings: Watch::new(),
+        stats_changed: Watch::new(),
+        test_results: Watch::new(),
+        passkey_signal,
+        factory_reset_signal,
+        connected: Cell::new(0),
+        pairing_slot: Cell::new(None),
+    };
+
+    let _ = join3(ble_task(runner), led::run(&mut control), async {
+        let forward = async {
+            let received_msgs = shared.received_msgs.sender();
+            let battery_readings = shared.battery_readings.sender();
+            let stats_changed = shared.stats_changed.sender();
+            let test_results = shared.test_results.sender();
+            loop {
+                match select4(
+                    received_msg_signal.wait(),
+                    battery_signal.wait(),
+                    stats::STATS_CHANGED.wait(),
+                    lora::TEST_RESULT.wait(),
+                )
+                .await
+                {
+                    Either4::First(received_msg) => received_msgs.send(received_msg),
+                    Either4::Second(reading) => battery_readings.send(reading),
+                    Either4::Third(()) => stats_changed.send(()),
+                    Either4::Fourth(result) => test_results.send(result),
                 }
             }
-        }
+        };
+        let serve = join_array(core::array::from_fn::<_, CONNECTIONS_MAX, _>(|slot| {
+            serve_slot(slot, &peripheral, &server, name, &shared)
+        }));
+
+        // Stops advertising and drops every connection
+        select(join(forward, serve), factory_reset_signal.wait()).await;
+        factory_reset(storage).await
     })
     .await;
 }
 
+/// Takes one connection at a time in connection slot `slot`, advertising once it's this slot's turn.
+async fn serve_slot<'values, C: Controller, S: NorFlash>(
+    slot: usize,
+    peripheral: &Mutex<NoopRawMutex, Peripheral<'values, C, DefaultPacketPool>>,
+    server: &Server<'values>,
+    name: &str,
+    shared: &Shared<'_, S>,
+) {
+    loop {
+        // Held until a central connects, then the next free slot takes over advertising
+        let mut peripheral = peripheral.lock().await;
+        // Read each time, so changes apply from the next time the device advertises
+        let (adv_interval_ms, ble_tx_power_dbm) = {
+            let info = shared.info.lock().await;
+            (info.adv_interval_ms, info.ble_tx_power_dbm)
+        };
+        let result = advertise(
+            &mut peripheral,
+            server,
+            name,
+            adv_interval_ms,
+            ble_tx_power_dbm,
+            || adv_status(shared.battery_readings.try_get()),
+        )
+        .await;
+        drop(peripheral);
+        match result {
+            Ok(conn) => {
+                shared.update_connected(1);
+                backlight::wake();
+                // set up tasks when the connection is established to a central, so they don't run when no one is connected.
+                if let Err(e) = gatt_events_task(slot, shared, server, &conn).await {
+                    log::warn!("[gatt] slot {slot} connection ended with error: {e:?}");
+                }
+                shared.update_connected(-1);
+            }
+            Err(e) => {
+                log::warn!("[adv] error: {e:?}");
+                // Don't spin on an error that keeps coming back
+                Timer::after(ADV_RETRY_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Erases all settings and the message history, then reboots, so the device comes up on defaults like a fresh one.
+///
+/// Bonds with centrals are only kept in RAM, so the reboot clears them too.
+async fn factory_reset<S: NorFlash>(storage: &Mutex<NoopRawMutex, S>) -> ! {
+    log::warn!("[reset] Factory resetting");
+    let mut storage = storage.lock().await;
+    match erase_info(&mut *storage).await {
+        Ok(()) => log::warn!("[reset] Erased stored settings"),
+        Err(err) => log::error!("[reset] Failed to erase stored settings: {err:?}"),
+    }
+    match erase_history(&mut *storage).await {
+        Ok(()) => log::warn!("[reset] Erased message history"),
+        Err(err) => log::error!("[reset] Failed to erase message history: {err:?}"),
+    }
+
+    log::warn!("[reset] Rebooting");
+    // Give the last reply and logs time to go out
+    Timer::after(REBOOT_DELAY).await;
+    cortex_m::peripheral::SCB::sys_reset()
+}
+
 /// This is a background task that is required to run forever alongside any other BLE tasks.
 ///
 /// ## Alternative
@@ -120,37 +570,139 @@ pub async fn run<C, RNG, S>(
 async fn ble_task<C: Controller, P: PacketPool>(mut runner: Runner<'_, C, P>) {
     loop {
         if let Err(e) = runner.run().await {
-            #[cfg(feature = "defmt")]
-            let e = defmt::Debug2Format(&e);
             panic!("[ble_task] error: {:?}", e);
         }
     }
 }
 
-/// Stream Events until the connection closes.
+/// Stream Events until the connection in connection slot `slot` closes.
 ///
 /// This function will handle the GATT events and process them.
 /// This is how we interact with read and write requests.
+#[allow(clippy::too_many_lines)]
 async fn gatt_events_task<S: NorFlash>(
-    control: &mut cyw43::Control<'static>,
-    storage: &mut S,
-    info: &mut Info,
-    msg_signal: &Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<128>>,
+    slot: usize,
+    shared: &Shared<'_, S>,
     server: &Server<'_>,
     conn: &GattConnection<'_, '_, DefaultPacketPool>,
 ) -> Result<(), Error> {
+    let storage = shared.storage;
+    // One for each slot, so there's always one free
+    let mut received_msgs = shared.received_msgs.receiver().unwrap();
+    let mut battery_readings = shared.battery_readings.receiver().unwrap();
+    let mut stats_changed = shared.stats_changed.receiver().unwrap();
+    let mut test_results = shared.test_results.receiver().unwrap();
     let message_characteristic = &server.service.message;
+    let key_handle = server.service.encryption_key.handle;
+    let factory_reset_handle = server.service.factory_reset.handle;
+    let time_handle = server.service.time.handle;
+    let destination_handle = server.service.destination.handle;
+    let command_handle = server.service.command.handle;
+
+    // Might have changed while no one was connected
+    if let Err(err) = server.set(&server.service.packet_stats, &stats::get().to_bytes()) {
+        log::error!("[gatt] Failed to show packet stats: {err:?}");
+    }
+
+    // Logged once the central has exchanged MTUs
+    let mut att_mtu = DEFAULT_ATT_MTU;
+    // Pushed back by every read and write, and every received message notified. Battery readings and packet stats
+    // don't count, or a central that walked away would be kept connected by them forever.
+    let mut last_activity = Instant::now();
 
     let reason = loop {
-        match conn.next().await {
+        // Read every time around, so a new timeout applies right away
+        let idle_timeout_secs = shared.info.lock().await.idle_timeout_secs;
+        let idle = async move {
+            if idle_timeout_secs == 0 {
+                core::future::pending().await
+            } else {
+                Timer::at(last_activity + Duration::from_secs(idle_timeout_secs.into())).await;
+            }
+        };
+
+        let event = match select4(
+            select(conn.next(), idle),
+            received_msgs.changed(),
+            battery_readings.changed(),
+            select(stats_changed.changed(), test_results.changed()),
+        )
+        .await
+        {
+            Either4::First(Either::First(event)) => event,
+            Either4::First(Either::Second(())) => {
+                log::warn!(
+                    "[gatt] Disconnecting central, no reads or writes for {idle_timeout_secs}s"
+                );
+                conn.raw().disconnect();
+                // Gives it time to go, rather than disconnecting it again
+                last_activity = Instant::now();
+                continue;
+            }
+            Either4::Second(received_msg) => {
+                last_activity = Instant::now();
+                // Only sets the value if the central hasn't subscribed to notifications
+                if let Err(err) = server.service.inbox.notify(conn, &received_msg).await {
+                    log::error!("[gatt] Failed to notify received message: {err:?}");
+                }
+                continue;
+            }
+            Either4::Third(reading) => {
+                let battery = &server.battery_service;
+                if let Err(err) = battery.level.notify(conn, &reading.percent).await {
+                    log::error!("[gatt] Failed to notify battery level: {err:?}");
+                }
+                if let Err(err) = battery.voltage.notify(conn, &reading.millivolts).await {
+                    log::error!("[gatt] Failed to notify battery voltage: {err:?}");
+                }
+                continue;
+            }
+            Either4::Fourth(Either::First(())) => {
+                let stats = stats::get().to_bytes();
+                if let Err(err) = server.service.packet_stats.notify(conn, &stats).await {
+                    log::error!("[gatt] Failed to notify packet stats: {err:?}");
+                }
+                continue;
+            }
+            Either4::Fourth(Either::Second(result)) => {
+                if let Err(err) = server.service.test_result.notify(conn, &result).await {
+                    log::error!("[gatt] Failed to notify test result: {err:?}");
+                }
+                continue;
+            }
+        };
+
+        let negotiated_mtu = conn.raw().att_mtu();
+        if negotiated_mtu != att_mtu {
+            att_mtu = negotiated_mtu;
+            log::info!("[gatt] Negotiated ATT MTU of {att_mtu}, offered {ATT_MTU}");
+        }
+
+        match event {
             GattConnectionEvent::Disconnected { reason } => break reason,
+            GattConnectionEvent::PassKeyDisplay(passkey) => {
+                log::info!("[gatt] showing pairing passkey");
+                shared.start_pairing(slot, passkey.value());
+            }
             GattConnectionEvent::PairingComplete { security_level, .. } => {
                 log::info!("[gatt] pairing complete: {security_level:?}");
+                shared.end_pairing(slot);
             }
             GattConnectionEvent::PairingFailed(err) => {
                 log::error!("[gatt] pairing error: {err:?}");
+                shared.end_pairing(slot);
             }
             GattConnectionEvent::Gatt { event } => {
+                backlight::wake();
+                last_activity = Instant::now();
+                let key_written =
+                    matches!(&event, GattEvent::Write(write) if write.handle() == key_handle);
+                let factory_reset_written = matches!(
+                    &event,
+                    GattEvent::Write(write) if write.handle() == factory_reset_handle
+                );
+                // Carried out once the write has been accepted
+                let mut command = None;
                 let result = match &event {
                     GattEvent::Read(event) => {
                         if event.handle() == message_characteristic.handle {
@@ -162,23 +714,133 @@ async fn gatt_events_task<S: NorFlash>(
                     }
                     GattEvent::Write(event) => {
                         if event.handle() == message_characteristic.handle {
-                            let mut value = event.value(message_characteristic).unwrap();
-                            if value.len() >= 2 {
-                                // For some god forsaken reason the second byte of the write payload is always 1 less than
-                                // was actually sent by the client/central. So we correct it here before sending it off to wherever else.
-                                // 😭😭😭😭😭😭😭😭
-                                unsafe { value.as_bytes_mut()[1] += 1 }
-                            }
+                            if event.data().len() > MAX_SENDABLE_MSG_LEN {
+                                log::warn!(
+                                    "[gatt] Rejected {}-byte message, max is {MAX_SENDABLE_MSG_LEN}",
+                                    event.data().len()
+                                );
+                                Some(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
+                            } else {
+                                match event.value(message_characteristic) {
+                                    Ok(mut value) => {
+                                        if value.len() >= 2 {
+                                            // For some god forsaken reason the second byte of the write payload is always 1 less than
+                                            // was actually sent by the client/central. So we correct it here before sending it off to wherever else.
+                                            // 😭😭😭😭😭😭😭😭
+                                            unsafe { value.as_bytes_mut()[1] += 1 }
+                                        }
 
-                            log::info!("[gatt] Write to Characteristic: {value}");
-                            msg_signal.signal(value);
+                                        log::info!("[gatt] Write to Characteristic: {value}");
+                                        shared.msg_signal.signal(value);
+                                        None
+                                    }
+                                    // Not UTF-8, or otherwise can't be read as a message
+                                    Err(err) => {
+                                        log::warn!("[gatt] Rejected malformed message: {err:?}");
+                                        Some(AttErrorCode::VALUE_NOT_ALLOWED)
+                                    }
+                                }
+                            }
+                        } else if event.handle() == factory_reset_handle {
+                            match parse(event.data()).map(u32::from_le_bytes) {
+                                Ok(FACTORY_RESET_CONFIRMATION) => {
+                                    log::warn!("[gatt] Factory reset requested by central");
+                                    None
+                                }
+                                Ok(_) => {
+                                    log::warn!("[gatt] Rejected unconfirmed factory reset");
+                                    Some(AttErrorCode::OUT_OF_RANGE)
+                                }
+                                Err(code) => Some(code),
+                            }
+                        } else if event.handle() == command_handle {
+                            match Command::decode(event.data()) {
+                                Ok(Command::ClearHistory) => {
+                                    // Erased before replying, so a failure can be reported
+                                    match erase_history(&mut *storage.lock().await).await {
+                                        Ok(()) => {
+                                            log::warn!("[gatt] Erased message history");
+                                            command = Some(Command::ClearHistory);
+                                            None
+                                        }
+                                        Err(err) => {
+                                            log::error!(
+                                                "[gatt] Failed to erase message history: {err:?}"
+                                            );
+                                            Some(AttErrorCode::UNLIKELY_ERROR)
+                                        }
+                                    }
+                                }
+                                Ok(Command::SendTestPackets) => {
+                                    log::info!("[gatt] Test packets requested by central");
+                                    lora::request_test();
+                                    None
+                                }
+                                Ok(Command::ClearKeyMismatch) => {
+                                    log::info!("[gatt] Cleared key mismatch hint");
+                                    lora::clear_key_mismatch();
+                                    None
+                                }
+                                Ok(decoded) => {
+                                    log::warn!("[gatt] {decoded:?} requested by central");
+                                    command = Some(decoded);
+                                    None
+                                }
+                                Err(err) => {
+                                    log::warn!("[gatt] Rejected invalid command: {err:?}");
+                                    Some(command_error_code(err))
+                                }
+                            }
+                        } else if event.handle() == time_handle {
+                            match parse(event.data()).map(u32::from_le_bytes) {
+                                Ok(UNSYNCED_TIMESTAMP) => Some(AttErrorCode::OUT_OF_RANGE),
+                                Ok(fleet_secs) => {
+                                    clock::set(fleet_secs);
+                                    None
+                                }
+                                Err(code) => Some(code),
+                            }
+                        } else if event.handle() == destination_handle {
+                            match parse(event.data()).map(u16::from_le_bytes) {
+                                Ok(lora::SENDER_ID) => Some(AttErrorCode::VALUE_NOT_ALLOWED),
+                                Ok(destination) => {
+                                    log::info!("[gatt] Sending messages to {destination:04X}");
+                                    lora::set_message_destination(destination);
+                                    None
+                                }
+                                Err(code) => Some(code),
+                            }
+                        } else {
+                            let mut info = shared.info.lock().await;
+                            match write_config(server, &mut info, event.handle(), event.data()) {
+                                Some(Ok(())) => {
+                                    match store_info(&mut *storage.lock().await, &info).await {
+                                        Ok(()) => {
+                                            log::info!(
+                                                "[gatt] Stored config, reboot to apply: {info:?}"
+                                            );
+                                            None
+                                        }
+                                        Err(err) => {
+                                            log::error!("[gatt] Failed to store config: {err:?}");
+                                            Some(AttErrorCode::UNLIKELY_ERROR)
+                                        }
+                                    }
+                                }
+                                Some(Err(code)) => {
+                                    log::warn!("[gatt] Rejected invalid config value");
+                                    Some(code)
+                                }
+                                None => None,
+                            }
                         }
-
-                        None
                     }
                     GattEvent::Other(_) => None,
                 };
 
+                let reboot = key_written && result.is_none();
+                let factory_reset = (factory_reset_written && result.is_none())
+                    || command == Some(Command::FactoryReset);
                 let reply_result = if let Some(code) = result {
                     log::info!("[gatt] Rejected GATT event");
                     event.reject(code)
@@ -193,51 +855,554 @@ async fn gatt_events_task<S: NorFlash>(
                 }
 
                 log::info!("[gatt] Sent GATT reply");
+
+                if reboot {
+                    // Every other device needs the same key to keep talking to this one
+                    log::warn!(
+                        "[gatt] Encryption key changed, rebooting. Devices without the new key can no longer be heard."
+                    );
+                    backlight::wake();
+                    crate::display::REBOOT_SIGNAL.signal(crate::display::KEY_CHANGED_ALERT);
+                    // Already stored, so the new key is used for everything from the next boot
+                    Timer::after(KEY_REBOOT_DELAY).await;
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+
+                if matches!(command, Some(Command::Reboot | Command::ClearHistory)) {
+                    log::warn!("[gatt] Rebooting");
+                    Timer::after(REBOOT_DELAY).await;
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+
+                if factory_reset {
+                    shared.factory_reset_signal.signal(());
+                }
             }
             _ => log::info!("[gatt] Other GATT event ignored"), // ignore other Gatt Connection Events
         }
     };
 
+    // Pairing can't finish once the central is gone
+    shared.end_pairing(slot);
     log::info!("[gatt] disconnected: {reason:?}");
     Ok(())
 }
 
-/// Create an advertiser to use to connect to a BLE Central, and wait for it to connect.
+/// Sets the device info characteristics to the firmware's version and `ID`, and the max message length, none of which
+/// change while the firmware runs.
+fn show_device_info(server: &Server<'_>) {
+    let service = &server.device_info_service;
+    // All fit, checked at compile time
+    let results = [
+        server.set(
+            &service.firmware_revision,
+            &env!("CARGO_PKG_VERSION").try_into().unwrap(),
+        ),
+        server.set(&service.serial_number, &crate::ID.try_into().unwrap()),
+        server.set(
+            &server.service.max_message_len,
+            &MAX_SENDABLE_MSG_LEN.try_into().unwrap(),
+        ),
+    ];
+
+    for err in results.into_iter().filter_map(Result::err) {
+        log::error!("[gatt] Failed to show device info: {err:?}");
+    }
+}
+
+/// Sets the config characteristics to the values in `info`.
+fn show_config(server: &Server<'_>, info: &Info) {
+    let service = &server.service;
+    let results = [
+        server.set(&service.region, &info.region.into()),
+        server.set(&service.frequency, &info.frequency_hz),
+        server.set(
+            &service.hop_channels,
+            &hop_channels_bytes(&info.hop_channels_hz),
+        ),
+        server.set(&service.tx_power, &info.tx_power_dbm),
+        server.set(&service.transmit_times, &info.transmit_times),
+        server.set(&service.listen_mode, &(info.listen_mode as u8)),
+        server.set(&service.lora_preset, &(info.lora_preset as u8)),
+        server.set(&service.rx_timeout_symbols, &info.rx_timeout_symbols),
+        server.set(
+            &service.battery_thresholds,
+            &battery_thresholds_bytes(info.low_battery_mv, info.critical_battery_mv),
+        ),
+        server.set(&service.debounce_ms, &info.debounce_ms),
+        server.set(&service.brightness, &info.brightness),
+        server.set(&service.screen_timeout_secs, &info.screen_timeout_secs),
+        server.set(&service.idle_timeout_secs, &info.idle_timeout_secs),
+        server.set(&service.message_expiry_mins, &info.message_expiry_mins),
+        server.set(&service.adv_interval_ms, &info.adv_interval_ms),
+        server.set(&service.ble_tx_power, &info.ble_tx_power_dbm),
+        server.set(&service.name, &info.name.clone().unwrap_or_default()),
+        server.set(
+            &service.signature,
+            &info.signature.clone().unwrap_or_default(),
+        ),
+        server.set(&service.relay, &info.relay.into()),
+        server.set(&service.adaptive_sf, &info.adaptive_sf.into()),
+        server.set(
+            &service.allow_list,
+            &allow_list_bytes(&info.allowed_senders),
+        ),
+        server.set(
+            &service.station,
+            &info.station.map_or(NO_STATION, Into::into),
+        ),
+    ];
+
+    for err in results.into_iter().filter_map(Result::err) {
+        log::error!("[gatt] Failed to show config: {err:?}");
+    }
+}
+
+/// Applies a write to one of the config characteristics to `info`, if the value is valid.
+///
+/// Returns `None` if `handle` isn't a config characteristic.
+fn write_config(
+    server: &Server<'_>,
+    info: &mut Info,
+    handle: u16,
+    data: &[u8],
+) -> Option<Result<(), AttErrorCode>> {
+    let service = &server.service;
+    let result = if handle == service.region.handle {
+        parse(data).and_then(|[region]: [u8; 1]| {
+            let region = Region::try_from(region).map_err(|_| AttErrorCode::OUT_OF_RANGE)?;
+            info.region = region;
+            if !region.frequency_range_hz().contains(&info.frequency_hz) {
+                // Move to a frequency that's legal in the new region
+                info.frequency_hz = region.default_frequency_hz();
+                let _ = server.set(&service.frequency, &info.frequency_hz);
+            }
+            // Same for hop channels, which are dropped rather than moved so the fleet's schedules still line up
+            let hop_channels_hz = &mut info.hop_channels_hz;
+            let hop_channels_len = hop_channels_hz.len();
+            hop_channels_hz
+                .retain(|frequency_hz| region.frequency_range_hz().contains(frequency_hz));
+            if hop_channels_hz.len() != hop_channels_len {
+                let _ = server.set(&service.hop_channels, &hop_channels_bytes(hop_channels_hz));
+            }
+            Ok(())
+        })
+    } else if handle == service.frequency.handle {
+        parse(data)
+            .map(u32::from_le_bytes)
+            .and_then(|frequency_hz| {
+                if info.region.frequency_range_hz().contains(&frequency_hz) {
+                    info.frequency_hz = frequency_hz;
+                    Ok(())
+                } else {
+                    Err(AttErrorCode::OUT_OF_RANGE)
+                }
+            })
+    } else if handle == service.hop_channels.handle {
+        parse_hop_channels(info.region, data).map(|hop_channels_hz| {
+            info.hop_channels_hz = hop_channels_hz;
+        })
+    } else if handle == service.tx_power.handle {
+        parse(data).map(i8::from_le_bytes).and_then(|tx_power_dbm| {
+            let max_tx_power_dbm = MAX_TX_POWER_DBM.min(info.region.max_tx_power_dbm());
+            if (MIN_TX_POWER_DBM..=max_tx_power_dbm).contains(&tx_power_dbm) {
+                info.tx_power_dbm = tx_power_dbm;
+                Ok(())
+            } else {
+                Err(AttErrorCode::OUT_OF_RANGE)
+            }
+        })
+    } else if handle == service.transmit_times.handle {
+        parse(data).and_then(|[transmit_times]: [u8; 1]| {
+            if TRANSMIT_TIMES_RANGE.contains(&transmit_times) {
+                info.transmit_times = transmit_times;
+                Ok(())
+            } else {
+                Err(AttErrorCode::OUT_OF_RANGE)
+            }
+        })
+    } else if handle == service.rx_timeout_symbols.handle {
+        parse(data)
+            .map(u16::from_le_bytes)
+            .and_then(|rx_timeout_symbols| {
+                if RX_TIMEOUT_SYMBOLS_RANGE.contains(&rx_timeout_symbols) {
+                    info.rx_timeout_symbols = rx_timeout_symbols;
+                    Ok(())
+                } else {
+                    Err(AttErrorCode::OUT_OF_RANGE)
+                }
+            })
+    } else if handle == service.battery_thresholds.handle {
+        parse(data).and_then(|bytes: [u8; 4]| {
+            let low_mv = u16::from_le_bytes([bytes[0], bytes[1]]);
+            let critical_mv = u16::from_le_bytes([bytes[2], bytes[3]]);
+            if battery::THRESHOLD_RANGE_MV.contains(&low_mv)
+                && battery::THRESHOLD_RANGE_MV.contains(&critical_mv)
+                && critical_mv < low_mv
+            {
+                info.low_battery_mv = low_mv;
+                info.critical_battery_mv = critical_mv;
+                Ok(())
+            } else {
+                Err(AttErrorCode::OUT_OF_RANGE)
+            }
+        })
+    } else if handle == service.adv_interval_ms.handle {
+        parse(data)
+            .map(u16::from_le_bytes)
+            .and_then(|adv_interval_ms| {
+                if ADV_INTERVAL_RANGE_MS.contains(&adv_interval_ms) {
+                    info.adv_interval_ms = adv_interval_ms;
+                    Ok(())
+                } else {
+                    Err(AttErrorCode::OUT_OF_RANGE)
+                }
+            })
+    } else if handle == service.ble_tx_power.handle {
+        parse(data)
+            .map(i8::from_le_bytes)
+            .and_then(|ble_tx_power_dbm| {
+                if BLE_TX_POWER_RANGE_DBM.contains(&ble_tx_power_dbm) {
+                    info.ble_tx_power_dbm = ble_tx_power_dbm;
+                    Ok(())
+                } else {
+                    Err(AttErrorCode::OUT_OF_RANGE)
+                }
+            })
+    } else if handle == service.debounce_ms.handle {
+        parse(data).map(u16::from_le_bytes).and_then(|debounce_ms| {
+            if DEBOUNCE_RANGE_MS.contains(&debounce_ms) {
+                info.debounce_ms = debounce_ms;
+                Ok(())
+            } else {
+                Err(AttErrorCode::OUT_OF_RANGE)
+            }
+        })
+    } else if handle == service.brightness.handle {
+        parse(data).and_then(|[brightness]: [u8; 1]| {
+            if brightness <= MAX_BRIGHTNESS {
+                info.brightness = brightness;
+                backlight::set_brightness(brightness);
+                Ok(())
+            } else {
+                Err(AttErrorCode::OUT_OF_RANGE)
+            }
+        })
+    } else if handle == service.screen_timeout_secs.handle {
+        parse(data)
+            .map(u16::from_le_bytes)
+            .map(|screen_timeout_secs| {
+                info.screen_timeout_secs = screen_timeout_secs;
+                backlight::set_screen_timeout(screen_timeout_secs);
+            })
+    } else if handle == service.idle_timeout_secs.handle {
+        parse(data)
+            .map(u16::from_le_bytes)
+            .map(|idle_timeout_secs| info.idle_timeout_secs = idle_timeout_secs)
+    } else if handle == service.message_expiry_mins.handle {
+        parse(data)
+            .map(u16::from_le_bytes)
+            .map(|message_expiry_mins| {
+                info.message_expiry_mins = message_expiry_mins;
+                crate::display::set_message_expiry(message_expiry_mins);
+            })
+    } else if handle == service.name.handle {
+        core::str::from_utf8(data)
+            .map_err(|_| AttErrorCode::VALUE_NOT_ALLOWED)
+            .and_then(|name| {
+                let name: heapless::String<NAME_CAPACITY> = name
+                    .try_into()
+                    .map_err(|()| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)?;
+                // Empty goes back to the default name
+                info.name = (!name.is_empty()).then_some(name);
+                Ok(())
+            })
+    } else if handle == service.signature.handle {
+        core::str::from_utf8(data)
+            .map_err(|_| AttErrorCode::VALUE_NOT_ALLOWED)
+            .and_then(|signature| {
+                let signature: heapless::String<SIGNATURE_CAPACITY> = signature
+                    .try_into()
+                    .map_err(|()| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)?;
+                // Empty stops signing messages
+                info.signature = (!signature.is_empty()).then_some(signature);
+                Ok(())
+            })
+    } else if handle == service.station.handle {
+        parse(data).and_then(|[station]: [u8; 1]| {
+            let station = Station::try_from(station).map_err(|_| AttErrorCode::OUT_OF_RANGE)?;
+            info.station = Some(station);
+            Ok(())
+        })
+    } else if handle == service.encryption_key.handle {
+        parse(data).and_then(|key| {
+            let key =
+                NonZeroU128::new(u128::from_le_bytes(key)).ok_or(AttErrorCode::OUT_OF_RANGE)?;
+            info.encryption_key = Some(key);
+            Ok(())
+        })
+    } else if handle == service.listen_mode.handle {
+        parse(data).and_then(|[listen_mode]: [u8; 1]| {
+            info.listen_mode =
+                ListenMode::try_from(listen_mode).map_err(|_| AttErrorCode::OUT_OF_RANGE)?;
+            Ok(())
+        })
+    } else if handle == service.lora_preset.handle {
+        parse(data).and_then(|[lora_preset]: [u8; 1]| {
+            info.lora_preset =
+                LoraPreset::try_from(lora_preset).map_err(|_| AttErrorCode::OUT_OF_RANGE)?;
+            Ok(())
+        })
+    } else if handle == service.edit_allow_list.handle {
+        let allowed_senders = &mut info.allowed_senders;
+        let result = match data {
+            [ALLOW_LIST_ADD, id @ ..] => parse(id).map(u16::from_le_bytes).and_then(|sender_id| {
+                if allowed_senders.contains(&sender_id) {
+                    Ok(())
+                } else {
+                    allowed_senders
+                        .push(sender_id)
+                        .map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)
+                }
+            }),
+            [ALLOW_LIST_REMOVE, id @ ..] => parse(id).map(u16::from_le_bytes).map(|sender_id| {
+                allowed_senders.retain(|&allowed| allowed != sender_id);
+            }),
+            [ALLOW_LIST_CLEAR] => {
+                allowed_senders.clear();
+                Ok(())
+            }
+            _ => Err(AttErrorCode::VALUE_NOT_ALLOWED),
+        };
+        let _ = server.set(&service.allow_list, &allow_list_bytes(allowed_senders));
+        result
+    } else if handle == service.relay.handle {
+        parse(data).and_then(|[relay]: [u8; 1]| {
+            info.relay = match relay {
+                0 => false,
+                1 => true,
+                _ => return Err(AttErrorCode::OUT_OF_RANGE),
+            };
+            Ok(())
+        })
+    } else if handle == service.adaptive_sf.handle {
+        parse(data).and_then(|[adaptive_sf]: [u8; 1]| {
+            info.adaptive_sf = match adaptive_sf {
+                0 => false,
+                1 => true,
+                _ => return Err(AttErrorCode::OUT_OF_RANGE),
+            };
+            Ok(())
+        })
+    } else {
+        return None;
+    };
+
+    Some(result)
+}
+
+/// `allowed_senders` laid out as in [`ALLOW_LIST_SIZE`].
+#[allow(clippy::cast_possible_truncation)]
+fn allow_list_bytes(allowed_senders: &[u16]) -> [u8; ALLOW_LIST_SIZE] {
+    let mut bytes = [0; ALLOW_LIST_SIZE];
+    // At most ALLOW_LIST_CAPACITY, so always fits
+    bytes[0] = allowed_senders.len() as u8;
+    for (chunk, sender_id) in bytes[1..]
+        .chunks_exact_mut(size_of::<u16>())
+        .zip(allowed_senders)
+    {
+        chunk.copy_from_slice(&sender_id.to_le_bytes());
+    }
+    bytes
+}
+
+/// `hop_channels_hz` laid out as in [`HOP_CHANNELS_SIZE`].
+#[allow(clippy::cast_possible_truncation)]
+fn hop_channels_bytes(hop_channels_hz: &[u32]) -> [u8; HOP_CHANNELS_SIZE] {
+    let mut bytes = [0; HOP_CHANNELS_SIZE];
+    // At most HOP_CHANNELS_CAPACITY, so always fits
+    bytes[0] = hop_channels_hz.len() as u8;
+    for (chunk, frequency_hz) in bytes[1..]
+        .chunks_exact_mut(size_of::<u32>())
+        .zip(hop_channels_hz)
+    {
+        chunk.copy_from_slice(&frequency_hz.to_le_bytes());
+    }
+    bytes
+}
+
+/// Frequencies written to the hop channels characteristic, laid out as in [`HOP_CHANNELS_SIZE`] but without the
+/// padding. Each must be in `region`'s band.
+fn parse_hop_channels(
+    region: Region,
+    data: &[u8],
+) -> Result<heapless::Vec<u32, HOP_CHANNELS_CAPACITY>, AttErrorCode> {
+    let Some((&len, frequencies)) = data.split_first() else {
+        return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+    };
+    if frequencies.len() != usize::from(len) * size_of::<u32>() {
+        return Err(AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH);
+    }
+
+    let mut hop_channels_hz = heapless::Vec::new();
+    for frequency_hz in frequencies.chunks_exact(size_of::<u32>()) {
+        let frequency_hz = u32::from_le_bytes(parse(frequency_hz)?);
+        if !region.frequency_range_hz().contains(&frequency_hz) {
+            return Err(AttErrorCode::OUT_OF_RANGE);
+        }
+        hop_channels_hz
+            .push(frequency_hz)
+            .map_err(|_| AttErrorCode::INSUFFICIENT_RESOURCES)?;
+    }
+    Ok(hop_channels_hz)
+}
+
+/// Battery thresholds laid out as the battery thresholds characteristic holds them.
+fn battery_thresholds_bytes(low_mv: u16, critical_mv: u16) -> [u8; 4] {
+    let mut bytes = [0; 4];
+    bytes[..2].copy_from_slice(&low_mv.to_le_bytes());
+    bytes[2..].copy_from_slice(&critical_mv.to_le_bytes());
+    bytes
+}
+
+/// ATT error a rejected write to the command characteristic is answered with
+const fn command_error_code(err: CommandError) -> AttErrorCode {
+    match err {
+        CommandError::WrongLength => AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH,
+        CommandError::UnknownOpcode => AttErrorCode::VALUE_NOT_ALLOWED,
+        CommandError::Unconfirmed => AttErrorCode::OUT_OF_RANGE,
+    }
+}
+
+/// Reads a fixed-size value written by a central.
+fn parse<const N: usize>(data: &[u8]) -> Result<[u8; N], AttErrorCode> {
+    data.try_into()
+        .map_err(|_| AttErrorCode::INVALID_ATTRIBUTE_VALUE_LENGTH)
+}
+
+/// The highest [`TxPower`] at or below `dbm`, or the lowest there is.
+const fn ble_tx_power(dbm: i8) -> TxPower {
+    match dbm {
+        20.. => TxPower::Plus20dBm,
+        18..=19 => TxPower::Plus18dBm,
+        16..=17 => TxPower::Plus16dBm,
+        14..=15 => TxPower::Plus14dBm,
+        12..=13 => TxPower::Plus12dBm,
+        10..=11 => TxPower::Plus10dBm,
+        8..=9 => TxPower::Plus8dBm,
+        7 => TxPower::Plus7dBm,
+        6 => TxPower::Plus6dBm,
+        5 => TxPower::Plus5dBm,
+        4 => TxPower::Plus4dBm,
+        3 => TxPower::Plus3dBm,
+        2 => TxPower::Plus2dBm,
+        0..=1 => TxPower::ZerodBm,
+        -4..=-1 => TxPower::Minus4dBm,
+        -8..=-5 => TxPower::Minus8dBm,
+        -12..=-9 => TxPower::Minus12dBm,
+        -16..=-13 => TxPower::Minus16dBm,
+        -20..=-17 => TxPower::Minus20dBm,
+        ..=-21 => TxPower::Minus40dBm,
+    }
+}
+
+/// Status for scanners to see without connecting, laid out as:
+///
+/// - Battery charge, 0 to 100%, or `0xFF` before the first reading
+/// - RSSI of the last packet received, as an `i8` in dBm, or `0x7F` if nothing has been received yet
+/// - Flags: bit 0 is set while this device is repeating a call for help, bit 1 while its battery is low
+fn adv_status(battery: Option<BatteryReading>) -> [u8; ADV_STATUS_SIZE] {
+    let status = crate::display::status();
+    let rssi = crate::display::last_rssi().map_or(i8::MAX, |rssi| {
+        // Clamped to fit, and below i8::MAX so it's never mistaken for no packet
+        i8::try_from(rssi.clamp(i16::from(i8::MIN), 0)).unwrap()
+    });
+    let flags = u8::from(status.emergency_broadcast) | (u8::from(status.low_battery) << 1);
+    [
+        battery.map_or(u8::MAX, |reading| reading.percent),
+        rssi.to_le_bytes()[0],
+        flags,
+    ]
+}
+
+/// Advertises until a central connects, restarting every [`ADV_STATUS_REFRESH`] with a fresh `status` in the scan
+/// response.
+///
+/// `name` is shortened if it doesn't fit in the advertising packet. The GAP name still has all of it.
 async fn advertise<'values, 'server, C: Controller>(
     peripheral: &mut Peripheral<'values, C, DefaultPacketPool>,
     server: &'server Server<'values>,
+    name: &str,
+    interval_ms: u16,
+    tx_power_dbm: i8,
+    status: impl Fn() -> [u8; ADV_STATUS_SIZE],
 ) -> Result<GattConnection<'values, 'server, DefaultPacketPool>, BleHostError<C::Error>> {
+    // Stored values could be from a build with other bounds
+    let interval = Duration::from_millis(
+        interval_ms
+            .clamp(*ADV_INTERVAL_RANGE_MS.start(), *ADV_INTERVAL_RANGE_MS.end())
+            .into(),
+    );
+    let name_ad = if name.len() <= AD_NAME_ROOM {
+        AdStructure::CompleteLocalName(name.as_bytes())
+    } else {
+        // Cut on a char boundary, so the short name is still valid UTF-8
+        let end = (0..=AD_NAME_ROOM)
+            .rev()
+            .find(|&end| name.is_char_boundary(end))
+            .unwrap_or(0);
+        AdStructure::ShortenedLocalName(&name.as_bytes()[..end])
+    };
     let mut advertiser_data = [0; 31];
     let len = AdStructure::encode_slice(
         &[
-            AdStructure::CompleteLocalName(BT_NAME.as_bytes()),
+            name_ad,
             AdStructure::Flags(LE_GENERAL_DISCOVERABLE | BR_EDR_NOT_SUPPORTED),
             AdStructure::ServiceUuids128(&[SERVICE_UUID.to_le_bytes()]),
         ],
         &mut advertiser_data[..],
     )?;
-    let advertiser = peripheral
-        .advertise(
-            &AdvertisementParameters {
-                primary_phy: PhyKind::Le1M,
-                secondary_phy: PhyKind::Le1M,
-                tx_power: TxPower::ZerodBm,
-                timeout: None,
-                max_events: None,
-                interval_min: Duration::from_millis(160),
-                interval_max: Duration::from_millis(160),
-                filter_policy: AdvFilterPolicy::default(),
-                channel_map: None,
-                fragment: false,
-            },
-            Advertisement::ConnectableScannableUndirected {
-                adv_data: &advertiser_data[..len],
-                scan_data: &[],
-            },
-        )
-        .await?;
-    log::info!("[adv] advertising");
-    let conn = advertiser.accept().await?.with_attribute_server(server)?;
-    log::info!("[adv] connection established");
-    Ok(conn)
+    log::info!(
+        "[adv] advertising every {}ms at {tx_power_dbm} dBm",
+        interval.as_millis()
+    );
+    loop {
+        let status_bytes = status();
+        let mut scan_data = [0; 31];
+        let scan_len = AdStructure::encode_slice(
+            &[AdStructure::ManufacturerSpecificData {
+                company_identifier: ADV_STATUS_COMPANY_ID,
+                payload: &status_bytes,
+            }],
+            &mut scan_data[..],
+        )?;
+        let advertiser = peripheral
+            .advertise(
+                &AdvertisementParameters {
+                    primary_phy: PhyKind::Le1M,
+                    secondary_phy: PhyKind::Le1M,
+                    tx_power: ble_tx_power(tx_power_dbm),
+                    timeout: None,
+                    max_events: None,
+                    interval_min: interval,
+                    interval_max: interval,
+                    filter_policy: AdvFilterPolicy::default(),
+                    channel_map: None,
+                    fragment: false,
+                },
+                Advertisement::ConnectableScannableUndirected {
+                    adv_data: &advertiser_data[..len],
+                    scan_data: &scan_data[..scan_len],
+                },
+            )
+            .await?;
+        // Dropping the advertiser stops advertising, so it's restarted with the new status
+        if let Either::First(accepted) =
+            select(advertiser.accept(), Timer::after(ADV_STATUS_REFRESH)).await
+        {
+            let conn = accepted?.with_attribute_server(server)?;
+            log::info!("[adv] connection established");
+            return Ok(conn);
+        }
+    }
 }
@@ -0,0 +1,76 @@
+//! Time shared across the fleet, without any device needing a real-time clock.
+//!
+//! A phone writes the time to one device over BLE, making it the fleet's time source. It broadcasts the time in a
+//! time sync packet every [`SYNC_INTERVAL`], and every device that hears one adopts it. Each device only keeps the
+//! offset between the fleet's time and its own uptime, so its clock runs on [`Instant`] from then on.
+//!
+//! Fleet time is in seconds, usually since the Unix epoch, but nothing depends on that.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
+use proto::fleet_time::{offset_secs, to_fleet_secs, to_uptime_secs};
+
+/// How often the time source broadcasts the time
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy)]
+struct ClockSync {
+    /// Fleet time minus uptime, in seconds
+    offset_secs: i64,
+    /// Whether the time was set on this device, rather than adopted from a time sync packet
+    is_source: bool,
+}
+
+static SYNC: Mutex<CriticalSectionRawMutex, Cell<Option<ClockSync>>> = Mutex::new(Cell::new(None));
+/// Signaled when the time is set on this device, so it's broadcast right away instead of at the next interval
+pub static TIME_SET: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Sets the fleet's time to `fleet_secs`, making this device the time source.
+pub fn set(fleet_secs: u32) {
+    log::info!("Time set to {fleet_secs}, broadcasting it");
+    store(fleet_secs, true);
+    TIME_SET.signal(());
+}
+
+/// Adopts `fleet_secs` from a time sync packet, unless this device is the time source.
+pub fn adopt(fleet_secs: u32) {
+    if is_source() {
+        return;
+    }
+    log::debug!("Adopted time {fleet_secs}");
+    store(fleet_secs, false);
+}
+
+fn store(fleet_secs: u32, is_source: bool) {
+    let sync = ClockSync {
+        offset_secs: offset_secs(fleet_secs, Instant::now().as_secs()),
+        is_source,
+    };
+    SYNC.lock(|cell| cell.set(Some(sync)));
+}
+
+/// Whether the time was set on this device, so it should broadcast it.
+pub fn is_source() -> bool {
+    SYNC.lock(Cell::get).is_some_and(|sync| sync.is_source)
+}
+
+/// Fleet time now, or `None` if this device has never been synced.
+pub fn now() -> Option<u32> {
+    let sync = SYNC.lock(Cell::get)?;
+    to_fleet_secs(sync.offset_secs, Instant::now().as_secs())
+}
+
+/// When a fleet time happened on this device's clock, or `None` if it's never been synced. Times from before it
+/// booted are clamped to boot.
+pub fn to_local(fleet_secs: u32) -> Option<Instant> {
+    let sync = SYNC.lock(Cell::get)?;
+    Some(Instant::from_secs(to_uptime_secs(
+        sync.offset_secs,
+        fleet_secs,
+    )))
+}
@@ -1,17 +1,279 @@
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use common::Station;
 use embassy_rp::{
     Peri,
     gpio::{self, Output},
 };
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    channel::Channel,
+    signal::Signal,
+};
+use embassy_time::{Duration, Instant};
 use embedded_graphics_coordinate_transform::Rotate90;
 use embedded_hal::spi::SpiDevice;
+use graphics::{Diagnostics, HistoryEntry, MessageAge, MessageStyle, Status};
+use heapless::Deque;
 
+/// Draws onto the screen, without keeping track of what's on it. [`Screen`] keeps the message and everything drawn
+/// over it, and redraws or clears the screen through this.
 pub struct Display<'d, T: SpiDevice> {
     pub display: Rotate90<st7735_lcd::ST7735<T, Output<'d>, Output<'d>>>,
 }
 
+/// Max number of bytes of a message that can be sent to the display
+pub const MAX_DISPLAY_LEN: usize = 128;
+
+/// Messages too long for the screen scroll up a line this often
+pub const SCROLL_INTERVAL: Duration = Duration::from_millis(1500);
+
+/// Number of [`DisplayMessage`]s core0 can hand over before it has to wait for core1 to take one
+pub const DISPLAY_CHANNEL_CAPACITY: usize = 4;
+
+/// Each message stays on screen at least this long before a newer one replaces it, so when several devices reply at
+/// once each reply can be read. Newer ones wait in a queue of [`MESSAGE_QUEUE_CAPACITY`] meanwhile. Alerts and
+/// emergencies don't wait, since they're drawn over the message.
+pub const MIN_MESSAGE_DWELL: Duration = Duration::from_secs(3);
+/// Max number of messages waiting for the one on screen to have been up for [`MIN_MESSAGE_DWELL`]. Once full, the
+/// oldest is shown early to make room.
+const MESSAGE_QUEUE_CAPACITY: usize = 4;
+
+/// How long the splash screen stays up after boot
+const SPLASH_DURATION: Duration = Duration::from_secs(2);
+
+/// Emergency alerts swap their colors this often until they're acknowledged
+pub const EMERGENCY_BLINK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Whether an emergency alert is up and hasn't been acknowledged yet
+static EMERGENCY_SHOWING: AtomicBool = AtomicBool::new(false);
+
+/// Number of recent messages kept for the history view, and in flash across reboots
+pub const HISTORY_CAPACITY: usize = 8;
+
+/// Messages stay on screen until the next one by default
+pub const DEFAULT_MESSAGE_EXPIRY_MINS: u16 = 0;
+/// Shown in place of a message once it's been up longer than the message expiry
+const IDLE_MESSAGE: &str = "No recent messages";
+/// Signaled with how many minutes messages stay on screen, or 0 to keep them up until the next one
+pub static MESSAGE_EXPIRY_SIGNAL: Signal<CriticalSectionRawMutex, u16> = Signal::new();
+/// Signaled with an alert to show while the device is about to reboot, like [`KEY_CHANGED_ALERT`]
+pub static REBOOT_SIGNAL: Signal<CriticalSectionRawMutex, &'static str> = Signal::new();
+/// Shown once a new encryption key is stored, until the device reboots to apply it
+pub const KEY_CHANGED_ALERT: &str = "Rebooting to apply key";
+/// Longer than any reboot takes to start, so the alert stays up until then
+pub const REBOOT_ALERT_DURATION: Duration = Duration::from_secs(10);
+
+/// Whether the history view is open, so button presses go to it rather than being sent
+static HISTORY_OPEN: AtomicBool = AtomicBool::new(false);
+/// Moves through the history view, sent from the input task on core0
+pub static HISTORY_CHANNEL: Channel<CriticalSectionRawMutex, HistoryNav, 4> = Channel::new();
+
+/// Ways to move through the history view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryNav {
+    Open,
+    Close,
+    /// One message further back
+    Older,
+    /// One message closer to the latest
+    Newer,
+}
+
+/// Whether the diagnostics screen is open
+static DIAGNOSTICS_OPEN: AtomicBool = AtomicBool::new(false);
+/// What the diagnostics screen shows, updated from the LoRa task on core0
+static DIAGNOSTICS: Mutex<CriticalSectionRawMutex, Cell<Diagnostics>> =
+    Mutex::new(Cell::new(Diagnostics::new()));
+/// Signaled when the diagnostics screen opens or closes, or what it shows changes while it's open
+pub static DIAGNOSTICS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// What the status bar shows, updated from either core
+static STATUS: Mutex<CriticalSectionRawMutex, Cell<Status>> = Mutex::new(Cell::new(Status::new()));
+/// Signaled whenever [`STATUS`] changes, so the status bar gets redrawn
+pub static STATUS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Changes what the status bar shows.
+pub fn update_status(update: impl FnOnce(&mut Status)) {
+    STATUS.lock(|cell| {
+        let mut status = cell.get();
+        update(&mut status);
+        cell.set(status);
+    });
+    STATUS_CHANGED.signal(());
+}
+
+/// What the status bar should show right now.
+pub fn status() -> Status {
+    STATUS.lock(Cell::get)
+}
+
+/// Changes what the diagnostics screen shows, redrawing it if it's open.
+pub fn update_diagnostics(update: impl FnOnce(&mut Diagnostics)) {
+    DIAGNOSTICS.lock(|cell| {
+        let mut diagnostics = cell.get();
+        update(&mut diagnostics);
+        cell.set(diagnostics);
+    });
+    if DIAGNOSTICS_OPEN.load(Ordering::Relaxed) {
+        DIAGNOSTICS_CHANGED.signal(());
+    }
+}
+
+/// RSSI of the last packet received, in dBm, whether or not the diagnostics screen is open.
+pub fn last_rssi() -> Option<i16> {
+    DIAGNOSTICS.lock(Cell::get).rssi
+}
+
+/// What the diagnostics screen should show right now, or `None` if it's closed.
+pub fn diagnostics() -> Option<Diagnostics> {
+    DIAGNOSTICS_OPEN
+        .load(Ordering::Relaxed)
+        .then(|| DIAGNOSTICS.lock(Cell::get))
+}
+
+/// Opens the diagnostics screen if it's closed, or closes it if it's open.
+pub fn toggle_diagnostics() {
+    let was_open = DIAGNOSTICS_OPEN.fetch_xor(true, Ordering::Relaxed);
+    log::info!(
+        "Diagnostics screen {}",
+        if was_open { "closed" } else { "opened" }
+    );
+    DIAGNOSTICS_CHANGED.signal(());
+}
+
+/// Changes how many minutes messages stay on screen before they're replaced with an idle indicator. 0 keeps them up
+/// until the next one.
+pub fn set_message_expiry(mins: u16) {
+    MESSAGE_EXPIRY_SIGNAL.signal(mins);
+}
+
+/// Acknowledges the emergency alert on screen, returning whether there was one. It's taken down on its next blink.
+pub fn acknowledge_emergency() -> bool {
+    EMERGENCY_SHOWING.swap(false, Ordering::Relaxed)
+}
+
+/// Whether the history view is open.
+pub fn history_open() -> bool {
+    HISTORY_OPEN.load(Ordering::Relaxed)
+}
+
+/// Opens, closes, or moves through the history view. Dropped if the display is too far behind to take it.
+pub fn navigate_history(nav: HistoryNav) {
+    match nav {
+        HistoryNav::Open => HISTORY_OPEN.store(true, Ordering::Relaxed),
+        HistoryNav::Close => HISTORY_OPEN.store(false, Ordering::Relaxed),
+        HistoryNav::Older | HistoryNav::Newer => {}
+    }
+    if HISTORY_CHANNEL.try_send(nav).is_err() {
+        log::warn!("Dropped history navigation: {nav:?}");
+    }
+}
+
+/// When a message that was just drawn should first scroll, or `None` if it fits on the screen.
+fn first_scroll_at(message: &str) -> Option<Instant> {
+    (graphics::max_scroll_offset(message, &MessageStyle::DEFAULT) > 0)
+        .then(|| Instant::now() + SCROLL_INTERVAL)
+}
+
 pub enum DisplayMessage {
     None,
-    Message(heapless::String<128>),
+    Message(heapless::String<MAX_DISPLAY_LEN>),
+    /// Received from another device, which sent it at `sent_at`, or `None` if that's unknown
+    Received {
+        text: heapless::String<MAX_DISPLAY_LEN>,
+        sent_at: Option<Instant>,
+    },
+    /// Shown over the current message for `duration`, then the current message comes back. Messages arriving
+    /// meanwhile replace the current message, but are only drawn once the alert is over.
+    Alert {
+        text: heapless::String<MAX_DISPLAY_LEN>,
+        duration: Duration,
+    },
+    /// Covers the whole screen, blinking, until acknowledged with a button press
+    Emergency(heapless::String<MAX_DISPLAY_LEN>),
+    /// Received before the last reboot, only added to the history view
+    Replayed(heapless::String<MAX_DISPLAY_LEN>),
+}
+
+impl DisplayMessage {
+    /// Creates a [`DisplayMessage::Received`] prefixed with the ID of the device that sent it, the signal strength it
+    /// was received with, and whether it was sent `direct` to only this device, cutting `text` off at the last full
+    /// character that fits.
+    pub fn from_sender(
+        sender_id: u16,
+        direct: bool,
+        signal_bars: u8,
+        sent_at: Option<Instant>,
+        text: &str,
+    ) -> Self {
+        let prefix = common::SenderPrefix {
+            sender_id,
+            signal_bars,
+            direct,
+        };
+        Self::Received {
+            text: truncated(format_args!("{prefix}"), text),
+            sent_at,
+        }
+    }
+
+    /// Creates a [`DisplayMessage::Message`] of just `text`, cutting it off at the last full character that fits.
+    pub fn from_text(text: &str) -> Self {
+        Self::with_prefix(format_args!(""), text)
+    }
+
+    /// Creates a [`DisplayMessage::Message`] telling the user whether another device acknowledged what they sent.
+    pub fn delivery_status(delivered: bool, text: &str) -> Self {
+        Self::with_prefix(format_args!("{}", common::delivery_prefix(delivered)), text)
+    }
+
+    /// Creates a [`DisplayMessage::Alert`] of `text` shown for `duration`, cutting it off at the last full character
+    /// that fits.
+    pub fn alert(text: &str, duration: Duration) -> Self {
+        Self::Alert {
+            text: truncated(format_args!(""), text),
+            duration,
+        }
+    }
+
+    /// Creates a [`DisplayMessage::Emergency`] asking for help at the sender's station, or from the sender's ID if it
+    /// hasn't picked one.
+    pub fn help_needed(sender_id: u16, station: Option<Station>) -> Self {
+        let help = common::HelpNeeded { sender_id, station };
+        // Station names are short enough to always fit
+        Self::Emergency(truncated(format_args!("{help}"), ""))
+    }
+
+    /// Creates a [`DisplayMessage::Replayed`] prefixed with the ID of the device that sent it. The signal strength
+    /// isn't kept, so it's left out.
+    pub fn replayed(sender_id: u16, text: &str) -> Self {
+        Self::Replayed(truncated(format_args!("{sender_id:04X}: "), text))
+    }
+
+    fn with_prefix(prefix: fmt::Arguments<'_>, text: &str) -> Self {
+        Self::Message(truncated(prefix, text))
+    }
+}
+
+/// `prefix` followed by as much of `text` as fits on the display
+pub fn truncated(prefix: fmt::Arguments<'_>, text: &str) -> heapless::String<MAX_DISPLAY_LEN> {
+    let mut msg = heapless::String::new();
+    msg.write_fmt(prefix).unwrap();
+
+    let mut end = text.len().min(msg.capacity() - msg.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    if end < text.len() {
+        log::warn!("Truncated {}-byte message to fit the display", text.len());
+    }
+
+    msg.push_str(&text[..end]).unwrap();
+    msg
 }
 
 impl<'d, T: SpiDevice> Display<'d, T> {
@@ -37,15 +299,568 @@ impl<'d, T: SpiDevice> Display<'d, T> {
             log::error!("error setup display: {err:?}");
         }
 
-        let mut display = Rotate90::new(display);
+        Display {
+            display: Rotate90::new(display),
+        }
+    }
+
+    /// Clears the whole screen.
+    pub fn clear(&mut self) {
+        Self::log_error(graphics::fill(&mut self.display));
+    }
+
+    /// Redraws the whole screen, with the status bar above the message.
+    pub fn draw(&mut self, status: &Status, message: &str) {
+        self.draw_styled(status, message, &MessageStyle::DEFAULT);
+    }
+
+    /// Redraws the whole screen, with the status bar above the message in `style`.
+    pub fn draw_styled(&mut self, status: &Status, message: &str, style: &MessageStyle) {
+        let result = graphics::fill(&mut self.display)
+            .and_then(|()| graphics::draw_status_bar(&mut self.display, status))
+            .and_then(|()| graphics::draw_message_styled(&mut self.display, message, style));
+        Self::log_error(result);
+    }
+
+    /// Redraws only the message, scrolled up by `offset` pixels.
+    pub fn draw_scrolled(&mut self, message: &str, offset: u32) {
+        Self::log_error(graphics::draw_message_scrolled(
+            &mut self.display,
+            message,
+            &MessageStyle::DEFAULT,
+            offset,
+        ));
+    }
+
+    /// Redraws only the status bar, leaving the message as is.
+    pub fn draw_status(&mut self, status: &Status) {
+        Self::log_error(graphics::draw_status_bar(&mut self.display, status));
+    }
 
-        graphics::fill(&mut display);
-        graphics::draw_message(&mut display, "Waiting for hard coded string cause yoni slow wiring");
-        Display { display }
+    /// Draws the passkey the user types into their phone to pair with this device.
+    pub fn draw_passkey(&mut self, status: &Status, passkey: u32) {
+        let mut message = heapless::String::<32>::new();
+        write!(message, "Pairing code:\n{passkey:06}").unwrap();
+        self.draw(status, &message);
     }
 
-    pub fn draw(&mut self, message: &str) {
-        graphics::fill(&mut self.display);
-        graphics::draw_message(&mut self.display, message);
+    /// Covers the whole screen with the splash screen.
+    pub fn draw_splash(&mut self, station: Option<Station>) {
+        Self::log_error(graphics::draw_splash(
+            &mut self.display,
+            crate::ID,
+            env!("CARGO_PKG_VERSION"),
+            station,
+        ));
+    }
+
+    /// Covers the whole screen with recent messages, newest first, starting from `entries[first]`.
+    pub fn draw_history(&mut self, entries: &[HistoryEntry<'_>], first: usize) {
+        Self::log_error(graphics::draw_history(&mut self.display, entries, first));
+    }
+
+    /// Covers the whole screen with the radio's config and last received signal.
+    pub fn draw_diagnostics(&mut self, diagnostics: &Diagnostics) {
+        Self::log_error(graphics::draw_diagnostics(&mut self.display, diagnostics));
+    }
+
+    /// Covers the whole screen with an emergency alert, with its colors swapped if `inverted`.
+    pub fn draw_emergency(&mut self, text: &str, inverted: bool) {
+        Self::log_error(graphics::draw_emergency(&mut self.display, text, inverted));
+    }
+
+    /// Logs a failed draw rather than panicking, since the display can glitch for a moment and the next draw is
+    /// likely to work. What's on screen may be left half drawn until then.
+    fn log_error<E: fmt::Debug>(result: Result<(), E>) {
+        if let Err(err) = result {
+            log::error!("Error drawing to display: {err:?}");
+        }
+    }
+}
+
+/// An emergency alert on screen
+struct Emergency {
+    text: heapless::String<MAX_DISPLAY_LEN>,
+    inverted: bool,
+    next_blink: Instant,
+}
+
+/// When a message in the history was sent, or received if the sender or this device has never synced its clock
+#[derive(Debug, Clone, Copy)]
+enum SentAt {
+    At(Instant),
+    /// Received before the last reboot
+    BeforeRestart,
+}
+
+/// What can be on screen, most important first. Only the most important one there is gets drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Layer {
+    /// Nothing, while the device is idle
+    Blank,
+    Passkey,
+    Emergency,
+    Splash,
+    Diagnostics,
+    History,
+    Alert,
+    Message,
+}
+
+/// Keeps track of everything that could be on screen, drawing whichever [`Layer`] matters most.
+pub struct Screen<'d, T: SpiDevice> {
+    display: Display<'d, T>,
+    status: Status,
+    message: heapless::String<MAX_DISPLAY_LEN>,
+    /// When the message was sent, or received if that's unknown, which is what its age counts from
+    message_sent_at: Instant,
+    /// How long messages stay up before they're replaced with [`IDLE_MESSAGE`], or `None` to keep them up
+    message_expiry: Option<Duration>,
+    /// Whether the message has been up longer than [`Screen::message_expiry`], so [`IDLE_MESSAGE`] is shown instead
+    message_expired: bool,
+    /// Messages waiting for the one on screen to have been up for [`MIN_MESSAGE_DWELL`], oldest first, and when each
+    /// was sent, or received if that's unknown
+    queued: Deque<(heapless::String<MAX_DISPLAY_LEN>, Instant), MESSAGE_QUEUE_CAPACITY>,
+    /// When the message on screen will have been up for [`MIN_MESSAGE_DWELL`], so a queued one can replace it
+    dwell_until: Option<Instant>,
+    /// How far the message is scrolled, for messages too long for the screen
+    scroll_offset: u32,
+    next_scroll: Option<Instant>,
+    /// Alert text and when it ends
+    alert: Option<(heapless::String<MAX_DISPLAY_LEN>, Instant)>,
+    emergency: Option<Emergency>,
+    passkey: Option<u32>,
+    /// Recent messages and when they were sent, oldest first
+    history: Deque<(SentAt, heapless::String<MAX_DISPLAY_LEN>), HISTORY_CAPACITY>,
+    /// Index of the newest message shown in the history view, 0 being the latest, or `None` if it's closed
+    history_first: Option<usize>,
+    /// When the splash screen comes down, or `None` once it has
+    splash_until: Option<Instant>,
+    /// What the diagnostics screen shows, or `None` if it's closed
+    diagnostics: Option<Diagnostics>,
+    blanked: bool,
+}
+
+impl<'d, T: SpiDevice> Screen<'d, T> {
+    /// Starts out showing the splash screen for [`SPLASH_DURATION`].
+    pub fn new(display: Display<'d, T>, status: Status) -> Self {
+        let mut screen = Self {
+            display,
+            status,
+            message: heapless::String::new(),
+            message_sent_at: Instant::now(),
+            message_expiry: None,
+            message_expired: false,
+            queued: Deque::new(),
+            dwell_until: None,
+            scroll_offset: 0,
+            next_scroll: None,
+            alert: None,
+            emergency: None,
+            passkey: None,
+            history: Deque::new(),
+            history_first: None,
+            splash_until: Some(Instant::now() + SPLASH_DURATION),
+            diagnostics: None,
+            blanked: false,
+        };
+        screen.redraw();
+        screen
+    }
+
+    /// Replaces the last message, drawing it unless something more important is on screen, or queues it until the
+    /// last one has been up for [`MIN_MESSAGE_DWELL`]. Takes the old message out of `message`, rather than copying
+    /// the new one. How long ago `sent_at` was is shown in the status bar and history view, counting from now if it's
+    /// `None`.
+    pub fn show_message(
+        &mut self,
+        message: &mut heapless::String<MAX_DISPLAY_LEN>,
+        sent_at: Option<Instant>,
+    ) {
+        if self.queued.is_empty() && self.message == *message && !self.message_expired {
+            return;
+        }
+
+        // Taken now, so a message that waits in the queue still counts from when it arrived
+        let sent_at = sent_at.unwrap_or_else(Instant::now);
+
+        if self.dwell_until.is_some_and(|until| Instant::now() < until) {
+            if self.queued.is_full() {
+                log::warn!("Too many messages waiting for the display, showing one early");
+                self.show_next_queued();
+            }
+            // Just made room
+            let _ = self.queued.push_back((core::mem::take(message), sent_at));
+            return;
+        }
+
+        self.replace_message(message, sent_at);
+    }
+
+    /// Shows the oldest queued message, if there is one.
+    fn show_next_queued(&mut self) {
+        if let Some((mut message, sent_at)) = self.queued.pop_front() {
+            self.replace_message(&mut message, sent_at);
+        }
+    }
+
+    /// Replaces the last message right away, swapping it into `message`, and adds the new one to the history.
+    fn replace_message(
+        &mut self,
+        message: &mut heapless::String<MAX_DISPLAY_LEN>,
+        sent_at: Instant,
+    ) {
+        core::mem::swap(&mut self.message, message);
+        self.dwell_until = Some(Instant::now() + MIN_MESSAGE_DWELL);
+        self.message_sent_at = sent_at;
+        self.message_expired = false;
+        self.push_history(SentAt::At(sent_at), self.message.clone());
+
+        crate::backlight::wake();
+        // The history view shows the new message too
+        if matches!(self.top(), Layer::History | Layer::Message) {
+            self.redraw();
+        }
+    }
+
+    /// Adds a message received before the last reboot to the history view, without showing it.
+    pub fn replay_message(&mut self, message: &str) {
+        let mut replayed = heapless::String::new();
+        // Same capacity, so always fits
+        replayed.push_str(message).unwrap();
+        self.push_history(SentAt::BeforeRestart, replayed);
+        if self.top() == Layer::History {
+            self.redraw();
+        }
+    }
+
+    /// Adds a message to the history, dropping the oldest if it's full.
+    fn push_history(&mut self, sent_at: SentAt, message: heapless::String<MAX_DISPLAY_LEN>) {
+        if self.history.is_full() {
+            self.history.pop_front();
+        }
+        // Just made room
+        let _ = self.history.push_back((sent_at, message));
+    }
+
+    /// Shows `text` over the last message for `duration`.
+    pub fn show_alert(&mut self, text: &str, duration: Duration) {
+        let mut alert = heapless::String::new();
+        // Same capacity, so always fits
+        alert.push_str(text).unwrap();
+        self.alert = Some((alert, Instant::now() + duration));
+        crate::backlight::wake();
+        if self.top() == Layer::Alert {
+            self.redraw();
+        }
+    }
+
+    /// Shows an emergency over everything but the pairing passkey, until it's acknowledged.
+    pub fn show_emergency(&mut self, text: &str) {
+        let mut emergency = heapless::String::new();
+        // Same capacity, so always fits
+        emergency.push_str(text).unwrap();
+        self.emergency = Some(Emergency {
+            text: emergency,
+            inverted: false,
+            next_blink: Instant::now() + EMERGENCY_BLINK_INTERVAL,
+        });
+        EMERGENCY_SHOWING.store(true, Ordering::Relaxed);
+        crate::backlight::wake();
+        if self.top() == Layer::Emergency {
+            self.redraw();
+        }
+    }
+
+    /// Shows the pairing passkey over everything else, or takes it down if `None`.
+    pub fn show_passkey(&mut self, passkey: Option<u32>) {
+        if passkey == self.passkey {
+            return;
+        }
+
+        self.passkey = passkey;
+        if passkey.is_some() {
+            crate::backlight::wake();
+        }
+        if self.top() != Layer::Blank {
+            self.redraw();
+        }
+    }
+
+    /// Changes how many minutes messages stay up before they're replaced with an idle indicator, or 0 to keep them up
+    /// until the next one. A message that's already been up too long is taken down on the next [`Screen::update`].
+    pub fn set_message_expiry(&mut self, mins: u16) {
+        self.message_expiry = (mins > 0).then(|| Duration::from_secs(u64::from(mins) * 60));
+        if self.message_expired && self.message_expiry.is_none() {
+            self.message_expired = false;
+            if self.top() == Layer::Message {
+                self.redraw();
+            }
+        }
+    }
+
+    /// When the message should be replaced with [`IDLE_MESSAGE`], or `None` if it never is or already has been.
+    fn message_expires_at(&self) -> Option<Instant> {
+        if self.message.is_empty() || self.message_expired {
+            return None;
+        }
+        self.message_expiry
+            .map(|expiry| self.message_sent_at + expiry)
+    }
+
+    /// The status bar, with the message's age if it's on screen
+    fn bar_status(&self) -> Status {
+        let message_age = self.shows_message_age().then(|| {
+            let age = Instant::now().saturating_duration_since(self.message_sent_at);
+            MessageAge::Secs(age.as_secs())
+        });
+        Status {
+            message_age,
+            ..self.status
+        }
+    }
+
+    /// Whether the status bar shows how long ago the message was sent, which is whenever the message is on screen
+    fn shows_message_age(&self) -> bool {
+        self.top() == Layer::Message && !self.message.is_empty() && !self.message_expired
+    }
+
+    /// When the message's age in the status bar next needs redrawing, or `None` if it isn't shown.
+    fn message_age_changes_at(&self) -> Option<Instant> {
+        let age = self.bar_status().message_age?;
+        let secs = age.next_change_secs()?;
+        Some(self.message_sent_at + Duration::from_secs(secs))
+    }
+
+    /// Opens, closes, or moves through the history view.
+    pub fn navigate_history(&mut self, nav: HistoryNav) {
+        let last = self.history.len().saturating_sub(1);
+        self.history_first = match (nav, self.history_first) {
+            (HistoryNav::Open, _) => Some(0),
+            (HistoryNav::Close, _) => None,
+            (HistoryNav::Older, Some(first)) => Some((first + 1).min(last)),
+            (HistoryNav::Newer, Some(first)) => Some(first.saturating_sub(1)),
+            (HistoryNav::Older | HistoryNav::Newer, None) => return,
+        };
+
+        if self.top() >= Layer::History {
+            self.redraw();
+        }
+    }
+
+    /// Opens the diagnostics screen over the history and messages, redraws it with what's changed, or closes it if
+    /// `None`.
+    pub fn show_diagnostics(&mut self, diagnostics: Option<Diagnostics>) {
+        if diagnostics == self.diagnostics {
+            return;
+        }
+
+        let was_top = self.top() == Layer::Diagnostics;
+        self.diagnostics = diagnostics;
+        if was_top || self.top() == Layer::Diagnostics {
+            self.redraw();
+        }
+    }
+
+    /// Redraws the status bar, if it's on screen. The splash screen is redrawn instead, since it shows the station.
+    pub fn show_status(&mut self, status: Status) {
+        self.status = status;
+        match self.top() {
+            Layer::Passkey | Layer::Alert => self.display.draw_status(&self.status),
+            Layer::Message => self.display.draw_status(&self.bar_status()),
+            Layer::Splash => self.redraw(),
+            Layer::Blank | Layer::Emergency | Layer::Diagnostics | Layer::History => {}
+        }
+    }
+
+    /// Clears the screen while the device is idle, or redraws it once it wakes up.
+    pub fn set_blanked(&mut self, blanked: bool) {
+        self.blanked = blanked;
+        if blanked {
+            self.next_scroll = None;
+            self.display.clear();
+        } else {
+            self.redraw();
+        }
+    }
+
+    /// When [`Screen::update`] next needs to be called, if ever.
+    pub fn next_update_at(&self) -> Option<Instant> {
+        [
+            self.emergency
+                .as_ref()
+                .map(|emergency| emergency.next_blink),
+            self.alert.as_ref().map(|(_, until)| *until),
+            self.splash_until,
+            self.next_scroll,
+            self.message_expires_at(),
+            self.message_age_changes_at(),
+            self.dwell_until.filter(|_| !self.queued.is_empty()),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Blinks the emergency or takes it down once acknowledged, ends the alert and splash screen once they've been up
+    /// long enough, shows the next queued message, scrolls the message, updates its age, and replaces it with
+    /// [`IDLE_MESSAGE`] once it's expired. Expiring doesn't wake the screen, so a blanked screen shows the idle indicator
+    /// when it next wakes.
+    pub fn update(&mut self) {
+        let now = Instant::now();
+        // Checked before anything else changes the screen, since a redraw draws the new age anyway
+        let age_changed = self.message_age_changes_at().is_some_and(|at| now >= at);
+
+        let blink = self
+            .emergency
+            .as_ref()
+            .is_some_and(|emergency| now >= emergency.next_blink);
+        if blink && !EMERGENCY_SHOWING.load(Ordering::Relaxed) {
+            log::info!("Emergency acknowledged");
+            let was_top = self.top() == Layer::Emergency;
+            self.emergency = None;
+            if was_top {
+                self.redraw();
+            }
+        } else if blink {
+            // Keeps the screen from blanking until someone sees it
+            crate::backlight::wake();
+            let top = self.top();
+            if let Some(emergency) = &mut self.emergency {
+                emergency.inverted = !emergency.inverted;
+                emergency.next_blink = now + EMERGENCY_BLINK_INTERVAL;
+                if top == Layer::Emergency {
+                    self.display
+                        .draw_emergency(&emergency.text, emergency.inverted);
+                }
+            }
+        }
+
+        if self.splash_until.is_some_and(|until| now >= until) {
+            let was_top = self.top() == Layer::Splash;
+            self.splash_until = None;
+            if was_top {
+                self.redraw();
+            }
+        }
+
+        if self.alert.as_ref().is_some_and(|(_, until)| now >= *until) {
+            let was_top = self.top() == Layer::Alert;
+            self.alert = None;
+            if was_top {
+                self.redraw();
+            }
+        }
+
+        if self.dwell_until.is_some_and(|until| now >= until) {
+            self.dwell_until = None;
+            self.show_next_queued();
+        }
+
+        if self.message_expires_at().is_some_and(|at| now >= at) {
+            log::info!("Message expired, showing idle indicator");
+            self.message_expired = true;
+            if self.top() == Layer::Message {
+                self.redraw();
+            }
+        }
+
+        if age_changed && self.shows_message_age() {
+            self.display.draw_status(&self.bar_status());
+        }
+
+        if self.next_scroll.is_some_and(|at| now >= at) {
+            self.scroll_offset = graphics::next_scroll_offset(
+                &self.message,
+                &MessageStyle::DEFAULT,
+                self.scroll_offset,
+                MessageStyle::DEFAULT.line_height(),
+            );
+            self.display
+                .draw_scrolled(&self.message, self.scroll_offset);
+            self.next_scroll = Some(now + SCROLL_INTERVAL);
+        }
+    }
+
+    /// The most important thing there is to show
+    const fn top(&self) -> Layer {
+        if self.blanked {
+            Layer::Blank
+        } else if self.passkey.is_some() {
+            Layer::Passkey
+        } else if self.emergency.is_some() {
+            Layer::Emergency
+        } else if self.splash_until.is_some() {
+            Layer::Splash
+        } else if self.diagnostics.is_some() {
+            Layer::Diagnostics
+        } else if self.history_first.is_some() {
+            Layer::History
+        } else if self.alert.is_some() {
+            Layer::Alert
+        } else {
+            Layer::Message
+        }
+    }
+
+    /// Draws the [`Screen::top`] layer from scratch. Only the message scrolls, starting back at the top.
+    fn redraw(&mut self) {
+        self.scroll_offset = 0;
+        self.next_scroll = None;
+
+        match self.top() {
+            Layer::Blank => {}
+            Layer::Passkey => {
+                if let Some(passkey) = self.passkey {
+                    self.display.draw_passkey(&self.status, passkey);
+                }
+            }
+            Layer::Emergency => {
+                if let Some(emergency) = &self.emergency {
+                    self.display
+                        .draw_emergency(&emergency.text, emergency.inverted);
+                }
+            }
+            Layer::Splash => self.display.draw_splash(self.status.station),
+            Layer::Diagnostics => {
+                if let Some(diagnostics) = &self.diagnostics {
+                    self.display.draw_diagnostics(diagnostics);
+                }
+            }
+            Layer::History => {
+                let now = Instant::now();
+                // Newest first
+                let entries: heapless::Vec<_, HISTORY_CAPACITY> = self
+                    .history
+                    .iter()
+                    .rev()
+                    .map(|(sent_at, message)| HistoryEntry {
+                        age: match sent_at {
+                            SentAt::At(at) => {
+                                MessageAge::Secs(now.saturating_duration_since(*at).as_secs())
+                            }
+                            SentAt::BeforeRestart => MessageAge::BeforeRestart,
+                        },
+                        message,
+                    })
+                    .collect();
+                self.display
+                    .draw_history(&entries, self.history_first.unwrap_or_default());
+            }
+            Layer::Alert => {
+                if let Some((alert, _)) = &self.alert {
+                    self.display
+                        .draw_styled(&self.status, alert, &MessageStyle::SYSTEM);
+                }
+            }
+            Layer::Message if self.message_expired => {
+                self.display
+                    .draw_styled(&self.status, IDLE_MESSAGE, &MessageStyle::SYSTEM);
+            }
+            Layer::Message => {
+                self.display.draw(&self.bar_status(), &self.message);
+                self.next_scroll = first_scroll_at(&self.message);
+            }
+        }
     }
 }
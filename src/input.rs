@@ -1,34 +1,185 @@
-use embassy_futures::select::{Either, select};
+use core::ops::RangeInclusive;
+
+use embassy_futures::select::{Either, Either3, select, select3};
 use embassy_rp::gpio::Input;
 use embassy_sync::{blocking_mutex::raw::RawMutex, signal::Signal};
-use embassy_time::{Duration, Timer};
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::display::{self, HistoryNav};
+
+pub use common::Button;
+
+/// Allowed time for a press or release to settle before the buttons are read again, in ms. Too short and bouncy
+/// switches register extra presses, too long and fast presses are dropped. Capped below [`DOUBLE_PRESS_WINDOW`] so
+/// there's always time left to press again.
+pub const DEBOUNCE_RANGE_MS: RangeInclusive<u16> = 20..=300;
+pub const DEFAULT_DEBOUNCE_MS: u16 = 250;
+/// Buttons held at least this long are long presses
+pub const LONG_PRESS_THRESHOLD: Duration = Duration::from_millis(800);
+/// A second press of the same button starting within this long of releasing the first makes a double press.
+/// Has to be longer than the debounce, or there's no time left to press again.
+pub const DOUBLE_PRESS_WINDOW: Duration = Duration::from_millis(400);
+// The window, and so the long press threshold after it, outlasts any debounce
+const _: () = assert!((*DEBOUNCE_RANGE_MS.end() as u64) < DOUBLE_PRESS_WINDOW.as_millis());
+const _: () = assert!(DOUBLE_PRESS_WINDOW.as_millis() < LONG_PRESS_THRESHOLD.as_millis());
+/// How long both buttons have to be held together to factory reset the device
+const FACTORY_RESET_HOLD: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum Button {
-    Good,
-    Help,
+pub enum ButtonEvent {
+    /// Released before [`LONG_PRESS_THRESHOLD`], and not pressed again within [`DOUBLE_PRESS_WINDOW`]
+    Press(Button),
+    /// Held for [`LONG_PRESS_THRESHOLD`], signaled as soon as it's reached rather than on release
+    LongPress(Button),
+    /// Pressed twice within [`DOUBLE_PRESS_WINDOW`], e.g. a double Help press could escalate an emergency
+    DoublePress(Button),
+}
+
+impl ButtonEvent {
+    pub const fn button(self) -> Button {
+        match self {
+            Self::Press(button) | Self::LongPress(button) | Self::DoublePress(button) => button,
+        }
+    }
 }
 
+/// Signals each button press on `signal`, or `factory_reset_signal` once both buttons are held for
+/// [`FACTORY_RESET_HOLD`]. `debounce_ms` is clamped to [`DEBOUNCE_RANGE_MS`].
 pub async fn task<'a, M: RawMutex>(
-    signal: &'a Signal<M, Button>,
+    signal: &'a Signal<M, ButtonEvent>,
+    factory_reset_signal: &'a Signal<M, ()>,
+    debounce_ms: u16,
     mut good_in: Input<'a>,
     mut help_in: Input<'a>,
 ) {
+    if !DEBOUNCE_RANGE_MS.contains(&debounce_ms) {
+        log::warn!("Debounce of {debounce_ms}ms is out of range, clamping it");
+    }
+    let debounce = Duration::from_millis(
+        debounce_ms
+            .clamp(*DEBOUNCE_RANGE_MS.start(), *DEBOUNCE_RANGE_MS.end())
+            .into(),
+    );
+
+    // A press that started while waiting to see if the previous one was a double press
+    let mut next_press: Option<(Button, Instant)> = None;
+
     loop {
-        signal.reset();
-        let good_low = good_in.wait_for_falling_edge();
-        let help_low = help_in.wait_for_falling_edge();
+        let (button, pressed_at) = if let Some(press) = next_press.take() {
+            press
+        } else {
+            signal.reset();
+            let good_low = good_in.wait_for_falling_edge();
+            let help_low = help_in.wait_for_falling_edge();
+
+            let button = match select(good_low, help_low).await {
+                Either::First(()) => Button::Good,
+                Either::Second(()) => Button::Help,
+            };
+            (button, Instant::now())
+        };
+        crate::backlight::wake();
 
-        match select(good_low, help_low).await {
-            Either::First(()) => {
-                signal.signal(Button::Good);
+        // Debounce successful press
+        Timer::at(pressed_at + debounce).await;
+
+        if good_in.is_low() && help_in.is_low() {
+            log::info!("Both buttons pressed, hold for factory reset");
+            let released = select(good_in.wait_for_high(), help_in.wait_for_high());
+            match select(Timer::after(FACTORY_RESET_HOLD), released).await {
+                Either::First(()) => {
+                    log::warn!("Factory reset requested with buttons");
+                    factory_reset_signal.signal(());
+                }
+                Either::Second(_) => log::info!("Buttons released, not factory resetting"),
             }
-            Either::Second(()) => {
-                signal.signal(Button::Help);
+            continue;
+        }
+
+        let pressed_in = match button {
+            Button::Good => &mut good_in,
+            Button::Help => &mut help_in,
+        };
+        if display::acknowledge_emergency() {
+            // Only acknowledges the emergency, rather than sending anything
+            wait_for_release(pressed_in, debounce).await;
+            continue;
+        }
+        if let Either::First(()) = select(
+            Timer::at(pressed_at + LONG_PRESS_THRESHOLD),
+            pressed_in.wait_for_high(),
+        )
+        .await
+        {
+            dispatch(signal, ButtonEvent::LongPress(button));
+            wait_for_release(pressed_in, debounce).await;
+            continue;
+        }
+        let released_at = Instant::now();
+
+        // Debounce release
+        Timer::after(debounce).await;
+
+        // Edges during the debounce aren't seen, so check for a press that already started
+        let next = if good_in.is_low() {
+            Some(Button::Good)
+        } else if help_in.is_low() {
+            Some(Button::Help)
+        } else {
+            match select3(
+                Timer::at(released_at + DOUBLE_PRESS_WINDOW),
+                good_in.wait_for_falling_edge(),
+                help_in.wait_for_falling_edge(),
+            )
+            .await
+            {
+                Either3::First(()) => None,
+                Either3::Second(()) => Some(Button::Good),
+                Either3::Third(()) => Some(Button::Help),
             }
+        };
+
+        if next == Some(button) {
+            dispatch(signal, ButtonEvent::DoublePress(button));
+            // Debounce the second press
+            Timer::after(debounce).await;
+            let pressed_in = match button {
+                Button::Good => &mut good_in,
+                Button::Help => &mut help_in,
+            };
+            wait_for_release(pressed_in, debounce).await;
+        } else {
+            dispatch(signal, ButtonEvent::Press(button));
+            next_press = next.map(|button| (button, Instant::now()));
         }
+    }
+}
 
-        // Debounce successful press
-        Timer::after(Duration::from_millis(250)).await;
+/// Hands `event` to the display if it's meant for it, otherwise signals it to be sent. A long press of Good opens and
+/// closes the history, and while it's open Good and Help go back and forward through it. A double press of Good opens
+/// and closes the diagnostics screen.
+fn dispatch<M: RawMutex>(signal: &Signal<M, ButtonEvent>, event: ButtonEvent) {
+    if event == ButtonEvent::DoublePress(Button::Good) {
+        display::toggle_diagnostics();
+        return;
     }
+
+    let nav = match event {
+        ButtonEvent::LongPress(Button::Good) if display::history_open() => Some(HistoryNav::Close),
+        ButtonEvent::LongPress(Button::Good) => Some(HistoryNav::Open),
+        ButtonEvent::Press(Button::Good) if display::history_open() => Some(HistoryNav::Older),
+        ButtonEvent::Press(Button::Help) if display::history_open() => Some(HistoryNav::Newer),
+        _ => None,
+    };
+
+    match nav {
+        Some(nav) => display::navigate_history(nav),
+        None => signal.signal(event),
+    }
+}
+
+async fn wait_for_release(input: &mut Input<'_>, debounce: Duration) {
+    input.wait_for_high().await;
+    // Debounce release
+    Timer::after(debounce).await;
 }
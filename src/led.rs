@@ -0,0 +1,68 @@
+//! Blinks the Pico's onboard LED, which is wired to the CYW43's GPIO 0, to show what the device is up to without
+//! needing the screen: a slow heartbeat while waiting for a BLE connection, solid while connected, and a quick double
+//! blink for each packet sent.
+
+use embassy_futures::select::{Either3, select3};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Timer};
+
+/// The CYW43 GPIO the onboard LED is wired to
+const LED_GPIO: u8 = 0;
+
+/// Steps of a blink pattern: whether the LED is on, and for how long
+type Pattern = &'static [(bool, Duration)];
+
+/// Repeated while advertising with no one connected
+const HEARTBEAT: Pattern = &[
+    (true, Duration::from_millis(100)),
+    (false, Duration::from_millis(1900)),
+];
+/// Repeated while a BLE central is connected
+const CONNECTED: Pattern = &[(true, Duration::from_secs(1))];
+/// Played once for each packet sent, then back to the last pattern
+const PACKET_SENT: Pattern = &[
+    (true, Duration::from_millis(60)),
+    (false, Duration::from_millis(80)),
+    (true, Duration::from_millis(60)),
+    (false, Duration::from_millis(200)),
+];
+
+static CONNECTED_SIGNAL: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+static PACKET_SENT_SIGNAL: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Switches between the [`HEARTBEAT`] and [`CONNECTED`] patterns.
+pub fn set_connected(connected: bool) {
+    CONNECTED_SIGNAL.signal(connected);
+}
+
+/// Plays [`PACKET_SENT`] once. Packets sent while it's playing only blink once between them.
+pub fn packet_sent() {
+    PACKET_SENT_SIGNAL.signal(());
+}
+
+/// Blinks the LED until told otherwise, starting with [`HEARTBEAT`].
+pub async fn run(control: &mut cyw43::Control<'_>) -> ! {
+    let mut connected = false;
+
+    loop {
+        let pattern = if connected { CONNECTED } else { HEARTBEAT };
+        match select3(
+            play(control, pattern),
+            CONNECTED_SIGNAL.wait(),
+            PACKET_SENT_SIGNAL.wait(),
+        )
+        .await
+        {
+            Either3::First(()) => {}
+            Either3::Second(new_connected) => connected = new_connected,
+            Either3::Third(()) => play(control, PACKET_SENT).await,
+        }
+    }
+}
+
+async fn play(control: &mut cyw43::Control<'_>, pattern: Pattern) {
+    for &(on, duration) in pattern {
+        control.gpio_set(LED_GPIO, on).await;
+        Timer::after(duration).await;
+    }
+}
@@ -0,0 +1,73 @@
+//! Where the firmware's logs go, picked at build time. Everything logs through the `log` macros, which end up either:
+//!
+//! - over USB serial with the `usb-log` feature, the default, for reading with a serial monitor
+//! - over RTT with the `defmt` feature, for reading with a debug probe
+//!
+//! Exactly one of the two must be enabled.
+
+use embassy_executor::Spawner;
+use embassy_rp::{Peri, peripherals::USB};
+
+#[cfg(all(feature = "usb-log", feature = "defmt"))]
+compile_error!(
+    "the `usb-log` and `defmt` features both pick where logs go, enable only one of them"
+);
+#[cfg(not(any(feature = "usb-log", feature = "defmt")))]
+compile_error!("enable the `usb-log` or `defmt` feature to pick where logs go");
+
+/// Least severe level that's logged
+const LOG_LEVEL: log::LevelFilter = log::LevelFilter::Debug;
+
+/// Starts sending logs to the backend picked at build time. Until this is called, they're dropped.
+///
+/// The USB peripheral is only used by the `usb-log` backend, and is left alone otherwise.
+pub fn init(spawner: Spawner, usb: Peri<'static, USB>) {
+    #[cfg(feature = "usb-log")]
+    {
+        let driver = embassy_rp::usb::Driver::new(usb, crate::Irqs);
+        spawner.spawn(usb_logger_task(driver).unwrap());
+    }
+
+    #[cfg(feature = "defmt")]
+    {
+        let _ = (spawner, usb);
+        if log::set_logger(&DefmtLogger).is_ok() {
+            log::set_max_level(LOG_LEVEL);
+        }
+    }
+}
+
+#[cfg(feature = "usb-log")]
+#[embassy_executor::task]
+async fn usb_logger_task(driver: embassy_rp::usb::Driver<'static, USB>) {
+    embassy_usb_logger::run!(1024, LOG_LEVEL, driver);
+}
+
+/// Forwards each `log` record to the defmt macro of the same level, so dependencies logging through `log` end up on
+/// RTT too
+#[cfg(feature = "defmt")]
+struct DefmtLogger;
+
+#[cfg(feature = "defmt")]
+impl log::Log for DefmtLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= LOG_LEVEL
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let args = defmt::Display2Format(record.args());
+        match record.level() {
+            log::Level::Error => defmt::error!("{}", args),
+            log::Level::Warn => defmt::warn!("{}", args),
+            log::Level::Info => defmt::info!("{}", args),
+            log::Level::Debug => defmt::debug!("{}", args),
+            log::Level::Trace => defmt::trace!("{}", args),
+        }
+    }
+
+    fn flush(&self) {}
+}
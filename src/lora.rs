@@ -1,9 +1,9 @@
-use core::ops::Range;
+use core::fmt::Write;
+use core::ops::{Range, RangeInclusive};
+use core::sync::atomic::{AtomicU16, Ordering};
 
-use ascon_aead::{
-    AsconAead128,
-    aead::{AeadInPlace, KeyInit},
-};
+use ascon_aead::aead::KeyInit;
+use common::{AllGood, LossyText, MessageKind, Station};
 use embassy_rp::{
     Peri,
     dma::Channel,
@@ -16,11 +16,12 @@ use embassy_sync::{
     signal::Signal,
     zerocopy_channel,
 };
-use embassy_time::Delay;
+use embassy_time::{Delay, Duration, Instant, Timer};
 use embedded_hal_bus::spi::ExclusiveDevice;
+use heapless::Vec;
 use lora_phy::{
     DelayNs,
-    mod_params::{ModulationParams, PacketParams, RadioError},
+    mod_params::{ModulationParams, PacketParams, PacketStatus, RadioError},
     mod_traits::RadioKind,
     sx127x::{self, Sx1276},
 };
@@ -29,28 +30,486 @@ use lora_phy::{
     RxMode,
     mod_params::{Bandwidth, CodingRate, SpreadingFactor},
 };
-use lorawan_device::async_device::region;
+use proto::{
+    BROADCAST_ID, CRC_SIZE, HEADER_SIZE, KIND_SIZE, MAX_MSG_LEN, MAX_PAYLOAD_LEN, PROTO_VERSION,
+    Packet, PacketHeader, PacketType, SENDER_ID_SIZE, SEQUENCE_SIZE, UNSYNCED_TIMESTAMP,
+    adaptive_sf::SfAdapter,
+    crypto::{MAC_SIZE, NONCE_SIZE, RecentNonces, build_outgoing, decrypt_in_place},
+    dedup::{RecentPackets, ReplayGuard},
+    duty_cycle::{AirtimeParams, DutyCycle},
+    fragment::{self, MAX_FRAGMENTS, Reassembler},
+    hopping,
+    listen_before_talk::{self, ListenBeforeTalk},
+    queue::{Priority, PriorityQueue},
+    random::{random_u32_in_range, random_u32_in_range_inclusive},
+    signature::{self, signed},
+};
 use rand_core::RngCore;
 use static_cell::StaticCell;
 
-use crate::{display::DisplayMessage, input::Button};
+use crate::{
+    battery::{self, BatteryLevel},
+    bt_server::MESSAGE_CAPACITY,
+    clock,
+    display::{self, DisplayMessage},
+    input::{Button, ButtonEvent},
+    led, stats,
+    storage::{HOP_CHANNELS_CAPACITY, Info, SIGNATURE_CAPACITY, StoredMessage},
+};
+
+/// Lowest TX power the SX1276's boosted PA supports, in dBm
+pub const MIN_TX_POWER_DBM: i8 = 2;
+/// Highest TX power the SX1276's boosted PA supports, in dBm
+pub const MAX_TX_POWER_DBM: i8 = 20;
+
+/// An acknowledgement's plaintext is the ID of the device being acknowledged and the sequence number it sent
+const ACK_SIZE: usize = SENDER_ID_SIZE + SEQUENCE_SIZE;
+// Every packet's length is sent to the radio as a `u8`, so `packet_len` never has to saturate
+const _: () = assert!(MAX_PAYLOAD_LEN <= u8::MAX as usize);
+/// Max length of a message split into fragments
+pub const MAX_FRAGMENTED_MSG_LEN: usize = MAX_FRAGMENTS * MAX_MSG_LEN;
+/// Longest message this device sends, split into as many fragments as it takes. Longer ones are rejected when they're
+/// written over BLE.
+pub const MAX_SENDABLE_MSG_LEN: usize = if MAX_FRAGMENTED_MSG_LEN < MESSAGE_CAPACITY {
+    MAX_FRAGMENTED_MSG_LEN
+} else {
+    MESSAGE_CAPACITY
+};
+// The longest message takes no more fragments than receivers reassemble
+const _: () = assert!(MAX_SENDABLE_MSG_LEN.div_ceil(MAX_MSG_LEN) <= MAX_FRAGMENTS);
+
+/// Identifies packets sent by this device, derived from the `ID` it was built with
+pub const SENDER_ID: u16 = sender_id(crate::ID);
+const _: () = assert!(
+    SENDER_ID != BROADCAST_ID,
+    "ID hashes to the broadcast ID, pick another"
+);
+
+/// Milliseconds to sleep between CAD cycles, randomized so devices don't all listen and talk in lockstep.
+/// Must stay shorter than a packet's preamble (at least about 16ms with every [`LoraPreset`]) or packets can be missed.
+const RANDOM_SLEEP_RANGE_MS: Range<u32> = 3..8;
+/// Milliseconds to hold off sending after hearing activity on the channel
+const BUSY_BACKOFF_RANGE_MS: RangeInclusive<u32> = 50..=250;
+/// Milliseconds added to each resend's backoff so devices resending at once don't keep colliding
+const RESEND_JITTER_RANGE_MS: Range<u32> = 300..800;
+/// Allowed number of times each packet is transmitted
+pub const TRANSMIT_TIMES_RANGE: RangeInclusive<u8> = 1..=5;
+pub const DEFAULT_TRANSMIT_TIMES: u8 = 2;
+/// Allowed number of symbols to wait for a packet's preamble once channel activity is heard, or for each RX window
+/// when always listening. The SX1276's CAD has no sensitivity to tune, so this is what's left: fewer gets the radio
+/// back to sleep sooner after a false detection in a noisy band, more catches packets whose preamble was heard late.
+/// At least the longest preamble, and at most what the SX1276's 10 bit symbol timeout holds.
+pub const RX_TIMEOUT_SYMBOLS_RANGE: RangeInclusive<u16> = 32..=1023;
+pub const DEFAULT_RX_TIMEOUT_SYMBOLS: u16 = 128;
+/// Messages are sent at most this many times before giving up on being acknowledged
+const MAX_SEND_ATTEMPTS: u8 = 4;
+/// Max number of messages and relayed packets waiting to be sent. Once full, the least urgent are dropped.
+const OUTGOING_CAPACITY: usize = 6;
+/// Packets this device sends can be relayed this many times on their way through the fleet
+const MAX_HOPS: u8 = 3;
+/// How often a call for help is repeated while an emergency broadcast is active, plus some jitter
+const EMERGENCY_REPEAT_INTERVAL: Duration = Duration::from_secs(30);
+/// Milliseconds added to each repeat's interval, so devices repeating at once don't keep colliding
+const EMERGENCY_REPEAT_JITTER_RANGE_MS: Range<u32> = 0..5000;
+/// An emergency broadcast stops on its own after this long, so it can't use up airtime forever
+const MAX_EMERGENCY_BROADCAST: Duration = Duration::from_secs(10 * 60);
+/// Milliseconds to wait before relaying a packet, randomized so devices relaying the same packet don't collide
+const RELAY_DELAY_RANGE_MS: Range<u32> = 50..400;
+/// Milliseconds added to an acknowledgement's delay, so devices acknowledging the same broadcast don't collide
+const ACK_JITTER_RANGE_MS: Range<u32> = 50..500;
+/// Max number of acknowledgements waiting to be sent. Once full, the oldest is dropped and left to be sent again when
+/// its message is resent.
+const PENDING_ACKS_CAPACITY: usize = 4;
+/// How long to wait for an acknowledgement after the first attempt, on top of the acknowledgement's airtime, doubling
+/// after each following attempt
+const ACK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Window the region's duty cycle limit is enforced over
+const DUTY_CYCLE_WINDOW: Duration = Duration::from_secs(60 * 60);
+/// Max number of transmissions remembered within [`DUTY_CYCLE_WINDOW`]
+const DUTY_CYCLE_HISTORY: usize = 64;
+
+/// How long a received packet is remembered so its repeated transmissions can be ignored
+const DUPLICATE_WINDOW: Duration = Duration::from_secs(30);
+/// Max number of packets remembered within [`DUPLICATE_WINDOW`]
+const RECENT_PACKETS_CAPACITY: usize = 16;
+/// How long a message this device acknowledged is remembered, so it's acknowledged again rather than shown again when
+/// its sender missed the acknowledgement and resends it. Longer than all of a message's attempts take.
+const ACKED_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// Max number of acknowledged messages remembered within [`ACKED_WINDOW`]
+const ACKED_CAPACITY: usize = 8;
+/// Number of nonces this device last encrypted with that new ones are checked against
+const RECENT_NONCES_CAPACITY: usize = 32;
+/// Max number of senders whose accepted sequence numbers are remembered. Must be a power of 2.
+const TRACKED_SENDERS: usize = 16;
+/// Max number of senders whose fragmented messages can be reassembled at once
+const REASSEMBLY_SENDERS: usize = 4;
+/// Partially received fragmented messages are discarded after this long
+const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The radio is re-initialized after this many radio operations fail in a row
+const MAX_CONSECUTIVE_RADIO_ERRORS: u8 = 5;
+/// Minimum time between re-initializations, so a radio that's gone for good doesn't keep it busy resetting
+const MIN_REINIT_INTERVAL: Duration = Duration::from_secs(10);
+/// The status bar hints at a key mismatch once this many packets in a row fail to decrypt
+const KEY_MISMATCH_THRESHOLD: u32 = 5;
+/// How long the alert that the radio is being re-initialized stays up
+const RADIO_ALERT_DURATION: Duration = Duration::from_secs(3);
+/// Alert shown while this device's own message is first being transmitted
+const SENDING_ALERT: &str = "Sending...";
+/// Alert shown once it's been transmitted, before any device has acknowledged it
+const SENT_ALERT: &str = "Sent";
+const SEND_FAILED_ALERT: &str = "Send failed";
+/// How long the sent or failed alert stays up
+const SEND_ALERT_DURATION: Duration = Duration::from_secs(2);
+
+/// Number of test packets sent for each request, see [`request_test`]
+const TEST_PACKETS: u8 = 3;
+/// Least time between test packets
+const TEST_PACKET_INTERVAL: Duration = Duration::from_secs(1);
+/// Payload of every test packet
+const TEST_PAYLOAD: &[u8] = b"LEWOC test";
+/// How long each test packet's result stays up on the display
+const TEST_ALERT_DURATION: Duration = Duration::from_secs(2);
+/// Max number of bytes in a test packet's result
+pub const TEST_RESULT_CAPACITY: usize = 64;
 
-// warning: set these appropriately for the region
-const LORAWAN_REGION: region::Region = region::Region::US915;
-const TX_POWER: i32 = 20; // requires boost
-const LORA_FREQUENCY_IN_HZ: u32 = 915_000_000;
+static TEST_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+/// Device messages from BLE are sent to, or [`BROADCAST_ID`] for every device
+static MESSAGE_DESTINATION: AtomicU16 = AtomicU16::new(BROADCAST_ID);
+/// Signaled with the result of each test packet, like `Test 1/3 sent` or the radio error it failed with
+pub static TEST_RESULT: Signal<
+    CriticalSectionRawMutex,
+    trouble_host::prelude::HeaplessString<TEST_RESULT_CAPACITY>,
+> = Signal::new();
 
-/// Packets must start with this "magic" word, or they will be ignored
-const MAGIC_WORD: u64 = 0x1234_5678_9012_3452;
-const MAGIC_WORD_SIZE: usize = size_of_val(&MAGIC_WORD);
-const MAX_PAYLOAD_LEN: usize = 222;
-const MAC_SIZE: usize = 16;
-const NONCE_SIZE: usize = 16;
-const MAX_MSG_LEN: usize = MAX_PAYLOAD_LEN - MAC_SIZE - NONCE_SIZE - MAGIC_WORD_SIZE;
+// Any signature leaves room for some of the message
+const _: () = assert!(SIGNATURE_CAPACITY + signature::SEPARATOR.len() < MAX_SENDABLE_MSG_LEN);
 
-const RANDOM_SLEEP_RANGE: Range<u32> = 3..8;
-const TRANSMIT_PKT_TIMES: usize = 2;
+/// Max length of the text shown for a received Good press
+const ALL_GOOD_CAPACITY: usize = 32;
+
+/// How urgently a message of `kind` needs to go out
+const fn priority(kind: MessageKind) -> Priority {
+    match kind {
+        MessageKind::Help => Priority::Emergency,
+        MessageKind::Text | MessageKind::Good => Priority::Normal,
+    }
+}
+
+/// What the sender shows for a message of `kind` once it's delivered, or not
+const fn sent_text(kind: MessageKind) -> &'static str {
+    match kind.button() {
+        Some(button) => button.message(),
+        None => "Message",
+    }
+}
+
+/// Counts radio operations failing in a row, to tell when the radio may be wedged and need re-initializing
+struct RadioHealth {
+    consecutive_errors: u8,
+    last_reinit: Option<Instant>,
+}
+
+impl RadioHealth {
+    const fn new() -> Self {
+        Self {
+            consecutive_errors: 0,
+            last_reinit: None,
+        }
+    }
+
+    const fn succeeded(&mut self) {
+        self.consecutive_errors = 0;
+    }
+
+    const fn failed(&mut self) {
+        self.consecutive_errors = self.consecutive_errors.saturating_add(1);
+    }
+
+    /// Whether enough operations have failed in a row, and it's been long enough since the last re-initialization
+    fn should_reinit(&self) -> bool {
+        self.consecutive_errors >= MAX_CONSECUTIVE_RADIO_ERRORS
+            && self
+                .last_reinit
+                .is_none_or(|at| at.elapsed() >= MIN_REINIT_INTERVAL)
+    }
+
+    fn reinit_started(&mut self) {
+        self.consecutive_errors = 0;
+        self.last_reinit = Some(Instant::now());
+    }
+}
+
+/// How the radio listens for packets
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum ListenMode {
+    /// Only receive once Channel Activity Detection hears something, sleeping in between. Saves power, but can miss
+    /// the start of packets.
+    ///
+    /// Between checks the radio is put to sleep, drawing next to nothing instead of the ~1.5mA it does in standby,
+    /// and core0 waits for the timer in WFE like any other await. A check at SF8 takes about as long as the sleep
+    /// after it, so the radio averages around half of the ~11mA it draws always listening. Dormant mode would save
+    /// more on the MCU, but it stops the clocks the timer, USB, and core1 depend on.
+    #[default]
+    Cad = 0,
+    /// Always receive, for devices like base stations that shouldn't miss traffic and don't run on battery
+    Continuous = 1,
+    /// Always receive and never transmit, not even acks or relays, for a listener watching over a fleet from one spot
+    /// without using any airtime. Packets still have to be encrypted with this device's key to be shown.
+    Monitor = 2,
+}
+
+impl ListenMode {
+    /// Whether anything is ever sent
+    pub const fn transmits(self) -> bool {
+        !matches!(self, Self::Monitor)
+    }
+}
+
+impl TryFrom<u8> for ListenMode {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Cad),
+            1 => Ok(Self::Continuous),
+            2 => Ok(Self::Monitor),
+            other => Err(other),
+        }
+    }
+}
 
+/// Named LoRa modulation settings, trading speed for range. Every device in a fleet has to use the same one to hear
+/// each other.
+///
+/// Airtimes are for a full packet, and sensitivities are the SX1276's from its datasheet, both approximate. Every preset
+/// fits the same [`MAX_PAYLOAD_LEN`], so packet sizes and buffers don't change with it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum LoraPreset {
+    /// SF7, 250 kHz, CR 4/5: about 180ms on air and -120 dBm sensitivity. For devices close together, like along a
+    /// single platform.
+    Fast = 0,
+    /// SF8, 125 kHz, CR 4/5: about 610ms on air and -126 dBm sensitivity
+    #[default]
+    Balanced = 1,
+    /// SF10, 125 kHz, CR 4/8: about 3.1s on air and -132 dBm sensitivity. Uses up a 1% duty cycle after about a dozen
+    /// full packets an hour.
+    LongRange = 2,
+}
+
+impl LoraPreset {
+    const fn bandwidth(self) -> Bandwidth {
+        match self {
+            Self::Fast => Bandwidth::_250KHz,
+            Self::Balanced | Self::LongRange => Bandwidth::_125KHz,
+        }
+    }
+
+    const fn coding_rate(self) -> CodingRate {
+        match self {
+            Self::Fast | Self::Balanced => CodingRate::_4_5,
+            Self::LongRange => CodingRate::_4_8,
+        }
+    }
+
+    /// Long enough that the preamble lasts longer than the sleep between CAD cycles
+    const fn preamble_len(self) -> u16 {
+        match self {
+            Self::Fast => 28,
+            Self::Balanced | Self::LongRange => 4,
+        }
+    }
+
+    /// Matches the modulation and packet params [`radio_params`] configures the radio with for this preset
+    const fn airtime_params(self) -> AirtimeParams {
+        let (spreading_factor, bandwidth_hz, coding_rate) = match self {
+            Self::Fast => (7, 250_000, 1),
+            Self::Balanced => (8, 125_000, 1),
+            Self::LongRange => (10, 125_000, 4),
+        };
+        AirtimeParams {
+            spreading_factor,
+            bandwidth_hz,
+            coding_rate,
+            preamble_len: self.preamble_len(),
+            explicit_header: true,
+            crc: true,
+        }
+    }
+}
+
+impl TryFrom<u8> for LoraPreset {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Fast),
+            1 => Ok(Self::Balanced),
+            2 => Ok(Self::LongRange),
+            other => Err(other),
+        }
+    }
+}
+
+/// A message this device is sending, kept until it's acknowledged or runs out of attempts
+struct Outgoing {
+    kind: MessageKind,
+    data: Vec<u8, MESSAGE_CAPACITY>,
+    /// Device the message is sent to, or [`BROADCAST_ID`] for every device
+    destination: u16,
+    /// Number of times the message has been sent so far
+    attempts: u8,
+    /// Sequence number of the message's first packet once it's been sent, which every resend reuses so receivers
+    /// recognize it
+    first_sequence: Option<u32>,
+}
+
+impl Outgoing {
+    /// A press of `button` sent to every device, followed by `station` so receivers can show where it came from.
+    fn press(button: Button, station: Option<Station>) -> Self {
+        let mut data = Vec::new();
+        if let Some(station) = station {
+            data.push(station.into()).unwrap();
+        }
+        Self {
+            kind: MessageKind::from(button),
+            data,
+            destination: BROADCAST_ID,
+            attempts: 0,
+            first_sequence: None,
+        }
+    }
+}
+
+/// Repeats a call for help until it's cancelled with a Good press, or it's been going for [`MAX_EMERGENCY_BROADCAST`]
+struct EmergencyBroadcast {
+    ends_at: Instant,
+    next_repeat_at: Instant,
+}
+
+/// Time until the next repeat of an emergency broadcast
+fn emergency_repeat_interval(rng: &mut impl RngCore) -> Duration {
+    EMERGENCY_REPEAT_INTERVAL
+        + Duration::from_millis(random_u32_in_range(rng, EMERGENCY_REPEAT_JITTER_RANGE_MS).into())
+}
+
+/// A packet from another device, rebroadcast as it was received but with one less hop
+struct Relay {
+    header: PacketHeader,
+    /// Re-encrypted with a new nonce when it's sent, since the header it's authenticated with changed
+    plaintext: Vec<u8, { KIND_SIZE + MAX_MSG_LEN }>,
+    /// Not sent before this, see [`RELAY_DELAY_RANGE_MS`]
+    send_at: Instant,
+}
+
+/// Acknowledgement of a message from another device, held back until the message's sender is listening again
+struct PendingAck {
+    sender_id: u16,
+    /// Sequence number of the acknowledged packet
+    sequence: u32,
+    /// After the sender's remaining copies of the packet, see [`ACK_JITTER_RANGE_MS`]
+    send_at: Instant,
+}
+
+/// Something waiting to be sent
+enum Queued {
+    Message(Outgoing),
+    Relay(Relay),
+}
+
+impl Queued {
+    fn priority(&self) -> Priority {
+        match self {
+            Self::Message(outgoing) => priority(outgoing.kind),
+            // Calls for help stay urgent however many hops away they started
+            Self::Relay(relay)
+                if relay.header.packet_type == PacketType::Data
+                    && relay.plaintext.first() == Some(&(MessageKind::Help as u8)) =>
+            {
+                Priority::Emergency
+            }
+            Self::Relay(_) => Priority::Relay,
+        }
+    }
+
+    /// Airtime of a single transmission of everything that's sent for it.
+    fn airtime(&self, airtime_params: &AirtimeParams) -> Duration {
+        match self {
+            Self::Message(outgoing) => fragment::split(&outgoing.data, MAX_MSG_LEN).fold(
+                Duration::from_ticks(0),
+                |airtime, fragment| {
+                    airtime + airtime_params.airtime(packet_len(KIND_SIZE + fragment.len()))
+                },
+            ),
+            Self::Relay(relay) => airtime_params.airtime(packet_len(relay.plaintext.len())),
+        }
+    }
+}
+
+/// Least urgent packets still sent at a battery level, so what's left of it is saved for calls for help
+const fn min_priority(level: BatteryLevel) -> Priority {
+    match level {
+        BatteryLevel::Normal => Priority::Relay,
+        BatteryLevel::Low => Priority::Normal,
+        BatteryLevel::Critical => Priority::Emergency,
+    }
+}
+
+/// Queues `queued` to be sent, dropping the least urgent if there's no room, or `queued` itself if the battery is too
+/// low to send it.
+fn enqueue(queue: &mut PriorityQueue<Queued, OUTGOING_CAPACITY>, queued: Queued) {
+    let priority = queued.priority();
+    if priority < min_priority(battery::level()) {
+        match queued {
+            Queued::Message(outgoing) => {
+                log::warn!("Battery too low, not sending {:?}", outgoing.kind);
+            }
+            Queued::Relay(relay) => log::debug!(
+                "Battery too low, not relaying packet from {:04X}",
+                relay.header.sender_id
+            ),
+        }
+        return;
+    }
+
+    match queue.push(priority, queued) {
+        Some(Queued::Message(dropped)) => {
+            log::warn!("Outgoing queue full, dropped {:?}", dropped.kind);
+        }
+        Some(Queued::Relay(dropped)) => log::debug!(
+            "Outgoing queue full, not relaying packet from {:04X}",
+            dropped.header.sender_id
+        ),
+        None => {}
+    }
+}
+
+/// A message that has been sent and is waiting for any device to acknowledge it
+struct AwaitingAck {
+    outgoing: Outgoing,
+    /// Sequence number of the message's last packet, which receivers acknowledge
+    sequence: u32,
+    /// When to send the message again if it still hasn't been acknowledged
+    retry_at: Instant,
+}
+
+/// Runs the radio, receiving packets onto the display and `received_msg_signal`, and sending button presses and BLE
+/// messages. Received messages, other than calls for help, are also signaled on `history_signal` to be kept in flash.
+///
+/// Messages to send are queued, with calls for help sent ahead of anything else, even a message still waiting to be
+/// acknowledged. A long press of Help keeps repeating the call for help until Good is pressed or
+/// [`MAX_EMERGENCY_BROADCAST`] is up.
+///
+/// With [`Info::relay`] on, packets from other devices are queued to be rebroadcast with one less hop, behind this
+/// device's own messages, until they run out of hops. Packets are recognized by their sender and sequence number, so
+/// copies relayed back are ignored rather than relayed in a loop.
+///
+/// With [`ListenMode::Cad`] the radio spends most of its time asleep between channel activity checks, which uses far
+/// less power but can miss packets. With [`ListenMode::Continuous`] it's always in RX, only stopping when an RX window
+/// times out to send anything pending, which hears everything at the cost of drawing RX current the whole time.
+/// [`ListenMode::Monitor`] is always in RX too, but never sends, dropping anything that would have been queued.
 #[allow(
     clippy::too_many_arguments,
     clippy::too_many_lines,
@@ -69,14 +528,25 @@ pub async fn run<'d, T: spi::Instance, SignalM: RawMutex>(
     dio1: Peri<'d, impl gpio::Pin>,
     rng: &mut impl RngCore,
     encryption_key: u128,
-    input_signal: &'static Signal<SignalM, Button>,
-    bt_msg_signal: &'static Signal<SignalM, trouble_host::prelude::HeaplessString<128>>,
+    info: &Info,
+    input_signal: &'static Signal<SignalM, ButtonEvent>,
+    bt_msg_signal: &'static Signal<
+        SignalM,
+        trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    >,
+    received_msg_signal: &'static Signal<
+        SignalM,
+        trouble_host::prelude::HeaplessString<MESSAGE_CAPACITY>,
+    >,
+    history_signal: &'static Signal<SignalM, StoredMessage>,
     mut sender: zerocopy_channel::Sender<'static, CriticalSectionRawMutex, DisplayMessage>,
 ) {
     static RECV_BUF: StaticCell<ascon_aead::aead::heapless::Vec<u8, MAX_PAYLOAD_LEN>> =
         StaticCell::new();
     static SEND_BUF: StaticCell<ascon_aead::aead::heapless::Vec<u8, MAX_PAYLOAD_LEN>> =
         StaticCell::new();
+    static REASSEMBLER: StaticCell<Reassembler<REASSEMBLY_SENDERS, MAX_FRAGMENTED_MSG_LEN>> =
+        StaticCell::new();
 
     let mut config = spi::Config::default();
     config.frequency = 1_000_000; // Maybe use higher frequency on final board if we make one
@@ -110,164 +580,1208 @@ pub async fn run<'d, T: spi::Instance, SignalM: RawMutex>(
     // Fill with 0s
     recv_buf.resize_default(MAX_PAYLOAD_LEN).unwrap();
     let send_buf = SEND_BUF.init_with(Default::default);
+    let reassembler = REASSEMBLER.init(Reassembler::new(MAX_MSG_LEN, REASSEMBLY_TIMEOUT));
+
+    // Keyed by sender and sequence number, so relayed copies are recognized too
+    let mut recent_packets =
+        RecentPackets::<(u16, u32), RECENT_PACKETS_CAPACITY>::new(DUPLICATE_WINDOW);
+    let mut replay_guard = ReplayGuard::<TRACKED_SENDERS>::new();
+    // Keyed by sender and sequence number of the acknowledged packet
+    let mut acked = RecentPackets::<(u16, u32), ACKED_CAPACITY>::new(ACKED_WINDOW);
+    let mut pending_acks = Vec::<PendingAck, PENDING_ACKS_CAPACITY>::new();
+    let mut recent_nonces = RecentNonces::<RECENT_NONCES_CAPACITY>::new();
+    // Start each boot's sequence numbers above any sent during previous boots
+    let mut sequence = proto::sequence_start(info.boot_count);
+
+    // Fall back to legal settings for the region if the stored ones aren't
+    let frequency_hz = if info
+        .region
+        .frequency_range_hz()
+        .contains(&info.frequency_hz)
+    {
+        info.frequency_hz
+    } else {
+        log::error!(
+            "{} Hz is outside of {}, using default frequency",
+            info.frequency_hz,
+            info.region.as_ref()
+        );
+        info.region.default_frequency_hz()
+    };
+    let hop_channels_hz: Vec<u32, HOP_CHANNELS_CAPACITY> = info
+        .hop_channels_hz
+        .iter()
+        .copied()
+        .filter(|frequency_hz| {
+            let legal = info.region.frequency_range_hz().contains(frequency_hz);
+            if !legal {
+                log::error!(
+                    "{frequency_hz} Hz is outside of {}, not hopping to it",
+                    info.region.as_ref()
+                );
+            }
+            legal
+        })
+        .collect();
+    // Frequency the radio params were last made for
+    let mut tuned_frequency_hz =
+        hopping::frequency_hz(&hop_channels_hz, frequency_hz, clock::now());
+    let tx_power = i32::from(info.tx_power_dbm.clamp(
+        MIN_TX_POWER_DBM,
+        MAX_TX_POWER_DBM.min(info.region.max_tx_power_dbm()),
+    ));
+    let transmit_times = info
+        .transmit_times
+        .clamp(*TRANSMIT_TIMES_RANGE.start(), *TRANSMIT_TIMES_RANGE.end());
+    let listen_mode = info.listen_mode;
+    let rx_timeout_symbols = info.rx_timeout_symbols.clamp(
+        *RX_TIMEOUT_SYMBOLS_RANGE.start(),
+        *RX_TIMEOUT_SYMBOLS_RANGE.end(),
+    );
+    let preset = info.lora_preset;
+    let mut airtime_params = preset.airtime_params();
+    display::update_diagnostics(|diagnostics| {
+        diagnostics.frequency_hz = tuned_frequency_hz;
+        diagnostics.spreading_factor = airtime_params.spreading_factor;
+        diagnostics.bandwidth_hz = airtime_params.bandwidth_hz;
+        diagnostics.coding_rate = airtime_params.coding_rate;
+        diagnostics.tx_power_dbm = tx_power;
+    });
+    log::info!(
+        "LoRa config: {} {frequency_hz} Hz {preset:?} {tx_power} dBm, each packet sent {transmit_times}x, listening {listen_mode:?} for {rx_timeout_symbols} symbols",
+        info.region.as_ref()
+    );
+    if !hop_channels_hz.is_empty() {
+        log::info!(
+            "Hopping between {hop_channels_hz:?} Hz every {}s, starting on {tuned_frequency_hz} Hz",
+            hopping::DWELL_SECS
+        );
+    }
+
+    if let Some(station) = info.station {
+        log::info!("Station: {}", station.as_ref());
+        let out_msg = sender.send().await;
+        *out_msg = DisplayMessage::from_text(station.as_ref());
+        sender.send_done();
+    }
+
+    let mut duty_cycle = DutyCycle::<DUTY_CYCLE_HISTORY>::new(
+        DUTY_CYCLE_WINDOW,
+        info.region.max_duty_cycle_permille(),
+    );
+    // Messages waiting for the channel to be clear and the duty cycle to allow sending them
+    let mut outgoing_queue = PriorityQueue::<Queued, OUTGOING_CAPACITY>::new();
+    let mut awaiting_ack: Option<AwaitingAck> = None;
+    let mut next_send_at = Instant::now();
+    // Only kept while this device is the time source
+    let mut last_time_sync: Option<Instant> = None;
+    let mut emergency_broadcast: Option<EmergencyBroadcast> = None;
+    let mut test_packets_left = 0;
+    let mut next_test_at = Instant::now();
 
     let key_bytes = encryption_key.to_le_bytes();
     let key = ascon_aead::AsconAead128Key::from_slice(&key_bytes);
     let cipher = ascon_aead::AsconAead128::new(key);
 
-    let mdltn_params = {
-        match lora.create_modulation_params(
-            SpreadingFactor::_8,
-            Bandwidth::_125KHz,
-            CodingRate::_4_5,
-            LORA_FREQUENCY_IN_HZ,
-        ) {
-            Ok(mp) => mp,
-            Err(err) => {
-                log::info!("Radio error: {err:?}");
-                return;
-            }
+    let (mut mdltn_params, mut rx_pkt_params, mut tx_pkt_params) = match radio_params(
+        &mut lora,
+        preset,
+        airtime_params.spreading_factor,
+        tuned_frequency_hz,
+    ) {
+        Ok(params) => params,
+        Err(err) => {
+            log::info!("Radio error: {err:?}");
+            return;
         }
     };
 
-    let rx_pkt_params = {
-        match lora.create_rx_packet_params(
-            4,
-            false,
-            u8::try_from(recv_buf.len()).unwrap(),
-            true,
-            false,
-            &mdltn_params,
-        ) {
-            Ok(pp) => pp,
-            Err(err) => {
-                log::info!("Radio error: {err:?}");
-                return;
+    let mut radio_health = RadioHealth::new();
+    let mut sf_adapter = info
+        .adaptive_sf
+        .then(|| SfAdapter::new(airtime_params.spreading_factor));
+    // Spreading factor the radio is switched to before anything else is received or sent
+    let mut sf_step: Option<u8> = None;
+
+    log::info!("LoRa rx tx loop starting");
+    loop {
+        // Queued as soon as they come in, so a newer one doesn't replace them while the channel is busy
+        // Never longer than `MAX_SENDABLE_MSG_LEN`, since the BLE server rejects longer writes
+        if let Some(bt_msg) = bt_msg_signal.try_take() {
+            if listen_mode.transmits() {
+                enqueue(
+                    &mut outgoing_queue,
+                    Queued::Message(Outgoing {
+                        kind: MessageKind::Text,
+                        data: signed(info.signature.as_deref(), &bt_msg, MAX_SENDABLE_MSG_LEN),
+                        destination: MESSAGE_DESTINATION.load(Ordering::Relaxed),
+                        attempts: 0,
+                        first_sequence: None,
+                    }),
+                );
+            } else {
+                log::warn!("Monitoring, not sending message from BLE");
+            }
+        }
+        if TEST_REQUESTED.try_take().is_some() {
+            if listen_mode.transmits() {
+                log::info!("Sending {TEST_PACKETS} test packets");
+                test_packets_left = TEST_PACKETS;
+            } else {
+                log::warn!("Monitoring, not sending test packets");
+            }
+        }
+        if let Some(event) = input_signal.try_take() {
+            log::debug!("Button event: {event:?}");
+            if listen_mode.transmits() {
+                if event == ButtonEvent::LongPress(Button::Help) && emergency_broadcast.is_none() {
+                    log::warn!("Emergency broadcast started");
+                    emergency_broadcast = Some(EmergencyBroadcast {
+                        ends_at: Instant::now() + MAX_EMERGENCY_BROADCAST,
+                        next_repeat_at: Instant::now() + emergency_repeat_interval(rng),
+                    });
+                    display::update_status(|status| status.emergency_broadcast = true);
+                } else if event.button() == Button::Good && emergency_broadcast.take().is_some() {
+                    log::info!("Emergency broadcast cancelled");
+                    display::update_status(|status| status.emergency_broadcast = false);
+                }
+                // Long and double presses are sent the same as short ones
+                enqueue(
+                    &mut outgoing_queue,
+                    Queued::Message(Outgoing::press(event.button(), info.station)),
+                );
+            } else {
+                log::warn!("Monitoring, not sending {:?}", event.button());
             }
         }
-    };
 
-    let mut tx_pkt_params = {
-        match lora.create_tx_packet_params(4, false, true, false, &mdltn_params) {
-            Ok(pp) => pp,
-            Err(err) => {
-                log::info!("Radio error: {err:?}");
-                return;
+        if let Some(broadcast) = &mut emergency_broadcast {
+            let now = Instant::now();
+            if now >= broadcast.ends_at {
+                log::warn!(
+                    "Emergency broadcast stopped after {}s",
+                    MAX_EMERGENCY_BROADCAST.as_secs()
+                );
+                emergency_broadcast = None;
+                display::update_status(|status| status.emergency_broadcast = false);
+            } else if now >= broadcast.next_repeat_at {
+                broadcast.next_repeat_at = now + emergency_repeat_interval(rng);
+                // Still on its way if the last one hasn't been sent or given up on yet
+                let help_pending = awaiting_ack
+                    .as_ref()
+                    .is_some_and(|awaiting| awaiting.outgoing.kind == MessageKind::Help)
+                    || outgoing_queue.iter().any(|queued| match queued {
+                        Queued::Message(outgoing) => outgoing.kind == MessageKind::Help,
+                        Queued::Relay(_) => false,
+                    });
+                if !help_pending {
+                    log::info!("Repeating call for help");
+                    enqueue(
+                        &mut outgoing_queue,
+                        Queued::Message(Outgoing::press(Button::Help, info.station)),
+                    );
+                }
             }
         }
-    };
 
-    log::info!("LoRa rx tx loop starting");
-    loop {
-        // Use Channel Activity Detection (CAD) before receiving to save power
-        if let Err(err) = lora.prepare_for_cad(&mdltn_params).await {
-            log::error!("Failed to prepare for cad: {err:?}");
-            continue;
+        if radio_health.should_reinit() {
+            log::error!(
+                "{MAX_CONSECUTIVE_RADIO_ERRORS} radio errors in a row, re-initializing radio"
+            );
+            let out_msg = sender.send().await;
+            *out_msg = DisplayMessage::alert("Radio error, recovering...", RADIO_ALERT_DURATION);
+            sender.send_done();
+
+            radio_health.reinit_started();
+            // Resets the chip and puts it back in the state it was in before the loop started
+            match lora.init().await {
+                Ok(()) => log::info!("Radio re-initialized"),
+                Err(err) => {
+                    log::error!("Error LoRa re-init: {err:?}");
+                    report_error("Re-init");
+                }
+            }
         }
 
-        let channel_is_active = match lora.cad(&mdltn_params).await {
-            Ok(channel_active) => channel_active,
-            Err(err) => {
-                log::error!("Error checking channel activity: {err:?}");
-                continue;
+        let hop_to_hz = hopping::frequency_hz(&hop_channels_hz, frequency_hz, clock::now());
+        if hop_to_hz != tuned_frequency_hz {
+            match radio_params(
+                &mut lora,
+                preset,
+                airtime_params.spreading_factor,
+                hop_to_hz,
+            ) {
+                Ok(params) => {
+                    log::debug!("Hopped from {tuned_frequency_hz} Hz to {hop_to_hz} Hz");
+                    (mdltn_params, rx_pkt_params, tx_pkt_params) = params;
+                    tuned_frequency_hz = hop_to_hz;
+                    display::update_diagnostics(|diagnostics| {
+                        diagnostics.frequency_hz = hop_to_hz;
+                    });
+                }
+                Err(err) => {
+                    log::error!("Error hopping to {hop_to_hz} Hz: {err:?}");
+                    report_error("Hop");
+                }
+            }
+        }
+
+        if let Some(spreading_factor) = sf_step.take() {
+            match radio_params(&mut lora, preset, spreading_factor, tuned_frequency_hz) {
+                Ok(params) => {
+                    log::info!(
+                        "Spreading factor stepped from {} to {spreading_factor}",
+                        airtime_params.spreading_factor
+                    );
+                    (mdltn_params, rx_pkt_params, tx_pkt_params) = params;
+                    // Longer airtimes count against the duty cycle and ack timeouts too
+                    airtime_params.spreading_factor = spreading_factor;
+                    display::update_diagnostics(|diagnostics| {
+                        diagnostics.spreading_factor = spreading_factor;
+                    });
+                }
+                Err(err) => {
+                    log::error!("Error stepping spreading factor to {spreading_factor}: {err:?}");
+                    report_error("Adaptive SF");
+                }
+            }
+        }
+
+        let channel_is_active = match listen_mode {
+            ListenMode::Cad => {
+                // Woken back up by preparing for the next check
+                if let Err(err) = lora.sleep(true).await {
+                    log::error!("Failed to put radio to sleep: {err:?}");
+                    radio_health.failed();
+                }
+                // Short enough that a pending send still goes out within a few milliseconds
+                Timer::after_millis(random_u32_in_range(rng, RANDOM_SLEEP_RANGE_MS).into()).await;
+
+                // Use Channel Activity Detection (CAD) before receiving to save power
+                if let Err(err) = lora.prepare_for_cad(&mdltn_params).await {
+                    log::error!("Failed to prepare for cad: {err:?}");
+                    radio_health.failed();
+                    continue;
+                }
+
+                match lora.cad(&mdltn_params).await {
+                    Ok(channel_active) => {
+                        radio_health.succeeded();
+                        channel_active
+                    }
+                    Err(err) => {
+                        log::error!("Error checking channel activity: {err:?}");
+                        radio_health.failed();
+                        continue;
+                    }
+                }
             }
+            ListenMode::Continuous | ListenMode::Monitor => true,
         };
 
+        // Only send when nothing was heard, so we don't talk over anyone
+        let mut channel_is_clear = !channel_is_active;
         if channel_is_active {
+            if listen_mode == ListenMode::Cad && !outgoing_queue.is_empty() {
+                // Let whoever is talking finish, and don't jump in at the same moment as other waiting devices
+                let backoff = Duration::from_millis(
+                    random_u32_in_range_inclusive(rng, BUSY_BACKOFF_RANGE_MS).into(),
+                );
+                next_send_at = next_send_at.max(Instant::now() + backoff);
+                stats::update(|stats| stats.busy_deferrals += 1);
+            }
+
             // Fill with 0s
             recv_buf.resize_default(MAX_PAYLOAD_LEN).unwrap();
-            match receive(&mut lora, &mdltn_params, &rx_pkt_params, recv_buf).await {
+            let result = receive(
+                &mut lora,
+                &mdltn_params,
+                &rx_pkt_params,
+                rx_timeout_symbols,
+                recv_buf,
+            )
+            .await;
+            if result.is_ok() {
+                radio_health.succeeded();
+            } else {
+                radio_health.failed();
+            }
+            match result {
                 Ok(None) => {
-                    // log::debug!("RX timed out");
+                    if listen_mode == ListenMode::Cad {
+                        // Often enough of these means CAD is picking up noise, try fewer RX timeout symbols
+                        log::info!("Channel activity heard, but no packet followed");
+                    }
+                    // Always listening, so a quiet RX window is the chance to send
+                    channel_is_clear = listen_mode == ListenMode::Continuous;
                 }
-                Ok(Some(num_read)) => {
-                    log::debug!("RX'd {num_read} bytes");
+                Ok(Some((num_read, header, status))) => {
+                    let sender_id = header.sender_id;
+                    log::debug!(
+                        "RX'd {num_read} bytes from {sender_id:04X} (seq {}) RSSI {} dBm SNR {} dB",
+                        header.sequence,
+                        status.rssi,
+                        status.snr
+                    );
+                    display::update_diagnostics(|diagnostics| {
+                        diagnostics.rssi = Some(status.rssi);
+                        diagnostics.snr = Some(status.snr);
+                    });
 
-                    // Only pass the read bytes to decrypt
-                    recv_buf.truncate(num_read);
+                    if !header.is_supported_version() {
+                        log::warn!(
+                            "Ignoring packet from {sender_id:04X} with protocol version {}, expected {PROTO_VERSION}",
+                            header.version
+                        );
+                        continue;
+                    }
+
+                    // Checked before anything else is trusted, so garbled packets aren't taken for another key
+                    if !proto::crc_matches(&recv_buf[..num_read]) {
+                        log::warn!("Ignoring packet that failed its CRC, garbled on the air");
+                        report_error("CRC");
+                        stats::update(|stats| stats.crc_failures += 1);
+                        continue;
+                    }
+
+                    if sender_id == SENDER_ID {
+                        log::debug!("Ignoring own packet relayed back");
+                        continue;
+                    }
+
+                    // Only pass the read bytes to decrypt, without the CRC already checked
+                    recv_buf.truncate(num_read - CRC_SIZE);
                     if let Err(err) = decrypt_in_place(&cipher, recv_buf) {
-                        log::error!("Error decrypting packet: {err:?}");
+                        // Arrived intact, so it was sent with another key or tampered with
+                        log::error!("Error decrypting packet that passed its CRC: {err:?}");
+                        report_error("Decrypt");
+                        stats::update(|stats| {
+                            stats.decrypt_failures += 1;
+                            stats.consecutive_decrypt_failures += 1;
+                        });
+                        if stats::get().consecutive_decrypt_failures == KEY_MISMATCH_THRESHOLD {
+                            log::warn!(
+                                "{KEY_MISMATCH_THRESHOLD} packets in a row failed to decrypt, is the key right?"
+                            );
+                            display::update_status(|status| status.key_mismatch = true);
+                        }
                     } else {
+                        // Only trust the header once it's authenticated, so forgeries can't get real packets ignored
+                        // or acknowledged
+                        let key = (sender_id, header.sequence);
+                        if acked.is_duplicate(&key) {
+                            // Its sender missed the acknowledgement and is resending it, so only the acknowledgement
+                            // is repeated. Copies of it heard before that's sent don't queue another.
+                            if pending_acks
+                                .iter()
+                                .all(|ack| (ack.sender_id, ack.sequence) != key)
+                            {
+                                log::debug!(
+                                    "Acknowledging repeated message from {sender_id:04X} again"
+                                );
+                                let send_at =
+                                    ack_send_at(&airtime_params, transmit_times, num_read, rng);
+                                queue_ack(&mut pending_acks, sender_id, header.sequence, send_at);
+                            }
+                            continue;
+                        }
+                        if recent_packets.is_duplicate(&key) {
+                            log::debug!("Ignoring repeated packet from {sender_id:04X}");
+                            continue;
+                        }
+                        if !replay_guard.accept(sender_id, header.sequence) {
+                            log::warn!(
+                                "Rejected replayed packet from {sender_id:04X} (seq {})",
+                                header.sequence
+                            );
+                            continue;
+                        }
+                        recent_packets.insert(key);
+
+                        stats::update(|stats| {
+                            stats.received += 1;
+                            stats.consecutive_decrypt_failures = 0;
+                        });
+                        // Someone has the same key after all
+                        display::update_status(|status| status.key_mismatch = false);
+
+                        // Already parsed before decrypting, so the header is known to be well-formed
+                        let packet = Packet::deserialize_from(recv_buf).unwrap();
+                        let relayed_header = header
+                            .relayed_by(SENDER_ID)
+                            .filter(|_| info.relay && listen_mode.transmits());
+                        if let Some(relayed_header) = relayed_header {
+                            let mut plaintext = Vec::new();
+                            // Never longer than what this device sends itself, unless the sender is misbehaving
+                            if plaintext.extend_from_slice(packet.payload).is_ok() {
+                                // Not while the sender is still sending copies, or they'd drown each other out
+                                let delay = random_u32_in_range(rng, RELAY_DELAY_RANGE_MS);
+                                enqueue(
+                                    &mut outgoing_queue,
+                                    Queued::Relay(Relay {
+                                        header: relayed_header,
+                                        plaintext,
+                                        send_at: Instant::now()
+                                            + remaining_copies_airtime(
+                                                &airtime_params,
+                                                transmit_times,
+                                                num_read,
+                                            )
+                                            + Duration::from_millis(delay.into()),
+                                    }),
+                                );
+                            }
+                        }
+
+                        if header.packet_type == PacketType::TimeSync {
+                            // Authenticated along with the rest of the header, so only the fleet can set the time
+                            if header.timestamp != UNSYNCED_TIMESTAMP {
+                                clock::adopt(header.timestamp);
+                            }
+                            continue;
+                        }
+                        if header.packet_type == PacketType::Test {
+                            log::info!(
+                                "Heard test packet from {sender_id:04X}, RSSI {} dBm SNR {} dB",
+                                status.rssi,
+                                status.snr
+                            );
+                            continue;
+                        }
+                        if header.packet_type == PacketType::Ack {
+                            let Ok(ack) = <[u8; ACK_SIZE]>::try_from(packet.payload) else {
+                                log::error!("Received malformed ack from {sender_id:04X}");
+                                continue;
+                            };
+                            let (acked_id, acked_sequence) = ack.split_at(SENDER_ID_SIZE);
+                            let acked_id = u16::from_le_bytes(acked_id.try_into().unwrap());
+                            let acked_sequence =
+                                u32::from_le_bytes(acked_sequence.try_into().unwrap());
+
+                            if acked_id != SENDER_ID
+                                || awaiting_ack
+                                    .as_ref()
+                                    .is_none_or(|awaiting| awaiting.sequence != acked_sequence)
+                            {
+                                // Not for the message this device is waiting on
+                                continue;
+                            }
+
+                            let outgoing = awaiting_ack.take().unwrap().outgoing;
+                            log::info!(
+                                "{sender_id:04X} acknowledged message after {} attempt(s)",
+                                outgoing.attempts
+                            );
+                            sf_step = sf_adapter.as_mut().and_then(SfAdapter::succeeded);
+                            let out_msg = sender.send().await;
+                            *out_msg =
+                                DisplayMessage::delivery_status(true, sent_text(outgoing.kind));
+                            sender.send_done();
+                            continue;
+                        }
+
+                        // Relayed above regardless, so the device it's for still gets it
+                        if !header.is_for(SENDER_ID) {
+                            log::debug!(
+                                "Ignoring message from {sender_id:04X} for {:04X}",
+                                header.destination
+                            );
+                            continue;
+                        }
+
+                        // Relayed above regardless, so the rest of the fleet still gets it
+                        if !header.is_from_allowed(&info.allowed_senders) {
+                            log::debug!(
+                                "Ignoring message from {sender_id:04X}, not on the allow list"
+                            );
+                            continue;
+                        }
+
                         // use received packet through recv_buf
-                        let data = &recv_buf[MAGIC_WORD_SIZE..];
+                        let Some((&kind, data)) = packet.payload.split_first() else {
+                            log::error!("Received packet without a payload kind");
+                            continue;
+                        };
 
-                        let output = match core::str::from_utf8(data) {
-                            Ok(str_data) => str_data,
+                        let Some(data) = reassembler.insert(
+                            sender_id,
+                            header.sequence,
+                            header.fragment,
+                            header.fragment_count,
+                            data,
+                        ) else {
+                            log::debug!(
+                                "Waiting for the rest of the fragments from {sender_id:04X}"
+                            );
+                            continue;
+                        };
+
+                        let kind = match MessageKind::try_from(kind) {
+                            Ok(kind) => kind,
                             Err(err) => {
-                                log::error!("Non-utf8 packet: {err:?}");
+                                log::error!("Unknown payload kind: {}", err.number);
                                 continue;
                             }
                         };
+                        // Button presses are followed by the sender's station, if it has picked one
+                        let station = kind
+                            .button()
+                            .and(data.first())
+                            .and_then(|&station| Station::try_from(station).ok());
+                        let mut all_good = heapless::String::<ALL_GOOD_CAPACITY>::new();
+                        let mut lossy = heapless::String::<MAX_FRAGMENTED_MSG_LEN>::new();
+                        let output = match kind {
+                            MessageKind::Text => match core::str::from_utf8(data) {
+                                Ok(text) => text,
+                                Err(err) => {
+                                    log::warn!("Non-utf8 packet: {err:?}, showing what's readable");
+                                    // Never longer than the reassembled message, so always fits
+                                    write!(lossy, "{}", LossyText(data)).unwrap();
+                                    &lossy
+                                }
+                            },
+                            MessageKind::Good => {
+                                // Fits the longest station name
+                                write!(all_good, "{}", AllGood { station }).unwrap();
+                                &all_good
+                            }
+                            MessageKind::Help => Button::Help.message(),
+                        };
                         log::info!("Received packet: {output:?}");
 
-                        if output.len() <= 128 {
-                            let out_msg = sender.send().await;
-                            *out_msg = DisplayMessage::Message(output.try_into().unwrap());
-                            sender.send_done();
+                        // Acknowledge the whole message once it's here, so the sender stops repeating it
+                        if !listen_mode.transmits() {
+                            log::debug!("Monitoring, not acknowledging message");
+                        } else if battery::level() == BatteryLevel::Critical
+                            && kind != MessageKind::Help
+                        {
+                            log::debug!("Battery critical, not acknowledging message");
                         } else {
-                            log::error!("Received message too long to display");
+                            let send_at =
+                                ack_send_at(&airtime_params, transmit_times, num_read, rng);
+                            queue_ack(&mut pending_acks, sender_id, header.sequence, send_at);
+                            acked.insert(key);
+                        }
+
+                        display::update_status(|display_status| {
+                            display_status.signal_bars = Some(signal_bars(status.rssi));
+                        });
+                        let out_msg = sender.send().await;
+                        *out_msg = if kind == MessageKind::Help {
+                            DisplayMessage::help_needed(sender_id, station)
+                        } else {
+                            // Unknown if the sender or this device has never been synced
+                            let sent_at = if header.timestamp == UNSYNCED_TIMESTAMP {
+                                None
+                            } else {
+                                clock::to_local(header.timestamp)
+                            };
+                            DisplayMessage::from_sender(
+                                sender_id,
+                                header.destination == SENDER_ID,
+                                signal_bars(status.rssi),
+                                sent_at,
+                                output,
+                            )
+                        };
+                        sender.send_done();
+
+                        if kind != MessageKind::Help {
+                            history_signal.signal(StoredMessage {
+                                text: display::truncated(format_args!(""), output),
+                                sender_id,
+                                boot_count: info.boot_count,
+                                uptime_secs: u32::try_from(Instant::now().as_secs())
+                                    .unwrap_or(u32::MAX),
+                            });
+                        }
+
+                        // Let a connected central know too, cut off at the last full character that fits
+                        let mut end = output.len().min(MESSAGE_CAPACITY);
+                        while !output.is_char_boundary(end) {
+                            end -= 1;
                         }
+                        let mut received_msg = trouble_host::prelude::HeaplessString::new();
+                        received_msg.push_str(&output[..end]).unwrap();
+                        received_msg_signal.signal(received_msg);
+                    }
+                }
+                Err(err) => {
+                    log::error!("Error rx: {err:?}");
+                    report_error("RX");
+                }
+            }
+        }
+
+        if channel_is_clear {
+            if let Some(index) = pending_acks
+                .iter()
+                .position(|ack| Instant::now() >= ack.send_at)
+            {
+                let pending = pending_acks.remove(index);
+                let airtime = airtime_params.airtime(packet_len(ACK_SIZE)) * transmit_times.into();
+                if duty_cycle.wait_time(airtime).is_some() {
+                    // Sent again if the message is resent
+                    log::warn!("Duty cycle limit reached, not acknowledging message");
+                } else {
+                    sequence = sequence.wrapping_add(1);
+                    let mut ack = [0; ACK_SIZE];
+                    let (acked_id, acked_sequence) = ack.split_at_mut(SENDER_ID_SIZE);
+                    acked_id.copy_from_slice(&pending.sender_id.to_le_bytes());
+                    acked_sequence.copy_from_slice(&pending.sequence.to_le_bytes());
+                    if build_outgoing(
+                        &cipher,
+                        rng,
+                        &mut recent_nonces,
+                        &Packet {
+                            header: own_header(PacketType::Ack, pending.sender_id, sequence),
+                            payload: &ack,
+                        },
+                        send_buf,
+                    )
+                    .is_err()
+                    {
+                        log::error!("Didn't send ack due to encryption error");
+                    } else if let Err(err) = send(
+                        &mut lora,
+                        &mdltn_params,
+                        &mut tx_pkt_params,
+                        tx_power,
+                        transmit_times,
+                        send_buf,
+                    )
+                    .await
+                    {
+                        log::error!("Error tx ack: {err:?}");
+                        report_error("TX ack");
+                        radio_health.failed();
+                    } else {
+                        radio_health.succeeded();
+                        duty_cycle.record(airtime);
+                        stats::update(|stats| stats.sent += 1);
+                        led::packet_sent();
+                    }
+                }
+            }
+
+            if clock::TIME_SET.try_take().is_some() {
+                last_time_sync = None;
+            }
+            // Not worth the battery once it's low
+            if clock::is_source()
+                && battery::level() == BatteryLevel::Normal
+                && last_time_sync.is_none_or(|at| at.elapsed() >= clock::SYNC_INTERVAL)
+                && Instant::now() >= next_send_at
+            {
+                let airtime = airtime_params.airtime(packet_len(0)) * transmit_times.into();
+                // Tried again on the next loop once the duty cycle allows it
+                if duty_cycle.wait_time(airtime).is_none() {
+                    last_time_sync = Some(Instant::now());
+                    sequence = sequence.wrapping_add(1);
+                    let header = own_header(PacketType::TimeSync, BROADCAST_ID, sequence);
+                    if build_outgoing(
+                        &cipher,
+                        rng,
+                        &mut recent_nonces,
+                        &Packet {
+                            header,
+                            payload: &[],
+                        },
+                        send_buf,
+                    )
+                    .is_err()
+                    {
+                        log::error!("Didn't send time sync due to encryption error");
+                    } else if let Err(err) = send(
+                        &mut lora,
+                        &mdltn_params,
+                        &mut tx_pkt_params,
+                        tx_power,
+                        transmit_times,
+                        send_buf,
+                    )
+                    .await
+                    {
+                        log::error!("Error tx time sync: {err:?}");
+                        report_error("TX time");
+                        radio_health.failed();
+                    } else {
+                        log::debug!("Broadcast time {}", header.timestamp);
+                        radio_health.succeeded();
+                        duty_cycle.record(airtime);
+                        stats::update(|stats| stats.sent += 1);
+                        led::packet_sent();
                     }
                 }
-                Err(err) => log::error!("Error rx: {err:?}"),
             }
-        } else {
-            let Some(send_data) = ({
-                bt_msg_signal.try_take().map_or_else(
-                    || {
-                        // If no bt msg, try button
-                        input_signal.try_take().map(|pressed_button| {
-                            match pressed_button {
-                                Button::Help => &b"HELP NEEDED"[..],
-                                Button::Good => &b"All good!"[..],
+
+            if test_packets_left > 0 && Instant::now() >= next_send_at.max(next_test_at) {
+                let airtime =
+                    airtime_params.airtime(packet_len(TEST_PAYLOAD.len())) * transmit_times.into();
+                // Tried again on the next loop once the duty cycle allows it
+                if duty_cycle.wait_time(airtime).is_none() {
+                    let number = TEST_PACKETS - test_packets_left + 1;
+                    test_packets_left -= 1;
+                    next_test_at = Instant::now() + TEST_PACKET_INTERVAL;
+                    sequence = sequence.wrapping_add(1);
+                    // Cut short if the error doesn't fit
+                    let mut result = trouble_host::prelude::HeaplessString::new();
+                    if build_outgoing(
+                        &cipher,
+                        rng,
+                        &mut recent_nonces,
+                        &Packet {
+                            // Only meant for devices in range of this one
+                            header: PacketHeader {
+                                hops: 0,
+                                ..own_header(PacketType::Test, BROADCAST_ID, sequence)
+                            },
+                            payload: TEST_PAYLOAD,
+                        },
+                        send_buf,
+                    )
+                    .is_err()
+                    {
+                        let _ = write!(result, "Test {number}/{TEST_PACKETS} failed to encrypt");
+                    } else {
+                        match send(
+                            &mut lora,
+                            &mdltn_params,
+                            &mut tx_pkt_params,
+                            tx_power,
+                            transmit_times,
+                            send_buf,
+                        )
+                        .await
+                        {
+                            Ok(()) => {
+                                radio_health.succeeded();
+                                duty_cycle.record(airtime);
+                                stats::update(|stats| stats.sent += 1);
+                                led::packet_sent();
+                                let _ = write!(result, "Test {number}/{TEST_PACKETS} sent");
                             }
-                            .try_into()
-                            .unwrap()
-                        })
-                    },
-                    |bt_msg| Some(bt_msg.into_bytes()),
-                )
-            }) else {
+                            Err(err) => {
+                                radio_health.failed();
+                                let _ =
+                                    write!(result, "Test {number}/{TEST_PACKETS} failed: {err:?}");
+                            }
+                        }
+                    }
+
+                    log::info!("{result}");
+                    let out_msg = sender.send().await;
+                    *out_msg = DisplayMessage::alert(&result, TEST_ALERT_DURATION);
+                    sender.send_done();
+                    TEST_RESULT.signal(result);
+                }
+            }
+
+            if awaiting_ack
+                .as_ref()
+                .is_some_and(|awaiting| Instant::now() >= awaiting.retry_at)
+            {
+                let outgoing = awaiting_ack.take().unwrap().outgoing;
+                sf_step = sf_adapter.as_mut().and_then(SfAdapter::failed);
+                if outgoing.attempts < MAX_SEND_ATTEMPTS {
+                    log::warn!("No ack after {} attempt(s), resending", outgoing.attempts);
+                    enqueue(&mut outgoing_queue, Queued::Message(outgoing));
+                } else {
+                    log::error!("Giving up on message after {} attempts", outgoing.attempts);
+                    let out_msg = sender.send().await;
+                    *out_msg = DisplayMessage::delivery_status(false, sent_text(outgoing.kind));
+                    sender.send_done();
+                }
+                if sf_step.is_some() {
+                    // Switched over at the top of the loop, so the resend goes out at the new spreading factor
+                    continue;
+                }
+            }
+
+            if Instant::now() < next_send_at {
+                // Still waiting for the duty cycle to allow sending
+                continue;
+            }
+
+            let now = Instant::now();
+            // Queued before the battery got low, so kept until it's charged or they're pushed out
+            let sendable = min_priority(battery::level());
+            // Only send the next message once the last one is done with, unless it's more urgent
+            let ready = |priority: Priority, queued: &Queued| {
+                priority >= sendable
+                    && match queued {
+                        Queued::Message(_) => awaiting_ack.as_ref().is_none_or(|awaiting| {
+                            priority > self::priority(awaiting.outgoing.kind)
+                        }),
+                        Queued::Relay(relay) => now >= relay.send_at,
+                    }
+            };
+            let Some(next) = outgoing_queue.peek(ready) else {
                 // Nothing to send right now
                 continue;
             };
-            send_buf.clear();
-            send_buf
-                .extend_from_slice(&MAGIC_WORD.to_le_bytes())
-                .unwrap();
 
-            match core::str::from_utf8(&send_data) {
-                Ok(str) => log::info!("Sending message: {str}"),
+            let airtime = next.airtime(&airtime_params) * transmit_times.into();
+            if let Some(wait) = duty_cycle.wait_time(airtime) {
+                log::warn!(
+                    "Duty cycle limit reached, deferring send for {}s",
+                    wait.as_secs()
+                );
+                next_send_at = Instant::now() + wait;
+                continue;
+            }
+
+            // Another device may have decided to send at the same moment, so check again after a random wait
+            match listen_before_talk(&mut lora, &mdltn_params, rng).await {
+                Ok(true) => radio_health.succeeded(),
+                Ok(false) => {
+                    log::debug!("Channel stayed busy before sending, holding off");
+                    let backoff = Duration::from_millis(
+                        random_u32_in_range_inclusive(rng, BUSY_BACKOFF_RANGE_MS).into(),
+                    );
+                    next_send_at = Instant::now() + backoff;
+                    stats::update(|stats| stats.busy_deferrals += 1);
+                    continue;
+                }
+                Err(err) => {
+                    log::error!("Error checking channel before sending: {err:?}");
+                    radio_health.failed();
+                    continue;
+                }
+            }
+
+            // Just peeked at it
+            let mut outgoing = match outgoing_queue.pop(ready).unwrap() {
+                Queued::Message(outgoing) => outgoing,
+                Queued::Relay(relay) => {
+                    log::debug!(
+                        "Relaying packet from {:04X} (seq {}), {} hop(s) left",
+                        relay.header.sender_id,
+                        relay.header.sequence,
+                        relay.header.hops
+                    );
+                    if build_outgoing(
+                        &cipher,
+                        rng,
+                        &mut recent_nonces,
+                        &Packet {
+                            header: relay.header,
+                            payload: &relay.plaintext,
+                        },
+                        send_buf,
+                    )
+                    .is_err()
+                    {
+                        log::error!("Didn't relay packet due to encryption error");
+                    } else if let Err(err) = send(
+                        &mut lora,
+                        &mdltn_params,
+                        &mut tx_pkt_params,
+                        tx_power,
+                        transmit_times,
+                        send_buf,
+                    )
+                    .await
+                    {
+                        log::error!("Error tx relay: {err:?}");
+                        report_error("TX relay");
+                        radio_health.failed();
+                    } else {
+                        radio_health.succeeded();
+                        duty_cycle.record(airtime);
+                        stats::update(|stats| stats.sent += 1);
+                        led::packet_sent();
+                    }
+                    continue;
+                }
+            };
+            if let Some(preempted) = awaiting_ack.take() {
+                log::info!(
+                    "Sending {:?} ahead of {:?} waiting for an ack",
+                    outgoing.kind,
+                    preempted.outgoing.kind
+                );
+                // Sent again once this one is done with, acked or not
+                enqueue(&mut outgoing_queue, Queued::Message(preempted.outgoing));
+            }
+            // Only the first attempt, retries happen in the background
+            let show_progress = outgoing.attempts == 0;
+            if show_progress {
+                // Replaced by the result once it's sent, this is only in case that never comes
+                let out_msg = sender.send().await;
+                *out_msg = DisplayMessage::alert(SENDING_ALERT, airtime + SEND_ALERT_DURATION);
+                sender.send_done();
+            }
+
+            let kind = outgoing.kind;
+            let send_data = &outgoing.data;
+            match core::str::from_utf8(send_data) {
+                Ok(str) if kind == MessageKind::Text => log::info!("Sending message: {str}"),
+                Ok(_) => log::info!("Sending {kind:?}"),
                 Err(_) => log::info!("Sending bytes: {send_data:?}"),
             }
 
-            send_buf.extend_from_slice(&send_data).unwrap();
+            let mut sent = true;
+            let fragment_count =
+                u8::try_from(fragment::split(send_data, MAX_MSG_LEN).count()).unwrap();
+            // Numbered once, so receivers recognize resends and acknowledge them again instead of showing them twice
+            let first_sequence = *outgoing.first_sequence.get_or_insert_with(|| {
+                let first_sequence = sequence.wrapping_add(1);
+                sequence = sequence.wrapping_add(fragment_count.into());
+                first_sequence
+            });
+            for (index, fragment) in (0..).zip(fragment::split(send_data, MAX_MSG_LEN)) {
+                let sequence = first_sequence.wrapping_add(index.into());
+                let mut payload = Vec::<u8, { KIND_SIZE + MAX_MSG_LEN }>::new();
+                payload.push(kind as u8).unwrap();
+                payload.extend_from_slice(fragment).unwrap();
+                if build_outgoing(
+                    &cipher,
+                    rng,
+                    &mut recent_nonces,
+                    &Packet {
+                        header: PacketHeader {
+                            fragment: index,
+                            fragment_count,
+                            ..own_header(PacketType::Data, outgoing.destination, sequence)
+                        },
+                        payload: &payload,
+                    },
+                    send_buf,
+                )
+                .is_err()
+                {
+                    log::error!("Didn't send packet due to encryption error");
+                    sent = false;
+                    break;
+                }
 
-            // Must have prepended MAGIC_WORD before this
-            if encrypt_in_place(&cipher, rng, send_buf).is_ok() {
-                match send(&mut lora, &mdltn_params, &mut tx_pkt_params, send_buf).await {
+                match send(
+                    &mut lora,
+                    &mdltn_params,
+                    &mut tx_pkt_params,
+                    tx_power,
+                    transmit_times,
+                    send_buf,
+                )
+                .await
+                {
                     Ok(()) => {
-                        log::debug!("sent out pkt");
+                        log::debug!("sent out pkt {}/{fragment_count}", index + 1);
+                        radio_health.succeeded();
+                        stats::update(|stats| stats.sent += 1);
+                        led::packet_sent();
+                    }
+                    Err(err) => {
+                        log::error!("Error tx: {err:?}");
+                        report_error("TX");
+                        radio_health.failed();
+                        sent = false;
+                        break;
                     }
-                    Err(err) => log::error!("Error tx: {err:?}"),
                 }
-            } else {
-                log::error!("Didn't send packet due to encryption error");
             }
+            duty_cycle.record(airtime);
+
+            if show_progress {
+                let out_msg = sender.send().await;
+                *out_msg = DisplayMessage::alert(
+                    if sent { SENT_ALERT } else { SEND_FAILED_ALERT },
+                    SEND_ALERT_DURATION,
+                );
+                sender.send_done();
+            }
+
+            // Back off exponentially, with some jitter so devices resending at once don't keep colliding
+            let ack_airtime = airtime_params.airtime(packet_len(ACK_SIZE)) * transmit_times.into();
+            let backoff = (ACK_TIMEOUT + ack_airtime) * (1 << outgoing.attempts)
+                + Duration::from_millis(random_u32_in_range(rng, RESEND_JITTER_RANGE_MS).into());
+            outgoing.attempts += 1;
+            awaiting_ack = Some(AwaitingAck {
+                outgoing,
+                sequence: first_sequence.wrapping_add(u32::from(fragment_count) - 1),
+                retry_at: Instant::now() + backoff,
+            });
+        }
+    }
+}
+
+/// Shows `what` as the last thing to go wrong with the radio on the diagnostics screen.
+fn report_error(what: &'static str) {
+    display::update_diagnostics(|diagnostics| diagnostics.last_error = Some(what));
+}
+
+/// Maps the RSSI of a received packet, in dBm, to 0-4 signal strength bars for the UI.
+/// Packets are accepted no matter how weak they are, this is only for reporting.
+pub const fn signal_bars(rssi: i16) -> u8 {
+    match rssi {
+        -70.. => 4,
+        -85..-70 => 3,
+        -100..-85 => 2,
+        -115..-100 => 1,
+        _ => 0,
+    }
+}
+
+/// Sends [`TEST_PACKETS`] test packets at the current settings, through the same encryption and radio path as any
+/// other packet, showing each one's result on the display and signaling it on [`TEST_RESULT`]. Any device that hears
+/// them only logs them, so a new board's radio can be checked on its own without disturbing the fleet.
+pub fn request_test() {
+    TEST_REQUESTED.signal(());
+}
+
+/// Sends messages written over BLE from now on to only the device with ID `destination`, or to every device if it's
+/// [`BROADCAST_ID`]. Button presses always go to every device.
+pub fn set_message_destination(destination: u16) {
+    MESSAGE_DESTINATION.store(destination, Ordering::Relaxed);
+}
+
+/// Takes down the key mismatch hint and starts counting decrypt failures in a row over, e.g. once the user knows a
+/// device with another key is nearby.
+pub fn clear_key_mismatch() {
+    stats::update(|stats| stats.consecutive_decrypt_failures = 0);
+    display::update_status(|status| status.key_mismatch = false);
+}
+
+/// Header of a packet this device sends itself, as the only fragment, stamped with the time if it's known. Relayed
+/// packets keep their sender's header instead.
+fn own_header(packet_type: PacketType, destination: u16, sequence: u32) -> PacketHeader {
+    PacketHeader {
+        version: PROTO_VERSION,
+        packet_type,
+        sender_id: SENDER_ID,
+        destination,
+        sequence,
+        timestamp: clock::now().unwrap_or(UNSYNCED_TIMESTAMP),
+        hops: MAX_HOPS,
+        fragment: 0,
+        fragment_count: 1,
+    }
+}
+
+/// Length of the packet sent for `plaintext_len` bytes of plaintext.
+fn packet_len(plaintext_len: usize) -> u8 {
+    u8::try_from(HEADER_SIZE + plaintext_len + MAC_SIZE + NONCE_SIZE + CRC_SIZE).unwrap_or(u8::MAX)
+}
+
+/// Airtime of the copies of a `received_len` byte packet its sender may still be sending, assuming it was heard on the
+/// first of them and the sender transmits each packet `transmit_times` times like this device. The radio can't hear
+/// while it's sending, so anything sent to the sender before it's done is lost.
+fn remaining_copies_airtime(
+    airtime_params: &AirtimeParams,
+    transmit_times: u8,
+    received_len: usize,
+) -> Duration {
+    airtime_params.airtime(u8::try_from(received_len).unwrap_or(u8::MAX))
+        * u32::from(transmit_times.saturating_sub(1))
+}
+
+/// When to acknowledge a `received_len` byte packet that just arrived: once its sender is done sending copies of it, see
+/// [`remaining_copies_airtime`], and some jitter.
+fn ack_send_at(
+    airtime_params: &AirtimeParams,
+    transmit_times: u8,
+    received_len: usize,
+    rng: &mut impl RngCore,
+) -> Instant {
+    Instant::now()
+        + remaining_copies_airtime(airtime_params, transmit_times, received_len)
+        + Duration::from_millis(random_u32_in_range(rng, ACK_JITTER_RANGE_MS).into())
+}
+
+/// Queues an acknowledgement of `sender_id`'s packet `sequence`, dropping the oldest waiting if there's no room.
+fn queue_ack(
+    pending_acks: &mut Vec<PendingAck, PENDING_ACKS_CAPACITY>,
+    sender_id: u16,
+    sequence: u32,
+    send_at: Instant,
+) {
+    if pending_acks.is_full() {
+        log::warn!("Too many acknowledgements waiting, dropping the oldest");
+        pending_acks.remove(0);
+    }
+    let _ = pending_acks.push(PendingAck {
+        sender_id,
+        sequence,
+        send_at,
+    });
+}
+
+/// Waits a random part of [`listen_before_talk::BACKOFF_RANGE_MS`] then checks for channel activity, up to
+/// [`listen_before_talk::MAX_ATTEMPTS`] times. Returns whether the channel was clear, so it's safe to send right away.
+async fn listen_before_talk(
+    lora: &mut LoRa<impl RadioKind, impl DelayNs>,
+    modulation_params: &ModulationParams,
+    rng: &mut impl RngCore,
+) -> Result<bool, RadioError> {
+    let mut lbt = ListenBeforeTalk::new();
+    while let Some(backoff_ms) = lbt.next_backoff_ms(rng) {
+        Timer::after_millis(backoff_ms.into()).await;
+        lora.prepare_for_cad(modulation_params).await?;
+        if !lora.cad(modulation_params).await? {
+            return Ok(true);
         }
+        log::debug!(
+            "Channel busy right before sending ({}/{})",
+            lbt.attempts(),
+            listen_before_talk::MAX_ATTEMPTS
+        );
     }
+    Ok(false)
+}
+
+/// Modulation, RX packet, and TX packet params for `preset`, at `spreading_factor` in place of the preset's own.
+/// Payloads up to [`MAX_PAYLOAD_LEN`] fit at any spreading factor, so only the airtime changes with it.
+fn radio_params(
+    lora: &mut LoRa<impl RadioKind, impl DelayNs>,
+    preset: LoraPreset,
+    spreading_factor: u8,
+    frequency_hz: u32,
+) -> Result<(ModulationParams, PacketParams, PacketParams), RadioError> {
+    let spreading_factor = match spreading_factor {
+        7 => SpreadingFactor::_7,
+        8 => SpreadingFactor::_8,
+        9 => SpreadingFactor::_9,
+        10 => SpreadingFactor::_10,
+        11 => SpreadingFactor::_11,
+        _ => SpreadingFactor::_12,
+    };
+    let modulation_params = lora.create_modulation_params(
+        spreading_factor,
+        preset.bandwidth(),
+        preset.coding_rate(),
+        frequency_hz,
+    )?;
+    let rx_packet_params = lora.create_rx_packet_params(
+        preset.preamble_len(),
+        false,
+        u8::try_from(MAX_PAYLOAD_LEN).unwrap(),
+        true,
+        false,
+        &modulation_params,
+    )?;
+    let tx_packet_params = lora.create_tx_packet_params(
+        preset.preamble_len(),
+        false,
+        true,
+        false,
+        &modulation_params,
+    )?;
+    Ok((modulation_params, rx_packet_params, tx_packet_params))
 }
 
 async fn send(
     lora: &mut LoRa<impl RadioKind, impl DelayNs>,
     modulation_params: &ModulationParams,
     packet_params: &mut PacketParams,
+    tx_power: i32,
+    times: u8,
     buf: &[u8],
 ) -> Result<(), RadioError> {
-    // Transmit each packet multiple times to increase the chance other devices receive it
-    for _ in 0..TRANSMIT_PKT_TIMES {
+    // Transmit each packet multiple times to increase the chance other devices receive it, receivers ignore the copies
+    for _ in 0..times {
         match lora
-            .prepare_for_tx(modulation_params, packet_params, TX_POWER, buf)
+            .prepare_for_tx(modulation_params, packet_params, tx_power, buf)
             .await
         {
             Ok(()) => {}
@@ -289,10 +1803,15 @@ async fn receive(
     lora: &mut LoRa<impl RadioKind, impl DelayNs>,
     modulation_params: &ModulationParams,
     packet_params: &PacketParams,
+    timeout_symbols: u16,
     buf: &mut [u8],
-) -> Result<Option<usize>, RadioError> {
+) -> Result<Option<(usize, PacketHeader, PacketStatus)>, RadioError> {
     match lora
-        .prepare_for_rx(RxMode::Single(128), modulation_params, packet_params)
+        .prepare_for_rx(
+            RxMode::Single(timeout_symbols),
+            modulation_params,
+            packet_params,
+        )
         .await
     {
         Ok(()) => {}
@@ -305,12 +1824,12 @@ async fn receive(
     // log::info!("LoRa rx-ing");
 
     match lora.rx(packet_params, buf).await {
-        Ok((received_len, _rx_pkt_status)) => {
-            if received_len >= u8::try_from(MAGIC_WORD_SIZE).unwrap()
-                && buf[..MAGIC_WORD_SIZE] == MAGIC_WORD.to_le_bytes()
-            {
-                // Only return received bytes if they start with the "magic word"
-                Ok(Some(received_len.into()))
+        Ok((received_len, status)) => {
+            let header =
+                Packet::deserialize_from(&buf[..received_len.into()]).map(|packet| packet.header);
+            if let Some(header) = header {
+                // Only return received bytes if they start with the "magic word" and have a known packet type
+                Ok(Some((received_len.into(), header, status)))
             } else {
                 log::info!("rx unknown packet");
                 Ok(None)
@@ -321,56 +1840,17 @@ async fn receive(
     }
 }
 
-/// Encrypts the contents of `buf` in-place. The first `MAGIC_WORD_SIZE` bytes should be the magic word before calling, and the rest is the plaintext.
-///
-/// After a successful call, `buf` will have structure: `MAGIC (MAGIC_WORD_SIZE-bytes) | CIPHERTEXT | MAC (16-bytes) | NONCE (16-bytes)`
-fn encrypt_in_place<const N: usize>(
-    cipher: &AsconAead128,
-    rng: &mut impl RngCore,
-    buf: &mut ascon_aead::aead::heapless::Vec<u8, N>,
-) -> ascon_aead::aead::Result<()> {
-    if buf.capacity() - buf.len() < MAC_SIZE + NONCE_SIZE + MAGIC_WORD_SIZE {
-        log::error!("encrypt buf too small for data, mac, and nonce");
-        return Err(ascon_aead::Error);
+/// Hashes a device's `ID` string into its [`SENDER_ID`] with 32-bit FNV-1a, folded down to 16 bits.
+#[allow(clippy::cast_possible_truncation)]
+const fn sender_id(id: &str) -> u16 {
+    let bytes = id.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
     }
 
-    let nonce = generate_nonce(rng);
-    let tag = cipher.encrypt_in_place_detached(&nonce, &[], &mut buf[MAGIC_WORD_SIZE..])?;
-    buf.extend_from_slice(&tag).unwrap();
-    buf.extend_from_slice(&nonce).unwrap();
-
-    Ok(())
-}
-
-/// Decrypts the contents of `buf` in-place. At call-time, buf should have structure: `MAGIC (MAGIC_WORD_SIZE-bytes) | CIPHERTEXT | MAC (16-bytes) | NONCE (16-bytes)`
-///
-/// After this function is successful, `buf` will have the structure: `MAGIC (MAGIC_WORD_SIZE-bytes) | PLAINTEXT`
-fn decrypt_in_place<const N: usize>(
-    cipher: &AsconAead128,
-    buf: &mut ascon_aead::aead::heapless::Vec<u8, N>,
-) -> ascon_aead::aead::Result<()> {
-    if buf.len() < 32 {
-        log::error!("Invalid decrypt buf len: {}", buf.len());
-        return Err(ascon_aead::Error);
-    }
-
-    let tag_pos = buf.len() - 32;
-    let (ciphertext, tag_and_nonce) = buf.split_at_mut(tag_pos);
-    let (tag, nonce) = tag_and_nonce.split_at_mut(16);
-
-    cipher.decrypt_in_place_detached(
-        ascon_aead::AsconAead128Nonce::from_slice(nonce),
-        &[],
-        &mut ciphertext[MAGIC_WORD_SIZE..],
-        ascon_aead::Tag::<AsconAead128>::from_slice(tag),
-    )?;
-    buf.truncate(tag_pos);
-
-    Ok(())
-}
-
-fn generate_nonce(rng: &mut impl RngCore) -> ascon_aead::AsconAead128Nonce {
-    let mut bytes = [0; 16];
-    rng.fill_bytes(&mut bytes);
-    ascon_aead::AsconAead128Nonce::clone_from_slice(&bytes)
+    ((hash >> 16) ^ hash) as u16
 }
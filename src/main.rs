@@ -1,32 +1,43 @@
 #![no_std]
 #![no_main]
 
+mod backlight;
+mod battery;
 mod bt_server;
+mod clock;
 mod display;
 mod input;
+mod led;
+mod logger;
 mod lora;
 mod peri;
-mod proto;
+mod stats;
 mod storage;
 
 use core::num::NonZeroU128;
 
 use embassy_executor::{Executor, Spawner};
 use embassy_futures::join;
+use embassy_futures::select::{Either3, Either4, select3, select4};
+use embassy_rp::adc::{self, Adc};
 use embassy_rp::clocks::RoscRng;
 use embassy_rp::gpio::Pull;
 use embassy_rp::multicore::{Stack, spawn_core1};
+use embassy_rp::pwm::{self, Pwm};
 use embassy_rp::{bind_interrupts, gpio, peripherals::USB, usb};
 use embassy_sync::blocking_mutex::raw::{CriticalSectionRawMutex, NoopRawMutex};
+use embassy_sync::mutex::Mutex;
 use embassy_sync::signal::Signal;
 use embassy_sync::zerocopy_channel;
-use embassy_time::{Delay, Timer};
+use embassy_time::{Delay, Instant, Timer};
 use embedded_hal_bus::spi::ExclusiveDevice;
 use gpio::{Input, Level, Output};
 
+use crate::battery::BatteryReading;
 use crate::display::DisplayMessage;
-use crate::input::Button;
+use crate::input::ButtonEvent;
 use crate::peri::{Core0Peripherals, Core1Peripherals};
+use crate::storage::StoredMessage;
 use cyw43_pio::{PioSpi, RM2_CLOCK_DIVIDER};
 use embassy_rp::peripherals::{DMA_CH0, PIO0, PIO1};
 use embassy_rp::pio::{self, Pio};
@@ -52,6 +63,7 @@ bind_interrupts!(struct Irqs {
     PIO0_IRQ_0 => pio::InterruptHandler<PIO0>;
     USBCTRL_IRQ => usb::InterruptHandler<USB>;
     PIO1_IRQ_0 => pio::InterruptHandler<PIO1>;
+    ADC_IRQ_FIFO => adc::InterruptHandler;
 });
 
 const FLASH_SIZE: usize = 4 * 1024 * 1024;
@@ -61,16 +73,14 @@ const ID: &str = env!("ID");
 static mut CORE1_STACK: Stack<8192> = Stack::new();
 static EXECUTOR0: StaticCell<Executor> = StaticCell::new();
 static EXECUTOR1: StaticCell<Executor> = StaticCell::new();
-static DISPLAY_CHANNEL_DATA: StaticCell<[DisplayMessage; 1]> = StaticCell::new();
+static DISPLAY_CHANNEL_DATA: StaticCell<[DisplayMessage; display::DISPLAY_CHANNEL_CAPACITY]> =
+    StaticCell::new();
+/// Signaled by the BLE stack with the passkey to show while pairing, then `None` to go back to the last message
+static PASSKEY_SIGNAL: Signal<CriticalSectionRawMutex, Option<u32>> = Signal::new();
 static DISPLAY_CHANNEL: StaticCell<
     zerocopy_channel::Channel<'static, CriticalSectionRawMutex, DisplayMessage>,
 > = StaticCell::new();
 
-#[embassy_executor::task]
-async fn logger_task(driver: usb::Driver<'static, USB>) {
-    embassy_usb_logger::run!(1024, log::LevelFilter::Debug, driver);
-}
-
 #[embassy_executor::task]
 async fn cyw43_task(
     runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
@@ -80,25 +90,52 @@ async fn cyw43_task(
 
 #[embassy_executor::task]
 async fn input(
-    signal: &'static Signal<NoopRawMutex, Button>,
+    signal: &'static Signal<NoopRawMutex, ButtonEvent>,
+    factory_reset_signal: &'static Signal<NoopRawMutex, ()>,
+    debounce_ms: u16,
     good_in: Input<'static>,
     help_in: Input<'static>,
 ) {
-    input::task(signal, good_in, help_in).await;
+    input::task(signal, factory_reset_signal, debounce_ms, good_in, help_in).await;
+}
+
+#[embassy_executor::task]
+async fn battery(
+    signal: &'static Signal<NoopRawMutex, BatteryReading>,
+    adc: Adc<'static, adc::Async>,
+    channel: adc::Channel<'static>,
+    low_mv: u16,
+    critical_mv: u16,
+) -> ! {
+    battery::run(adc, channel, signal, low_mv, critical_mv).await
+}
+
+#[embassy_executor::task]
+async fn backlight(pwm: Pwm<'static>) -> ! {
+    backlight::run(pwm).await
 }
 
 #[embassy_executor::task]
 async fn core0_main(
     spawner: Spawner,
-    sender: zerocopy_channel::Sender<'static, CriticalSectionRawMutex, DisplayMessage>,
+    mut sender: zerocopy_channel::Sender<'static, CriticalSectionRawMutex, DisplayMessage>,
     p: Core0Peripherals,
 ) {
     /// SAFETY: `NoopRawMutex` is ok since we only signal WITHIN core0's executor
-    static INPUT_SIGNAL: ConstStaticCell<Signal<NoopRawMutex, Button>> =
+    static INPUT_SIGNAL: ConstStaticCell<Signal<NoopRawMutex, ButtonEvent>> =
         ConstStaticCell::new(Signal::new());
     static BT_MSG_SIGNAL: ConstStaticCell<
-        Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<128>>,
+        Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<bt_server::MESSAGE_CAPACITY>>,
+    > = ConstStaticCell::new(Signal::new());
+    static RECEIVED_MSG_SIGNAL: ConstStaticCell<
+        Signal<NoopRawMutex, trouble_host::prelude::HeaplessString<bt_server::MESSAGE_CAPACITY>>,
     > = ConstStaticCell::new(Signal::new());
+    static BATTERY_SIGNAL: ConstStaticCell<Signal<NoopRawMutex, BatteryReading>> =
+        ConstStaticCell::new(Signal::new());
+    static FACTORY_RESET_SIGNAL: ConstStaticCell<Signal<NoopRawMutex, ()>> =
+        ConstStaticCell::new(Signal::new());
+    static HISTORY_SIGNAL: ConstStaticCell<Signal<NoopRawMutex, StoredMessage>> =
+        ConstStaticCell::new(Signal::new());
     static STATE: StaticCell<cyw43::State> = StaticCell::new();
 
     // add some delay to give an attached debug probe time to parse the
@@ -107,8 +144,7 @@ async fn core0_main(
     // https://github.com/knurling-rs/defmt/pull/683
     Timer::after_millis(10).await;
 
-    let driver = usb::Driver::new(p.usb, Irqs);
-    spawner.spawn(logger_task(driver).unwrap());
+    logger::init(spawner, p.usb);
 
     let fw = cyw43_firmware::CYW43_43439A0;
     let clm = cyw43_firmware::CYW43_43439A0_CLM;
@@ -128,7 +164,6 @@ async fn core0_main(
         p.dma0,
     );
 
-    // spawner.spawn(pwm_backlight_task(p.PWM_SLICE1, p.PIN_3).unwrap());
     // spawner.spawn(btn_to_led(btn, light).unwrap());
 
     let state = STATE.init(cyw43::State::new());
@@ -144,27 +179,95 @@ async fn core0_main(
     let mut flash: embassy_rp::flash::Flash<'_, _, _, FLASH_SIZE> =
         embassy_rp::flash::Flash::new(p.flash, p.dma1);
 
-    let info = storage::load_info(&mut flash)
-        .await
-        .unwrap_or_else(|| storage::Info {
-            encryption_key: DEFAULT_ENCRYPTION_KEY.try_into().ok(),
-        });
+    // Only written back if what's in flash could be read, so a flash error doesn't replace the settings with defaults
+    let (mut info, store) = match storage::load_info(&mut flash).await {
+        Ok(info) => (info, true),
+        Err(err) => {
+            let store = match err {
+                storage::StorageError::NotFound => {
+                    log::info!("No stored info, using defaults");
+                    true
+                }
+                storage::StorageError::Corrupt => {
+                    log::error!("Stored info is corrupt, replacing it with defaults");
+                    true
+                }
+                storage::StorageError::Flash(err) => {
+                    log::error!("Failed to read stored info, using defaults until reboot: {err:?}");
+                    false
+                }
+            };
+            let info = storage::Info {
+                encryption_key: DEFAULT_ENCRYPTION_KEY.try_into().ok(),
+                ..Default::default()
+            };
+            (info, store)
+        }
+    };
     log::info!("loaded info: {info:#?}");
 
+    display::update_status(|status| status.station = info.station);
+    backlight::set_brightness(info.brightness);
+    backlight::set_screen_timeout(info.screen_timeout_secs);
+    display::set_message_expiry(info.message_expiry_mins);
+
+    info.boot_count = info.boot_count.wrapping_add(1);
+    if !store {
+        log::warn!("Not storing boot count, the stored info couldn't be read");
+    } else if let Err(err) = storage::store_info(&mut flash, &info).await {
+        log::error!("Failed to store boot count: {err:?}");
+    }
+
+    let (history, mut next_history_sequence) = storage::load_history(&mut flash).await;
+    log::info!("Replaying {} stored message(s)", history.len());
+    for message in &history {
+        let out_msg = sender.send().await;
+        *out_msg = DisplayMessage::replayed(message.sender_id, &message.text);
+        sender.send_done();
+    }
+    // Shared between the BLE server's settings and the message history
+    let flash = Mutex::<NoopRawMutex, _>::new(flash);
+
     let input_signal = INPUT_SIGNAL.take();
     let bt_msg_signal = BT_MSG_SIGNAL.take();
+    let received_msg_signal = RECEIVED_MSG_SIGNAL.take();
+    let battery_signal = BATTERY_SIGNAL.take();
+    let factory_reset_signal = FACTORY_RESET_SIGNAL.take();
+    let history_signal = HISTORY_SIGNAL.take();
 
     spawner.spawn(
         input(
             input_signal,
+            factory_reset_signal,
+            info.debounce_ms,
             Input::new(p.pin6, Pull::Up),
             Input::new(p.pin7, Pull::Up),
         )
         .unwrap(),
     );
+    spawner.spawn(
+        battery(
+            battery_signal,
+            Adc::new(p.adc, Irqs, adc::Config::default()),
+            adc::Channel::new_pin(p.pin40, Pull::None),
+            info.low_battery_mv,
+            info.critical_battery_mv,
+        )
+        .unwrap(),
+    );
 
-    join::join(
-        bt_server::run(control, controller, bt_msg_signal, &mut RoscRng, &mut flash),
+    join::join3(
+        bt_server::run(
+            control,
+            controller,
+            bt_msg_signal,
+            received_msg_signal,
+            battery_signal,
+            &PASSKEY_SIGNAL,
+            factory_reset_signal,
+            &mut RoscRng,
+            &flash,
+        ),
         // core::future::pending::<()>(),
         lora::run(
             p.spi0,
@@ -180,10 +283,28 @@ async fn core0_main(
             &mut RoscRng,
             info.encryption_key
                 .map_or(DEFAULT_ENCRYPTION_KEY, NonZeroU128::get),
+            &info,
             input_signal,
             bt_msg_signal,
+            received_msg_signal,
+            history_signal,
             sender,
         ),
+        async {
+            loop {
+                let message = history_signal.wait().await;
+                let result = storage::store_message(
+                    &mut *flash.lock().await,
+                    next_history_sequence,
+                    &message,
+                )
+                .await;
+                if let Err(err) = result {
+                    log::error!("Failed to store message in history: {err:?}");
+                }
+                next_history_sequence = next_history_sequence.wrapping_add(1);
+            }
+        },
     )
     .await;
 
@@ -224,30 +345,78 @@ async fn core1_main(
     let display_spi =
         ExclusiveDevice::new(display_spi, Output::new(p.pin2, Level::High), Delay).unwrap();
 
-    let mut display = display::Display::new(display_spi, p.pin0, p.pin1);
-    let mut last_msg_str = heapless::String::<128>::new();
+    let display = display::Display::new(display_spi, p.pin0, p.pin1);
+    spawner.spawn(
+        backlight(Pwm::new_output_b(
+            p.pwm_slice1,
+            p.pin3,
+            pwm::Config::default(),
+        ))
+        .unwrap(),
+    );
+    let mut screen = display::Screen::new(display, display::status());
 
     loop {
-        let msg = receiver.receive().await;
-
-        match msg {
-            DisplayMessage::None => {}
-            DisplayMessage::Message(msg_str) => {
-                if last_msg_str != *msg_str {
-                    display.draw(msg_str);
-                    core::mem::swap(&mut last_msg_str, msg_str);
+        let update_at = screen.next_update_at();
+        let update = async move {
+            match update_at {
+                Some(at) => Timer::at(at).await,
+                None => core::future::pending().await,
+            }
+        };
+
+        match select4(
+            receiver.receive(),
+            select3(
+                PASSKEY_SIGNAL.wait(),
+                display::MESSAGE_EXPIRY_SIGNAL.wait(),
+                display::REBOOT_SIGNAL.wait(),
+            ),
+            select4(
+                display::STATUS_CHANGED.wait(),
+                display::HISTORY_CHANNEL.receive(),
+                backlight::BLANK_SIGNAL.wait(),
+                display::DIAGNOSTICS_CHANGED.wait(),
+            ),
+            update,
+        )
+        .await
+        {
+            Either4::First(msg) => {
+                match msg {
+                    DisplayMessage::None => {}
+                    DisplayMessage::Message(msg_str) => {
+                        screen.show_message(msg_str, Some(Instant::now()));
+                    }
+                    DisplayMessage::Received { text, sent_at } => {
+                        screen.show_message(text, *sent_at);
+                    }
+                    DisplayMessage::Alert { text, duration } => screen.show_alert(text, *duration),
+                    DisplayMessage::Emergency(text) => screen.show_emergency(text),
+                    DisplayMessage::Replayed(text) => screen.replay_message(text),
                 }
+
+                receiver.receive_done();
             }
+            Either4::Second(Either3::First(passkey)) => screen.show_passkey(passkey),
+            Either4::Second(Either3::Second(mins)) => screen.set_message_expiry(mins),
+            Either4::Second(Either3::Third(alert)) => {
+                screen.show_alert(alert, display::REBOOT_ALERT_DURATION);
+            }
+            Either4::Third(Either4::First(())) => screen.show_status(display::status()),
+            Either4::Third(Either4::Second(nav)) => screen.navigate_history(nav),
+            Either4::Third(Either4::Third(blanked)) => screen.set_blanked(blanked),
+            Either4::Third(Either4::Fourth(())) => screen.show_diagnostics(display::diagnostics()),
+            Either4::Fourth(()) => screen.update(),
         }
-
-        receiver.receive_done();
     }
 }
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
     let p = embassy_rp::init(embassy_rp::config::Config::default());
-    let channel_data = DISPLAY_CHANNEL_DATA.init([DisplayMessage::None]);
+    let channel_data = DISPLAY_CHANNEL_DATA
+        .init([const { DisplayMessage::None }; display::DISPLAY_CHANNEL_CAPACITY]);
     let channel = DISPLAY_CHANNEL.init(zerocopy_channel::Channel::new(channel_data));
     let (sender, receiver) = channel.split();
 
@@ -265,6 +434,8 @@ fn main() -> ! {
                         pin0: p.PIN_0,
                         pin1: p.PIN_1,
                         pin2: p.PIN_2,
+                        pin3: p.PIN_3,
+                        pwm_slice1: p.PWM_SLICE1,
                         pin26: p.PIN_26,
                         pin27: p.PIN_27,
                         pin28: p.PIN_28,
@@ -303,6 +474,8 @@ fn main() -> ! {
                 pin24: p.PIN_24,
                 pin25: p.PIN_25,
                 pin29: p.PIN_29,
+                pin40: p.PIN_40,
+                adc: p.ADC,
             },
         )
         .unwrap();
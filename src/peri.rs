@@ -1,9 +1,9 @@
 use embassy_rp::{
     Peri,
     peripherals::{
-        DMA_CH0, DMA_CH1, DMA_CH2, DMA_CH3, FLASH, PIN_0, PIN_1, PIN_2, PIN_4, PIN_6, PIN_7,
-        PIN_16, PIN_17, PIN_18, PIN_19, PIN_20, PIN_22, PIN_23, PIN_24, PIN_25, PIN_26, PIN_27,
-        PIN_28, PIN_29, PIO0, PIO1, SPI0, USB,
+        ADC, DMA_CH0, DMA_CH1, DMA_CH2, DMA_CH3, FLASH, PIN_0, PIN_1, PIN_2, PIN_3, PIN_4, PIN_6,
+        PIN_7, PIN_16, PIN_17, PIN_18, PIN_19, PIN_20, PIN_22, PIN_23, PIN_24, PIN_25, PIN_26,
+        PIN_27, PIN_28, PIN_29, PIN_40, PIO0, PIO1, PWM_SLICE1, SPI0, USB,
     },
 };
 
@@ -29,6 +29,8 @@ pub struct Core0Peripherals {
     pub pin24: Peri<'static, PIN_24>,
     pub pin25: Peri<'static, PIN_25>,
     pub pin29: Peri<'static, PIN_29>,
+    pub pin40: Peri<'static, PIN_40>,
+    pub adc: Peri<'static, ADC>,
 }
 
 pub struct Core1Peripherals {
@@ -36,6 +38,8 @@ pub struct Core1Peripherals {
     pub pin0: Peri<'static, PIN_0>,
     pub pin1: Peri<'static, PIN_1>,
     pub pin2: Peri<'static, PIN_2>,
+    pub pin3: Peri<'static, PIN_3>,
+    pub pwm_slice1: Peri<'static, PWM_SLICE1>,
     pub pin26: Peri<'static, PIN_26>,
     pub pin27: Peri<'static, PIN_27>,
     pub pin28: Peri<'static, PIN_28>,
@@ -0,0 +1,32 @@
+//! Counts what the radio has done since boot, so BLE centrals can check on how a device is doing in the field.
+//!
+//! Counters are kept in RAM only, so they start back at 0 every boot.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{Mutex, raw::CriticalSectionRawMutex},
+    signal::Signal,
+};
+
+pub use proto::stats::PacketStats;
+
+static STATS: Mutex<CriticalSectionRawMutex, Cell<PacketStats>> =
+    Mutex::new(Cell::new(PacketStats::new()));
+/// Signaled whenever a counter changes
+pub static STATS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Changes the counters with `f`, letting the BLE server know.
+pub fn update(f: impl FnOnce(&mut PacketStats)) {
+    STATS.lock(|stats| {
+        let mut new_stats = stats.get();
+        f(&mut new_stats);
+        stats.set(new_stats);
+    });
+    STATS_CHANGED.signal(());
+}
+
+/// The counters as they are now.
+pub fn get() -> PacketStats {
+    STATS.lock(Cell::get)
+}
@@ -1,61 +1,231 @@
 use core::{num::NonZeroU128, ops::Range};
 
+use common::{Region, Station};
 use embedded_storage_async::nor_flash::NorFlash;
-use sequential_storage::{
-    cache::NoCache,
-    map::{SerializationError, Value},
+
+use proto::storage::StoredInfo;
+pub use proto::storage::{
+    ALLOW_LIST_CAPACITY, HOP_CHANNELS_CAPACITY, NAME_CAPACITY, NO_STATION, SIGNATURE_CAPACITY,
+    StorageError,
+};
+
+use crate::backlight::{DEFAULT_BRIGHTNESS, DEFAULT_SCREEN_TIMEOUT_SECS};
+use crate::battery::{DEFAULT_CRITICAL_MV, DEFAULT_LOW_MV};
+use crate::bt_server::{
+    DEFAULT_ADV_INTERVAL_MS, DEFAULT_BLE_TX_POWER_DBM, DEFAULT_IDLE_TIMEOUT_SECS,
+};
+use crate::display::{DEFAULT_MESSAGE_EXPIRY_MINS, HISTORY_CAPACITY, MAX_DISPLAY_LEN};
+use crate::input::DEFAULT_DEBOUNCE_MS;
+use crate::lora::{
+    DEFAULT_RX_TIMEOUT_SYMBOLS, DEFAULT_TRANSMIT_TIMES, ListenMode, LoraPreset, MAX_TX_POWER_DBM,
 };
 
 const DATA_START_ADDR: u32 = 0x0010_0000;
 pub const INFO_START_OFFSET: u32 = 0x0;
+/// Right after the info's two 4KiB sectors
+pub const HISTORY_START_OFFSET: u32 = 0x2000;
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct Info {
     /// Symmetric encryption key for all packets sent and received. If changed, requires reset of device.
     pub encryption_key: Option<NonZeroU128>,
+    /// Incremented every boot, so packet sequence numbers keep increasing across reboots.
+    pub boot_count: u16,
+    /// Regulatory region the radio operates in, which limits its frequency and TX power. If changed, requires reset of device.
+    pub region: Region,
+    /// LoRa frequency in Hz, must be within `region`'s band. If changed, requires reset of device.
+    pub frequency_hz: u32,
+    /// LoRa transmit power in dBm. If changed, requires reset of device.
+    pub tx_power_dbm: i8,
+    /// Number of times each packet is transmitted, more helps in noisy areas but uses more airtime. If changed, requires reset of device.
+    pub transmit_times: u8,
+    /// Whether the radio sleeps between checks for activity or always listens, and whether it ever transmits. If changed,
+    /// requires reset of device.
+    pub listen_mode: ListenMode,
+    /// Station this device is placed at, if one has been picked. Shown on the display at boot.
+    pub station: Option<Station>,
+    /// Time given for button presses and releases to settle, in ms. If changed, requires reset of device.
+    pub debounce_ms: u16,
+    /// Display backlight brightness, 0 to 100%. Applied right away.
+    pub brightness: u8,
+    /// Seconds without activity before the screen is blanked, or 0 to never blank it. Applied right away.
+    pub screen_timeout_secs: u16,
+    /// Name the device shows up as over BLE, or `None` for the default. If changed, requires reset of device.
+    pub name: Option<heapless::String<NAME_CAPACITY>>,
+    /// Whether packets heard from other devices are rebroadcast, extending the fleet's range. Devices at the edge of
+    /// the network can turn it off to save power and airtime. If changed, requires reset of device.
+    pub relay: bool,
+    /// Only messages from these senders are shown, or from everyone if it's empty. Packets from anyone are still
+    /// relayed. If changed, requires reset of device.
+    pub allowed_senders: heapless::Vec<u16, ALLOW_LIST_CAPACITY>,
+    /// LoRa modulation, trading speed for range. Every device has to use the same one. If changed, requires reset of
+    /// device.
+    pub lora_preset: LoraPreset,
+    /// Symbols to wait for a packet once channel activity is heard, or for each RX window when always listening. If
+    /// changed, requires reset of device.
+    pub rx_timeout_symbols: u16,
+    /// Battery voltage in mV below which only calls for help are relayed and the status bar warns of it. If changed,
+    /// requires reset of device.
+    pub low_battery_mv: u16,
+    /// Battery voltage in mV below which nothing but calls for help is sent, and the backlight is kept dim. Below
+    /// `low_battery_mv`. If changed, requires reset of device.
+    pub critical_battery_mv: u16,
+    /// Seconds a BLE central can go without reading or writing anything before it's disconnected, or 0 to never
+    /// disconnect it. Applied right away.
+    pub idle_timeout_secs: u16,
+    /// Minutes a message stays on screen before it's replaced with an idle indicator, or 0 to keep it up until the next
+    /// one. Calls for help stay up until acknowledged either way. Applied right away.
+    pub message_expiry_mins: u16,
+    /// Whether the spreading factor steps up past the preset's while messages keep going unacknowledged, and back down
+    /// once they get through. Only devices at the same spreading factor hear each other. If changed, requires reset of
+    /// device.
+    pub adaptive_sf: bool,
+    /// Milliseconds between BLE advertisements. Shorter makes the device quicker to find, longer saves battery. Applied
+    /// the next time the device advertises.
+    pub adv_interval_ms: u16,
+    /// BLE TX power in dBm, rounded down to one the controller supports. Applied the next time the device advertises.
+    pub ble_tx_power_dbm: i8,
+    /// Put in front of messages written over BLE, so people can tell who they're from, or `None` to send them as
+    /// they are. Only for people to read, devices go by sender ID. If changed, requires reset of device.
+    pub signature: Option<heapless::String<SIGNATURE_CAPACITY>>,
+    /// Frequencies in Hz the radio hops between on a schedule shared by the fleet, in place of `frequency_hz`, or empty
+    /// to stay on `frequency_hz`. If changed, requires reset of device.
+    pub hop_channels_hz: heapless::Vec<u32, HOP_CHANNELS_CAPACITY>,
 }
 
-impl Info {
-    fn from_stored(stored: &StoredInfo) -> Self {
+impl Default for Info {
+    fn default() -> Self {
+        let region = Region::default();
         Self {
-            encryption_key: stored.encryption_key.try_into().ok(),
+            encryption_key: None,
+            boot_count: 0,
+            region,
+            frequency_hz: region.default_frequency_hz(),
+            tx_power_dbm: MAX_TX_POWER_DBM,
+            transmit_times: DEFAULT_TRANSMIT_TIMES,
+            listen_mode: ListenMode::default(),
+            station: None,
+            debounce_ms: DEFAULT_DEBOUNCE_MS,
+            brightness: DEFAULT_BRIGHTNESS,
+            screen_timeout_secs: DEFAULT_SCREEN_TIMEOUT_SECS,
+            name: None,
+            relay: true,
+            allowed_senders: heapless::Vec::new(),
+            lora_preset: LoraPreset::default(),
+            rx_timeout_symbols: DEFAULT_RX_TIMEOUT_SYMBOLS,
+            low_battery_mv: DEFAULT_LOW_MV,
+            critical_battery_mv: DEFAULT_CRITICAL_MV,
+            idle_timeout_secs: DEFAULT_IDLE_TIMEOUT_SECS,
+            message_expiry_mins: DEFAULT_MESSAGE_EXPIRY_MINS,
+            adaptive_sf: false,
+            adv_interval_ms: DEFAULT_ADV_INTERVAL_MS,
+            ble_tx_power_dbm: DEFAULT_BLE_TX_POWER_DBM,
+            signature: None,
+            hop_channels_hz: heapless::Vec::new(),
         }
     }
 }
 
-#[derive(Debug, Clone)]
-struct StoredInfo {
-    encryption_key: u128,
-}
-
-impl StoredInfo {
-    pub const SER_SIZE: usize = size_of::<u128>();
-}
-
-impl<'a> Value<'a> for StoredInfo {
-    fn serialize_into(&self, buffer: &mut [u8]) -> Result<usize, SerializationError> {
-        if buffer.len() < Self::SER_SIZE {
-            return Err(SerializationError::BufferTooSmall);
+impl Info {
+    fn from_stored(stored: &StoredInfo) -> Self {
+        Self {
+            encryption_key: stored.encryption_key.try_into().ok(),
+            boot_count: stored.boot_count,
+            region: Region::try_from(stored.region).unwrap_or_default(),
+            frequency_hz: stored.frequency_hz,
+            tx_power_dbm: stored.tx_power_dbm,
+            transmit_times: stored.transmit_times,
+            listen_mode: ListenMode::try_from(stored.listen_mode).unwrap_or_default(),
+            station: Station::try_from(stored.station).ok(),
+            debounce_ms: stored.debounce_ms,
+            brightness: stored.brightness,
+            screen_timeout_secs: stored.screen_timeout_secs,
+            name: stored
+                .name
+                .get(..stored.name_len.into())
+                .and_then(|name| core::str::from_utf8(name).ok())
+                .filter(|name| !name.is_empty())
+                .and_then(|name| name.try_into().ok()),
+            relay: stored.relay,
+            // Clamped to the capacity, so always fits
+            allowed_senders: heapless::Vec::from_slice(
+                &stored.allowed_senders[..usize::from(stored.allowed_len).min(ALLOW_LIST_CAPACITY)],
+            )
+            .unwrap(),
+            lora_preset: LoraPreset::try_from(stored.lora_preset).unwrap_or_default(),
+            rx_timeout_symbols: stored.rx_timeout_symbols,
+            low_battery_mv: stored.low_battery_mv,
+            critical_battery_mv: stored.critical_battery_mv,
+            idle_timeout_secs: stored.idle_timeout_secs,
+            message_expiry_mins: stored.message_expiry_mins,
+            adaptive_sf: stored.adaptive_sf,
+            adv_interval_ms: stored.adv_interval_ms,
+            ble_tx_power_dbm: stored.ble_tx_power_dbm,
+            signature: stored
+                .signature
+                .get(..stored.signature_len.into())
+                .and_then(|signature| core::str::from_utf8(signature).ok())
+                .filter(|signature| !signature.is_empty())
+                .and_then(|signature| signature.try_into().ok()),
+            // Clamped to the capacity, so always fits
+            hop_channels_hz: heapless::Vec::from_slice(
+                &stored.hop_channels_hz
+                    [..usize::from(stored.hop_channels_len).min(HOP_CHANNELS_CAPACITY)],
+            )
+            .unwrap(),
         }
-
-        // Serialize encryption key first
-        buffer[0..size_of::<u128>()].copy_from_slice(&self.encryption_key.to_le_bytes());
-
-        Ok(Self::SER_SIZE)
     }
 
-    fn deserialize_from(buffer: &'a [u8]) -> Result<Self, SerializationError>
-    where
-        Self: Sized,
-    {
-        if buffer.len() < Self::SER_SIZE {
-            Err(SerializationError::BufferTooSmall)
-        } else {
-            Ok(Self {
-                encryption_key: u128::from_le_bytes(
-                    buffer[0..size_of::<u128>()].try_into().unwrap(),
-                ),
-            })
+    #[allow(clippy::cast_possible_truncation)]
+    fn to_stored(&self) -> StoredInfo {
+        let mut name = [0; NAME_CAPACITY];
+        let name_len = self.name.as_ref().map_or(0, |info_name| {
+            name[..info_name.len()].copy_from_slice(info_name.as_bytes());
+            // At most NAME_CAPACITY, so always fits
+            info_name.len() as u8
+        });
+        let mut signature = [0; SIGNATURE_CAPACITY];
+        let signature_len = self.signature.as_ref().map_or(0, |info_signature| {
+            signature[..info_signature.len()].copy_from_slice(info_signature.as_bytes());
+            // At most SIGNATURE_CAPACITY, so always fits
+            info_signature.len() as u8
+        });
+        let mut allowed_senders = [0; ALLOW_LIST_CAPACITY];
+        allowed_senders[..self.allowed_senders.len()].copy_from_slice(&self.allowed_senders);
+        let mut hop_channels_hz = [0; HOP_CHANNELS_CAPACITY];
+        hop_channels_hz[..self.hop_channels_hz.len()].copy_from_slice(&self.hop_channels_hz);
+        StoredInfo {
+            encryption_key: self.encryption_key.map_or(0, NonZeroU128::get),
+            boot_count: self.boot_count,
+            region: self.region.into(),
+            frequency_hz: self.frequency_hz,
+            tx_power_dbm: self.tx_power_dbm,
+            transmit_times: self.transmit_times,
+            listen_mode: self.listen_mode as u8,
+            station: self.station.map_or(NO_STATION, Into::into),
+            debounce_ms: self.debounce_ms,
+            brightness: self.brightness,
+            screen_timeout_secs: self.screen_timeout_secs,
+            name,
+            name_len,
+            relay: self.relay,
+            allowed_senders,
+            // At most ALLOW_LIST_CAPACITY, so always fits
+            allowed_len: self.allowed_senders.len() as u8,
+            lora_preset: self.lora_preset as u8,
+            rx_timeout_symbols: self.rx_timeout_symbols,
+            low_battery_mv: self.low_battery_mv,
+            critical_battery_mv: self.critical_battery_mv,
+            idle_timeout_secs: self.idle_timeout_secs,
+            message_expiry_mins: self.message_expiry_mins,
+            adaptive_sf: self.adaptive_sf,
+            adv_interval_ms: self.adv_interval_ms,
+            ble_tx_power_dbm: self.ble_tx_power_dbm,
+            signature,
+            signature_len,
+            hop_channels_hz,
+            // At most HOP_CHANNELS_CAPACITY, so always fits
+            hop_channels_len: self.hop_channels_hz.len() as u8,
         }
     }
 }
@@ -68,44 +238,72 @@ const fn flash_range<S: NorFlash>(offset: u32) -> Range<u32> {
     (DATA_START_ADDR + offset)..((DATA_START_ADDR + offset) + (sector_size::<S>()))
 }
 
+/// Stores `info` as the latest record, superseding the last one. Losing power partway through leaves either the old
+/// settings or the new ones loadable, see [`proto::storage::store_info`].
 pub async fn store_info<S: NorFlash>(
     storage: &mut S,
     info: &Info,
-) -> Result<(), sequential_storage::Error<S::Error>> {
-    sequential_storage::erase_all(storage, flash_range::<S>(INFO_START_OFFSET)).await?;
-    let mut buffer = [0; StoredInfo::SER_SIZE.next_multiple_of(32)];
-    let value = StoredInfo {
-        encryption_key: info.encryption_key.map_or(0, NonZeroU128::get),
-    };
-
-    sequential_storage::map::store_item(
+) -> Result<(), StorageError<S::Error>> {
+    proto::storage::store_info(
         storage,
         flash_range::<S>(INFO_START_OFFSET),
-        &mut NoCache::new(),
-        &mut buffer,
-        &(),
-        &value,
+        &info.to_stored(),
     )
-    .await?;
-    Ok(())
+    .await
 }
 
-pub async fn load_info<S: NorFlash>(storage: &mut S) -> Option<Info> {
-    let mut buffer = [0; StoredInfo::SER_SIZE.next_multiple_of(32)];
-    let mut cache = NoCache::new();
-    let mut iter = sequential_storage::map::fetch_all_items::<(), _, _>(
+/// A received message kept in flash, so the history view still has it after a reboot
+pub type StoredMessage = proto::storage::StoredMessage<MAX_DISPLAY_LEN>;
+type HistoryRecord = proto::storage::HistoryRecord<MAX_DISPLAY_LEN>;
+
+/// Stores `message` as the newest in the history, replacing the oldest once there are [`HISTORY_CAPACITY`].
+/// `sequence` must be one more than the last message's, starting from what [`load_history`] returned.
+pub async fn store_message<S: NorFlash>(
+    storage: &mut S,
+    sequence: u32,
+    message: &StoredMessage,
+) -> Result<(), StorageError<S::Error>> {
+    let mut buffer = [0; HistoryRecord::SER_SIZE.next_multiple_of(32)];
+    proto::storage::store_message::<_, _, HISTORY_CAPACITY>(
         storage,
-        flash_range::<S>(INFO_START_OFFSET),
-        &mut cache,
+        flash_range::<S>(HISTORY_START_OFFSET),
         &mut buffer,
+        sequence,
+        message,
     )
     .await
-    .ok()?;
+}
 
-    let mut curr_info = None;
-    while let Some(((), value)) = iter.next::<StoredInfo>(&mut buffer).await.ok()? {
-        curr_info = Some(value);
-    }
+/// Loads the stored history, oldest first, and the sequence number to store the next message with. Records that
+/// can't be read are skipped.
+pub async fn load_history<S: NorFlash>(
+    storage: &mut S,
+) -> (heapless::Vec<StoredMessage, HISTORY_CAPACITY>, u32) {
+    let mut buffer = [0; HistoryRecord::SER_SIZE.next_multiple_of(32)];
+    proto::storage::load_history(storage, flash_range::<S>(HISTORY_START_OFFSET), &mut buffer).await
+}
 
-    curr_info.as_ref().map(Info::from_stored)
+/// Erases the stored history.
+pub async fn erase_history<S: NorFlash>(storage: &mut S) -> Result<(), StorageError<S::Error>> {
+    sequential_storage::erase_all(storage, flash_range::<S>(HISTORY_START_OFFSET)).await?;
+    Ok(())
+}
+
+/// Erases the stored [`Info`], so it's back to defaults after a reboot.
+pub async fn erase_info<S: NorFlash>(storage: &mut S) -> Result<(), StorageError<S::Error>> {
+    sequential_storage::erase_all(storage, flash_range::<S>(INFO_START_OFFSET)).await?;
+    Ok(())
+}
+
+/// Loads the latest stored [`Info`], migrating older layouts. Fails with [`StorageError::NotFound`] if nothing has
+/// been stored, or [`StorageError::Corrupt`] if the latest record can't be read, so callers can tell a fresh device
+/// from one whose settings were lost.
+pub async fn load_info<S: NorFlash>(storage: &mut S) -> Result<Info, StorageError<S::Error>> {
+    proto::storage::load_info(
+        storage,
+        flash_range::<S>(INFO_START_OFFSET),
+        &Info::default().to_stored(),
+    )
+    .await
+    .map(|stored| Info::from_stored(&stored))
 }